@@ -0,0 +1,165 @@
+use std::fmt;
+use crate::summary::TargetSummary;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssertMetric {
+    Loss,
+    Avg,
+    P99,
+}
+
+impl fmt::Display for AssertMetric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssertMetric::Loss => f.write_str("loss"),
+            AssertMetric::Avg => f.write_str("avg"),
+            AssertMetric::P99 => f.write_str("p99"),
+        }
+    }
+}
+
+/// A limit checked against the summary at the end of the run
+#[derive(Debug, Clone)]
+pub struct Assertion {
+    pub(crate) target: Option<String>,
+    pub(crate) metric: AssertMetric,
+    pub(crate) limit: f64,
+}
+
+/// parse `[TARGET=]VALUE`
+fn parse_assertion(s: &str, metric: AssertMetric) -> Result<Assertion, String> {
+    let (target, value) = match s.rsplit_once('=') {
+        Some((target, value)) => (Some(target.to_string()), value),
+        None => (None, s),
+    };
+    let limit = value
+        .trim_end_matches(['%', 'm', 's'])
+        .parse::<f64>()
+        .map_err(|_| format!("invalid limit: {}, expected [TARGET=]VALUE", s))?;
+    Ok(Assertion { target, metric, limit })
+}
+
+pub fn parse_assert_loss(s: &str) -> Result<Assertion, String> {
+    parse_assertion(s, AssertMetric::Loss)
+}
+
+pub fn parse_assert_avg(s: &str) -> Result<Assertion, String> {
+    parse_assertion(s, AssertMetric::Avg)
+}
+
+pub fn parse_assert_p99(s: &str) -> Result<Assertion, String> {
+    parse_assertion(s, AssertMetric::P99)
+}
+
+/// A target that did not meet an assertion
+#[derive(Debug)]
+pub struct Violation {
+    pub(crate) addr: String,
+    pub(crate) ip: String,
+    pub(crate) metric: AssertMetric,
+    // None when there was no reply to measure
+    pub(crate) value: Option<f64>,
+    pub(crate) limit: f64,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unit = if self.metric == AssertMetric::Loss { "%" } else { "ms" };
+        match self.value {
+            Some(value) => write!(
+                f,
+                "{} ({}): {} {:.2}{} > {}{}",
+                self.addr, self.ip, self.metric, value, unit, self.limit, unit
+            ),
+            None => write!(f, "{} ({}): {} no replies, limit {}{}", self.addr, self.ip, self.metric, self.limit, unit),
+        }
+    }
+}
+
+/// check every target against its assertions, a per target assertion
+/// replaces the global one of the same metric
+pub fn check_assertions(assertions: &[Assertion], summaries: &[TargetSummary]) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    for summary in summaries {
+        for metric in [AssertMetric::Loss, AssertMetric::Avg, AssertMetric::P99] {
+            let assertion = assertions
+                .iter()
+                .rfind(|a| a.metric == metric && a.target.as_deref().is_some_and(|t| t == summary.addr || t == summary.ip))
+                .or_else(|| assertions.iter().rfind(|a| a.metric == metric && a.target.is_none()));
+            let Some(assertion) = assertion else {
+                continue;
+            };
+
+            let value = match metric {
+                AssertMetric::Loss => Some(summary.loss),
+                _ if summary.received == 0 => None,
+                AssertMetric::Avg => Some(summary.avg),
+                AssertMetric::P99 => Some(summary.p99),
+            };
+            if value.is_none_or(|v| v > assertion.limit) {
+                violations.push(Violation {
+                    addr: summary.addr.clone(),
+                    ip: summary.ip.clone(),
+                    metric,
+                    value,
+                    limit: assertion.limit,
+                });
+            }
+        }
+    }
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(addr: &str, received: usize, loss: f64, avg: f64) -> TargetSummary {
+        TargetSummary {
+            addr: addr.into(),
+            ip: format!("{}-ip", addr),
+            sent: 10,
+            received,
+            loss,
+            avg,
+            p99: avg * 2.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parse_limits_with_and_without_target() {
+        let assertion = parse_assert_loss("5%").unwrap();
+        assert_eq!((assertion.target, assertion.metric, assertion.limit), (None, AssertMetric::Loss, 5.0));
+
+        let assertion = parse_assert_avg("example.com=50ms").unwrap();
+        assert_eq!(assertion.target.as_deref(), Some("example.com"));
+        assert_eq!(assertion.limit, 50.0);
+
+        assert!(parse_assert_p99("fast").is_err());
+        assert!(parse_assert_p99("example.com=").is_err());
+    }
+
+    #[test]
+    fn per_target_assertions_replace_global_ones() {
+        let assertions = [
+            parse_assert_loss("1").unwrap(),
+            parse_assert_loss("flaky=30").unwrap(),
+            parse_assert_avg("100").unwrap(),
+        ];
+        let summaries = [summary("stable", 10, 2.0, 20.0), summary("flaky", 8, 20.0, 150.0)];
+
+        let violations: Vec<String> = check_assertions(&assertions, &summaries).iter().map(|v| v.to_string()).collect();
+        assert_eq!(violations, [
+            "stable (stable-ip): loss 2.00% > 1%",
+            "flaky (flaky-ip): avg 150.00ms > 100ms",
+        ]);
+    }
+
+    #[test]
+    fn no_replies_violates_latency_assertions() {
+        let violations = check_assertions(&[parse_assert_p99("10").unwrap()], &[summary("down", 0, 100.0, 0.0)]);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].value, None);
+    }
+}
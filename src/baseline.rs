@@ -0,0 +1,162 @@
+// weight of a new sample in the moving average
+const ALPHA: f64 = 0.05;
+// anomalous samples move the baseline slower, so a short spike does not
+// become the new normal while a lasting shift still does eventually
+const ANOMALY_ALPHA: f64 = ALPHA / 4.0;
+// lower bound of the deviation, relative to the mean, so a very stable
+// target does not flag every tiny wobble
+const MIN_RELATIVE_STDDEV: f64 = 0.05;
+// consecutive anomalous samples needed to count as an anomaly period
+const PERIOD_SAMPLES: usize = 3;
+
+#[derive(Debug, Clone, Default)]
+pub struct BaselineStats {
+    pub(crate) mean: f64,
+    pub(crate) stddev: f64,
+    // anomalous samples since start
+    pub(crate) anomalies: usize,
+    // periods with several anomalous samples in a row
+    pub(crate) periods: usize,
+    // true while the target is in an anomaly period
+    pub(crate) in_period: bool,
+}
+
+/// Adaptive latency baseline of one target, an exponentially weighted
+/// moving average of the rtt mean and variance
+#[derive(Debug)]
+pub struct Baseline {
+    sensitivity: f64,
+    warmup: usize,
+    samples: usize,
+    mean: f64,
+    variance: f64,
+    streak: usize,
+    stats: BaselineStats,
+}
+
+impl Baseline {
+    /// `sensitivity` is the number of standard deviations above the mean a
+    /// sample has to be to count as anomalous, nothing is flagged during
+    /// the first `warmup` samples
+    pub fn new(sensitivity: f64, warmup: usize) -> Self {
+        Self {
+            sensitivity,
+            warmup,
+            samples: 0,
+            mean: 0.0,
+            variance: 0.0,
+            streak: 0,
+            stats: BaselineStats::default(),
+        }
+    }
+
+    /// learn from a reply, returns true when the rtt is significantly above normal
+    pub fn observe(&mut self, rtt: f64) -> bool {
+        self.samples += 1;
+        if self.samples == 1 {
+            self.mean = rtt;
+            self.update_stats();
+            return false;
+        }
+
+        let deviation = self.stddev().max(self.mean * MIN_RELATIVE_STDDEV);
+        let anomalous = self.samples > self.warmup
+            && self.sensitivity > 0.0
+            && rtt > self.mean + self.sensitivity * deviation;
+
+        let diff = rtt - self.mean;
+        if anomalous {
+            // outliers would blow up the variance and hide the anomalies following them
+            self.mean += ANOMALY_ALPHA * diff;
+        } else {
+            let incr = ALPHA * diff;
+            self.mean += incr;
+            self.variance = (1.0 - ALPHA) * (self.variance + diff * incr);
+        }
+
+        if anomalous {
+            self.streak += 1;
+            self.stats.anomalies += 1;
+            if self.streak == PERIOD_SAMPLES {
+                self.stats.periods += 1;
+            }
+        } else {
+            self.streak = 0;
+        }
+        self.update_stats();
+        anomalous
+    }
+
+    /// a timeout breaks a run of anomalous samples
+    pub fn observe_timeout(&mut self) {
+        self.streak = 0;
+        self.update_stats();
+    }
+
+    pub fn stats(&self) -> &BaselineStats {
+        &self.stats
+    }
+
+    fn stddev(&self) -> f64 {
+        self.variance.sqrt()
+    }
+
+    fn update_stats(&mut self) {
+        self.stats.mean = self.mean;
+        self.stats.stddev = self.stddev();
+        self.stats.in_period = self.streak >= PERIOD_SAMPLES;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a target around 20ms with a little noise
+    fn learn(baseline: &mut Baseline, samples: usize) {
+        for i in 0..samples {
+            assert!(!baseline.observe(20.0 + (i % 3) as f64 * 0.5));
+        }
+    }
+
+    #[test]
+    fn nothing_is_flagged_during_warmup() {
+        let mut baseline = Baseline::new(3.0, 20);
+        learn(&mut baseline, 5);
+        for _ in 0..15 {
+            assert!(!baseline.observe(500.0));
+        }
+        assert_eq!(baseline.stats().anomalies, 0);
+    }
+
+    #[test]
+    fn spike_after_warmup_is_flagged() {
+        let mut baseline = Baseline::new(3.0, 20);
+        learn(&mut baseline, 50);
+        assert!((baseline.stats().mean - 20.5).abs() < 0.5);
+        assert!(baseline.observe(100.0));
+        assert!(!baseline.observe(20.5));
+        assert_eq!(baseline.stats().anomalies, 1);
+        assert_eq!(baseline.stats().periods, 0);
+    }
+
+    #[test]
+    fn consecutive_anomalies_make_a_period_and_a_timeout_ends_it() {
+        let mut baseline = Baseline::new(3.0, 20);
+        learn(&mut baseline, 50);
+        for _ in 0..PERIOD_SAMPLES {
+            assert!(baseline.observe(100.0));
+        }
+        assert_eq!(baseline.stats().periods, 1);
+        assert!(baseline.stats().in_period);
+        baseline.observe_timeout();
+        assert!(!baseline.stats().in_period);
+    }
+
+    #[test]
+    fn zero_sensitivity_disables_detection() {
+        let mut baseline = Baseline::new(0.0, 0);
+        learn(&mut baseline, 10);
+        assert!(!baseline.observe(1000.0));
+    }
+}
@@ -0,0 +1,256 @@
+use std::collections::HashSet;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+use clap::Args;
+use tokio::{runtime::Builder, task};
+use crate::data_processor::{DataProcessor, ProcessorConfig};
+use crate::health::HealthRules;
+use crate::network::{get_host_ipaddr, send_ping};
+use crate::ping_event::PingEvent;
+use crate::shutdown::{handle_signals, Shutdown};
+use crate::outage::MAX_OUTAGE_HISTORY;
+use crate::summary::TargetSummary;
+use crate::error::lock;
+
+pub const UNKNOWN_EXIT_CODE: i32 = CheckState::Unknown as i32;
+
+/// Monitoring plugin states, the value is the exit code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CheckState {
+    Ok = 0,
+    Warning = 1,
+    Critical = 2,
+    Unknown = 3,
+}
+
+impl CheckState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CheckState::Ok => "OK",
+            CheckState::Warning => "WARNING",
+            CheckState::Critical => "CRITICAL",
+            CheckState::Unknown => "UNKNOWN",
+        }
+    }
+}
+
+/// Round trip average and packet loss limits, given as `RTA,LOSS%` like check_ping
+#[derive(Debug, Clone, Copy)]
+pub struct CheckThreshold {
+    rta: f64,
+    loss: f64,
+}
+
+fn parse_check_threshold(s: &str) -> Result<CheckThreshold, String> {
+    let (rta, loss) = s
+        .split_once(',')
+        .ok_or_else(|| format!("invalid threshold: {}, expected RTA,LOSS% e.g. 100.0,20%", s))?;
+    let rta = rta.trim().parse::<f64>().map_err(|_| format!("invalid round trip average: {}", rta))?;
+    let loss = loss
+        .trim()
+        .trim_end_matches('%')
+        .parse::<f64>()
+        .map_err(|_| format!("invalid packet loss: {}", loss))?;
+    Ok(CheckThreshold { rta, loss })
+}
+
+#[derive(Args, Debug)]
+pub struct CheckArgs {
+    #[arg(help = "target IP address or hostname to check", required = true)]
+    target: Vec<String>,
+
+    #[arg(short, long, value_parser = parse_check_threshold, default_value = "100.0,20%", help = "Warning threshold RTA,LOSS%")]
+    warning: CheckThreshold,
+
+    #[arg(short, long, value_parser = parse_check_threshold, default_value = "500.0,60%", help = "Critical threshold RTA,LOSS%")]
+    critical: CheckThreshold,
+
+    #[arg(short, long, default_value_t = 5, help = "Number of pings to send to each target")]
+    packets: usize,
+
+    #[arg(short, long, default_value_t = 0, help = "Interval in seconds between pings")]
+    interval: i32,
+
+    #[arg(short, long, default_value_t = 10, help = "Seconds before the check gives up with UNKNOWN")]
+    timeout: u64,
+
+    #[clap(long = "force_ipv6", default_value_t = false, short = '6', help = "Force using IPv6")]
+    force_ipv6: bool,
+}
+
+/// run the check, print the status line and return the exit code
+pub fn run_check(args: CheckArgs) -> i32 {
+    let (state, line) = match check(&args) {
+        Ok(summaries) => status_line(&args, &summaries),
+        Err(err) => (CheckState::Unknown, format!("PING UNKNOWN - {}", err)),
+    };
+    println!("{}", line);
+    state as i32
+}
+
+// ping every target packets times and return their statistics
+fn check(args: &CheckArgs) -> Result<Vec<TargetSummary>, String> {
+    let mut seen = HashSet::new();
+    let targets: Vec<(String, String)> = args.target.iter()
+        .filter(|t| seen.insert((*t).clone()))
+        .map(|t| get_host_ipaddr(t, args.force_ipv6).map(|ip| (t.clone(), ip)).map_err(|e| e.to_string()))
+        .collect::<Result<_, _>>()?;
+
+    let rt = Builder::new_multi_thread()
+        .worker_threads(targets.len() + 1)
+        .enable_all()
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let shutdown = Shutdown::new();
+    let errs = Arc::new(Mutex::new(Vec::new()));
+    let (ping_event_tx, ping_event_rx) = mpsc::sync_channel::<PingEvent>(0);
+    let ping_event_tx = Arc::new(ping_event_tx);
+
+    // the processor ends once every ping task dropped its sender
+    let processor = {
+        let targets = targets.clone();
+        std::thread::spawn(move || {
+            let config = ProcessorConfig {
+                outage_threshold: 3,
+                health_rules: HealthRules::new(&[], 0.0, Duration::ZERO),
+                anomaly_sensitivity: 0.0,
+                baseline_warmup: 0,
+                outage_history: MAX_OUTAGE_HISTORY,
+            };
+            let mut processor = DataProcessor::new(&targets, "table", &config);
+            while let Ok(event) = ping_event_rx.recv() {
+                processor.process_event(event);
+            }
+            processor.summaries()
+        })
+    };
+
+    let count = args.packets;
+    let interval = if args.interval == 0 { 500 } else { args.interval * 1000 };
+    let finished = rt.block_on(async {
+        // Ctrl+C or SIGTERM ends the pings early, the check then reports UNKNOWN
+        handle_signals(shutdown.clone());
+        let mut tasks = Vec::new();
+        for (addr, ip) in &targets {
+            let (addr, ip) = (addr.clone(), ip.clone());
+            let (errs, shutdown, ping_event_tx) = (errs.clone(), shutdown.clone(), ping_event_tx.clone());
+            tasks.push(task::spawn(async move {
+                if let Err(err) = send_ping(addr, ip.clone(), errs.clone(), count, interval, shutdown, ping_event_tx).await {
+                    lock(&errs).push(format!("host({}) ping err: {}", ip, err));
+                }
+            }));
+        }
+        let all = async {
+            for task in tasks {
+                task.await.ok();
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(args.timeout), all).await.is_ok()
+    });
+
+    if !finished {
+        // the ping tasks may be stuck waiting for the ping process, do not wait for them
+        shutdown.cancel();
+        rt.shutdown_background();
+        return Err(format!("check timed out after {}s", args.timeout));
+    }
+    drop(ping_event_tx);
+    if shutdown.is_cancelled() {
+        return Err("interrupted".to_string());
+    }
+    let summaries = processor.join().map_err(|_| "data processor stopped unexpectedly".to_string())?;
+
+    // no result at all means ping itself failed, not the network
+    if summaries.iter().all(|s| s.sent == 0) {
+        let errs = lock(&errs);
+        return Err(errs.first().cloned().unwrap_or_else(|| "no ping results".to_string()));
+    }
+    Ok(summaries)
+}
+
+// the plugin output: status, a short text per target and perfdata
+fn status_line(args: &CheckArgs, summaries: &[TargetSummary]) -> (CheckState, String) {
+    let mut worst = CheckState::Ok;
+    let mut texts = Vec::new();
+    let mut perfdata = Vec::new();
+    let single = summaries.len() == 1;
+
+    for s in summaries {
+        let state = if s.received == 0 || s.loss >= args.critical.loss || s.avg >= args.critical.rta {
+            CheckState::Critical
+        } else if s.loss >= args.warning.loss || s.avg >= args.warning.rta {
+            CheckState::Warning
+        } else {
+            CheckState::Ok
+        };
+        worst = worst.max(state);
+
+        // U is the unknown value of the plugin guidelines, there is no average without a reply
+        let rta = if s.received == 0 { "U".to_string() } else { format!("{:.2} ms", s.avg) };
+        let text = format!("Packet loss = {:.0}%, RTA = {}", s.loss, rta);
+        texts.push(if single { text } else { format!("{} {}: {}", s.addr, state.as_str(), text) });
+
+        let prefix = if single { String::new() } else { format!("{}_", s.addr) };
+        let rta_value = if s.received == 0 { "U".to_string() } else { format!("{:.3}ms", s.avg) };
+        perfdata.push(format!("{}rta={};{:.3};{:.3};0", prefix, rta_value, args.warning.rta, args.critical.rta));
+        perfdata.push(format!(
+            "{}loss={:.0}%;{};{};0;100",
+            prefix, s.loss, args.warning.loss, args.critical.loss
+        ));
+    }
+
+    let line = format!("PING {} - {}|{}", worst.as_str(), texts.join(", "), perfdata.join(" "));
+    (worst, line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(targets: &[&str]) -> CheckArgs {
+        CheckArgs {
+            target: targets.iter().map(|t| t.to_string()).collect(),
+            warning: parse_check_threshold("100.0,20%").unwrap(),
+            critical: parse_check_threshold("500.0,60%").unwrap(),
+            packets: 5,
+            interval: 0,
+            timeout: 10,
+            force_ipv6: false,
+        }
+    }
+
+    fn summary(addr: &str, received: usize, loss: f64, avg: f64) -> TargetSummary {
+        TargetSummary { addr: addr.into(), sent: 5, received, loss, avg, ..Default::default() }
+    }
+
+    #[test]
+    fn parse_thresholds() {
+        let threshold = parse_check_threshold("200.5, 40%").unwrap();
+        assert_eq!((threshold.rta, threshold.loss), (200.5, 40.0));
+        assert_eq!(parse_check_threshold("100,20").unwrap().loss, 20.0);
+        assert!(parse_check_threshold("100").is_err());
+        assert!(parse_check_threshold("fast,20%").is_err());
+        assert!(parse_check_threshold("100,most").is_err());
+    }
+
+    #[test]
+    fn single_target_status_line() {
+        let (state, line) = status_line(&args(&["example.com"]), &[summary("example.com", 5, 0.0, 12.345)]);
+        assert_eq!(state, CheckState::Ok);
+        assert_eq!(line, "PING OK - Packet loss = 0%, RTA = 12.35 ms|rta=12.345ms;100.000;500.000;0 loss=0%;20;60;0;100");
+
+        let (state, line) = status_line(&args(&["example.com"]), &[summary("example.com", 0, 100.0, 0.0)]);
+        assert_eq!(state, CheckState::Critical);
+        assert_eq!(line, "PING CRITICAL - Packet loss = 100%, RTA = U|rta=U;100.000;500.000;0 loss=100%;20;60;0;100");
+    }
+
+    #[test]
+    fn worst_target_decides_the_state() {
+        let summaries = [summary("a", 5, 0.0, 10.0), summary("b", 4, 20.0, 10.0)];
+        let (state, line) = status_line(&args(&["a", "b"]), &summaries);
+        assert_eq!(state, CheckState::Warning);
+        assert!(line.starts_with("PING WARNING - a OK: Packet loss = 0%, RTA = 10.00 ms, b WARNING: "));
+        assert!(line.contains(" b_loss=20%;20;60;0;100"));
+    }
+}
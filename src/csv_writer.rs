@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::time::Duration;
+use chrono::Local;
+use crate::ip_data::IpData;
+use crate::output::StatsTimer;
+use crate::stats::IntervalStats;
+
+const PING_HEADER: &str = "timestamp,target,ip,probe,seq,status,rtt_ms,state";
+const SUMMARY_HEADER: &str = "timestamp,target,ip,sent,received,loss,min_ms,avg_ms,max_ms,jitter_ms,state,outages";
+
+/// Writes results as CSV, either one row per packet or, with a summary
+/// interval, one row per target every interval
+pub struct CsvWriter {
+    header_written: bool,
+    summary_timer: Option<StatsTimer>,
+    intervals: HashMap<String, IntervalStats>, // key: addr_ip
+}
+
+impl CsvWriter {
+    pub fn new(summary_interval: Duration) -> Self {
+        Self {
+            header_written: false,
+            summary_timer: (!summary_interval.is_zero()).then(|| StatsTimer::new(summary_interval)),
+            intervals: HashMap::new(),
+        }
+    }
+
+    /// switched to another file, `has_header` when it already starts with a header row
+    pub fn start_file(&mut self, has_header: bool) {
+        self.header_written = has_header;
+    }
+
+    pub fn write_header(&mut self, out: &mut impl Write) -> io::Result<()> {
+        if self.header_written {
+            return Ok(());
+        }
+        self.header_written = true;
+        let header = if self.summary_timer.is_some() { SUMMARY_HEADER } else { PING_HEADER };
+        writeln!(out, "{}", header)
+    }
+
+    pub fn write_update(&mut self, out: &mut impl Write, data: &IpData) -> io::Result<()> {
+        self.write_header(out)?;
+
+        if self.summary_timer.is_some() {
+            self.intervals.entry(format!("{}_{}", data.addr, data.ip)).or_default().push(data);
+            return Ok(());
+        }
+
+        let timestamp = data.last_timestamp.unwrap_or_else(Local::now).to_rfc3339();
+        let (status, rtt) = if data.last_attr < 0.0 {
+            ("timeout", String::new())
+        } else {
+            ("ok", format!("{:.2}", data.last_attr))
+        };
+        writeln!(
+            out,
+            "{},{},{},icmp,{},{},{},{}",
+            timestamp,
+            field(&data.addr),
+            field(&data.ip),
+            data.last_seq,
+            status,
+            rtt,
+            data.health
+        )
+    }
+
+    /// write a summary row per target once the summary interval has passed
+    pub fn write_summary(&mut self, out: &mut impl Write, ip_data: &[IpData]) -> io::Result<()> {
+        if !self.summary_timer.as_mut().is_some_and(|timer| timer.due()) {
+            return Ok(());
+        }
+        self.write_header(out)?;
+
+        let timestamp = Local::now().to_rfc3339();
+        for data in ip_data {
+            let interval = self.intervals.remove(&format!("{}_{}", data.addr, data.ip)).unwrap_or_default();
+            // without a reply there is no rtt, an empty field rather than a made up 0
+            let rtt = |value: f64| if interval.received() > 0 { format!("{:.2}", value) } else { String::new() };
+            writeln!(
+                out,
+                "{},{},{},{},{},{:.2},{},{},{},{},{},{}",
+                timestamp,
+                field(&data.addr),
+                field(&data.ip),
+                interval.sent,
+                interval.received(),
+                interval.loss(),
+                rtt(interval.min()),
+                rtt(interval.avg()),
+                rtt(interval.max()),
+                rtt(interval.jitter()),
+                data.health,
+                data.outage.count
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// quote a field when it would break the row
+pub(crate) fn field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(addr: &str, seq: usize, rtt: f64) -> IpData {
+        let mut data = IpData::new(addr.to_string(), "192.0.2.1".to_string());
+        data.last_seq = seq;
+        data.last_attr = rtt;
+        data
+    }
+
+    #[test]
+    fn one_row_per_packet_after_a_single_header() {
+        let mut writer = CsvWriter::new(Duration::ZERO);
+        let mut out = Vec::new();
+        writer.write_update(&mut out, &reply("example.com", 1, 12.5)).unwrap();
+        writer.write_update(&mut out, &reply("a,\"b\"", 2, -1.0)).unwrap();
+
+        let csv = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], PING_HEADER);
+        assert!(lines[1].ends_with(",example.com,192.0.2.1,icmp,1,ok,12.50,OK"));
+        assert!(lines[2].ends_with(",\"a,\"\"b\"\"\",192.0.2.1,icmp,2,timeout,,OK"));
+    }
+
+    #[test]
+    fn summary_rows_cover_the_interval() {
+        let mut writer = CsvWriter::new(Duration::from_millis(1));
+        let mut out = Vec::new();
+        for (seq, rtt) in [(1, 10.0), (2, -1.0), (3, 20.0), (4, 30.0)] {
+            writer.write_update(&mut out, &reply("example.com", seq, rtt)).unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(5));
+        writer.write_summary(&mut out, &[reply("example.com", 4, 30.0)]).unwrap();
+
+        let csv = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], SUMMARY_HEADER);
+        assert!(lines[1].ends_with(",example.com,192.0.2.1,4,3,25.00,10.00,20.00,30.00,10.00,OK,0"), "{}", lines[1]);
+    }
+
+    #[test]
+    fn intervals_without_replies_have_no_rtts() {
+        let mut writer = CsvWriter::new(Duration::from_millis(1));
+        let mut out = Vec::new();
+        writer.write_update(&mut out, &reply("example.com", 1, -1.0)).unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        writer.write_summary(&mut out, &[reply("example.com", 1, -1.0), reply("example.org", 0, -1.0)]).unwrap();
+
+        let csv = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].ends_with(",example.com,192.0.2.1,1,0,100.00,,,,,OK,0"), "{}", lines[1]);
+        // a target without any result in the interval
+        assert!(lines[2].ends_with(",example.org,192.0.2.1,0,0,0.00,,,,,OK,0"), "{}", lines[2]);
+    }
+}
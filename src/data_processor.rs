@@ -1,67 +1,220 @@
-use std::collections::{HashMap, VecDeque};
-use std::sync::{Arc, Mutex, mpsc};
+use std::collections::HashMap;
+use std::sync::mpsc;
+use chrono::{DateTime, Local};
+use tokio::sync::oneshot;
 use crate::ping_event::PingEvent;
 use crate::ip_data::IpData;
+use crate::outage::OutageTracker;
+use crate::health::{HealthMachine, HealthRules, HealthTransition};
+use crate::sinks::{SinkEvent, SinkHub};
+use crate::baseline::Baseline;
+use crate::rolling::RollingWindow;
+use crate::shutdown::Shutdown;
+use crate::stats::RttHistogram;
+use crate::summary::TargetSummary;
+
+// number of health transitions kept per target for display
+const MAX_HEALTH_EVENTS: usize = 20;
+
+#[derive(Clone)]
+pub struct ProcessorConfig {
+    pub(crate) outage_threshold: usize,
+    pub(crate) health_rules: HealthRules,
+    pub(crate) anomaly_sensitivity: f64,
+    pub(crate) baseline_warmup: usize,
+    // finished outages kept per target, MAX_OUTAGE_HISTORY for live runs
+    pub(crate) outage_history: usize,
+}
+
+// per target state that is not sent to the ui
+struct TargetTrackers {
+    outage: OutageTracker,
+    health: HealthMachine,
+    baseline: Baseline,
+    rolling: RollingWindow,
+    // every rtt of the run, for the exit summary
+    rtts: RttHistogram,
+}
 
 pub struct DataProcessor {
     data_map: HashMap<String, IpData>, // key: addr_ip
+    // keys in the order the targets were given
+    keys: Vec<String>,
+    trackers: HashMap<String, TargetTrackers>, // key: addr_ip
     point_num: usize,
+    // transitions not yet handed to the sinks
+    transitions: Vec<HealthTransition>,
+    config: ProcessorConfig,
+    // summaries of targets removed by a reload, still part of the run
+    removed: Vec<TargetSummary>,
+}
+
+/// Changes to a running session, made by a reload, or a request for its statistics
+pub enum TargetUpdate {
+    Add { addr: String, ip: String },
+    Remove { addr: String, ip: String },
+    // the shown name of a target changed, its data is kept
+    Rename { addr: String, name: String },
+    Rules(HealthRules),
+    // a hostname resolves to other addresses, the targets were already moved with Add and Remove
+    DnsChange { addr: String, old_ips: Vec<String>, new_ips: Vec<String> },
+    // the statistics so far, for a dump on SIGUSR1
+    Summaries(oneshot::Sender<Vec<TargetSummary>>),
 }
 
 impl DataProcessor {
-    pub fn new(targets: &[(String, String)], view_type: &str) -> Self {
+    pub fn new(targets: &[(String, String)], view_type: &str, config: &ProcessorConfig) -> Self {
+        Self::new_at(targets, view_type, config, Local::now())
+    }
+
+    /// a processor for results starting at `started_at`, e.g. those of a recording
+    pub fn new_at(targets: &[(String, String)], view_type: &str, config: &ProcessorConfig, started_at: DateTime<Local>) -> Self {
         let point_num = if view_type == "point" || view_type == "sparkline" {
             200
         } else {
             10
         };
-        let mut data_map = HashMap::new();
-        
+        let mut processor = Self {
+            data_map: HashMap::new(),
+            keys: Vec::new(),
+            trackers: HashMap::new(),
+            point_num,
+            transitions: Vec::new(),
+            config: config.clone(),
+            removed: Vec::new(),
+        };
         for (addr, ip) in targets {
-            let key = format!("{}_{}", addr, ip);
-            data_map.insert(key, IpData {
-                addr: addr.clone(),
-                ip: ip.clone(),
-                rtts: VecDeque::new(),
-                last_attr: 0.0,
-                min_rtt: 0.0,
-                max_rtt: 0.0,
-                timeout: 0,
-                received: 0,
-                pop_count: 0,
-            });
+            processor.add_target(addr, ip, started_at);
         }
-        
-        Self { data_map, point_num }
+        processor
     }
-    
+
+    /// start tracking a target, its outages are counted from `started_at`
+    pub fn add_target(&mut self, addr: &str, ip: &str, started_at: DateTime<Local>) {
+        let key = format!("{}_{}", addr, ip);
+        if self.data_map.contains_key(&key) {
+            return;
+        }
+        self.data_map.insert(key.clone(), IpData::new(addr.to_string(), ip.to_string()));
+        self.keys.push(key.clone());
+        self.trackers.insert(key, TargetTrackers {
+            outage: OutageTracker::new(self.config.outage_threshold, started_at).with_history_limit(self.config.outage_history),
+            health: HealthMachine::new(&self.config.health_rules, addr, ip),
+            baseline: Baseline::new(self.config.anomaly_sensitivity, self.config.baseline_warmup),
+            rolling: RollingWindow::default(),
+            rtts: RttHistogram::default(),
+        });
+    }
+
+    /// stop tracking a target, its summary is kept for the end of the run
+    pub fn remove_target(&mut self, addr: &str, ip: &str) {
+        let key = format!("{}_{}", addr, ip);
+        self.keys.retain(|k| *k != key);
+        if let (Some(data), Some(trackers)) = (self.data_map.remove(&key), self.trackers.remove(&key)) {
+            self.removed.push(TargetSummary::new(&data, &trackers.rtts));
+        }
+    }
+
+    /// show the IPs of a target under a new name, their data and trackers are kept
+    pub fn rename_target(&mut self, addr: &str, name: &str) {
+        for key in self.keys.iter_mut() {
+            if self.data_map.get(key.as_str()).is_none_or(|data| data.addr != addr) {
+                continue;
+            }
+            let (Some(mut data), Some(mut trackers)) = (self.data_map.remove(key.as_str()), self.trackers.remove(key.as_str())) else {
+                continue;
+            };
+            *key = format!("{}_{}", name, data.ip);
+            data.addr = name.to_string();
+            trackers.health.set_rules(&self.config.health_rules, name, &data.ip);
+            self.data_map.insert(key.clone(), data);
+            self.trackers.insert(key.clone(), trackers);
+        }
+    }
+
+    /// apply new health thresholds, the health state of every target is kept
+    pub fn set_rules(&mut self, rules: HealthRules) {
+        for key in &self.keys {
+            if let (Some(data), Some(trackers)) = (self.data_map.get(key), self.trackers.get_mut(key)) {
+                trackers.health.set_rules(&rules, &data.addr, &data.ip);
+            }
+        }
+        self.config.health_rules = rules;
+    }
+
     pub fn process_event(&mut self, event: PingEvent) -> Option<IpData> {
-        match event {
-            PingEvent::Success { addr, ip, rtt, .. } => {
+        let (data, trackers, seq, now) = match event {
+            PingEvent::Success { addr, ip, rtt, seq, timestamp } => {
                 let key = format!("{}_{}", addr, ip);
-                if let Some(data) = self.data_map.get_mut(&key) {
-                    Self::update_success_stats(data, rtt, self.point_num);
-                    Some(data.clone())
-                } else {
-                    None
-                }
+                let data = self.data_map.get_mut(&key)?;
+                let trackers = self.trackers.get_mut(&key)?;
+                let anomalous = trackers.baseline.observe(rtt);
+                Self::update_success_stats(data, rtt, anomalous, self.point_num);
+                trackers.outage.on_success(timestamp);
+                trackers.rolling.push(timestamp, Some(rtt));
+                trackers.rtts.push(rtt);
+                (data, trackers, seq, timestamp)
             },
-            PingEvent::Timeout { addr, ip, .. } => {
+            PingEvent::Timeout { addr, ip, seq, timestamp } => {
                 let key = format!("{}_{}", addr, ip);
-                if let Some(data) = self.data_map.get_mut(&key) {
-                    Self::update_timeout_stats(data, self.point_num);
-                    Some(data.clone())
-                } else {
-                    None
-                }
+                let data = self.data_map.get_mut(&key)?;
+                let trackers = self.trackers.get_mut(&key)?;
+                trackers.baseline.observe_timeout();
+                Self::update_timeout_stats(data, self.point_num);
+                trackers.outage.on_timeout(timestamp);
+                trackers.rolling.push(timestamp, None);
+                (data, trackers, seq, timestamp)
             },
+            PingEvent::Error { addr, ip, .. } => {
+                // counted only, the next result carries it to the ui and sinks
+                let data = self.data_map.get_mut(&format!("{}_{}", addr, ip))?;
+                data.errors += 1;
+                return None;
+            },
+        };
+
+        data.last_seq = seq;
+        data.last_timestamp = Some(now);
+
+        data.outage = trackers.outage.stats().clone();
+        data.baseline = trackers.baseline.stats().clone();
+        data.rolling = trackers.rolling.stats(now);
+        if let Some(transition) = trackers.health.evaluate(data, now) {
+            self.transitions.push(transition.clone());
+            data.health_events.push_back(transition);
+            if data.health_events.len() > MAX_HEALTH_EVENTS {
+                data.health_events.pop_front();
+            }
         }
+        data.health = trackers.health.state();
+        Some(data.clone())
+    }
+
+    /// whole run statistics of every target, including those removed on the way
+    pub fn summaries(&self) -> Vec<TargetSummary> {
+        self.keys
+            .iter()
+            .filter_map(|key| Some(TargetSummary::new(self.data_map.get(key)?, &self.trackers.get(key)?.rtts)))
+            .chain(self.removed.iter().cloned())
+            .collect()
+    }
+
+    /// current data of every target, in the order the targets were given
+    pub fn snapshot(&self) -> Vec<IpData> {
+        self.keys.iter().filter_map(|key| self.data_map.get(key).cloned()).collect()
+    }
+
+    /// health transitions since the last call
+    pub fn take_transitions(&mut self) -> Vec<HealthTransition> {
+        std::mem::take(&mut self.transitions)
     }
     
-    fn update_success_stats(data: &mut IpData, rtt: f64, point_num: usize) {
+    fn update_success_stats(data: &mut IpData, rtt: f64, anomalous: bool, point_num: usize) {
         data.received += 1;
         data.last_attr = rtt;
+        data.rtt_sum += rtt;
         data.rtts.push_back(rtt);
+        data.anomalies.push_back(anomalous);
         
         if data.min_rtt == 0.0 || rtt < data.min_rtt {
             data.min_rtt = rtt;
@@ -72,17 +225,20 @@ impl DataProcessor {
         
         if data.rtts.len() > point_num {
             data.rtts.pop_front();
+            data.anomalies.pop_front();
             data.pop_count += 1;
         }
     }
     
     fn update_timeout_stats(data: &mut IpData, point_num: usize) {
         data.rtts.push_back(-1.0);
+        data.anomalies.push_back(false);
         data.last_attr = -1.0;
         data.timeout += 1;
         
         if data.rtts.len() > point_num {
             data.rtts.pop_front();
+            data.anomalies.pop_front();
             data.pop_count += 1;
         }
     }
@@ -92,17 +248,47 @@ impl DataProcessor {
 pub fn start_data_processor(
     ping_event_rx: mpsc::Receiver<PingEvent>,
     ui_data_tx: mpsc::SyncSender<IpData>,
-    targets: Vec<(String, String)>,
-    view_type: String,
-    running: Arc<Mutex<bool>>,
-) {
+    updates_rx: mpsc::Receiver<TargetUpdate>,
+    mut processor: DataProcessor,
+    mut sinks: SinkHub,
+    shutdown: Shutdown,
+) -> std::thread::JoinHandle<Vec<TargetSummary>> {
     std::thread::spawn(move || {
-        let mut processor = DataProcessor::new(&targets, &view_type);
-        
-        while *running.lock().unwrap() {
+
+        while !shutdown.is_cancelled() {
+            for update in updates_rx.try_iter() {
+                match update {
+                    TargetUpdate::Add { addr, ip } => processor.add_target(&addr, &ip, Local::now()),
+                    TargetUpdate::Remove { addr, ip } => processor.remove_target(&addr, &ip),
+                    TargetUpdate::Rename { addr, name } => processor.rename_target(&addr, &name),
+                    TargetUpdate::Rules(rules) => processor.set_rules(rules),
+                    TargetUpdate::DnsChange { addr, old_ips, new_ips } => {
+                        sinks.publish(SinkEvent::DnsChange { addr, old_ips, new_ips });
+                    }
+                    TargetUpdate::Summaries(summaries_tx) => {
+                        summaries_tx.send(processor.summaries()).ok();
+                    }
+                }
+            }
             match ping_event_rx.recv_timeout(std::time::Duration::from_millis(100)) {
                 Ok(event) => {
+                    if let PingEvent::Error { ref addr, ref ip, ref message } = event {
+                        sinks.publish(SinkEvent::ProbeError {
+                            addr: addr.clone(),
+                            ip: ip.clone(),
+                            message: message.clone(),
+                        });
+                    }
                     if let Some(updated_data) = processor.process_event(event) {
+                        if !sinks.is_empty() {
+                            sinks.publish(SinkEvent::Update { data: updated_data.clone() });
+                        }
+                        for transition in processor.take_transitions() {
+                            sinks.publish(SinkEvent::Transition {
+                                transition,
+                                data: updated_data.clone(),
+                            });
+                        }
                         if ui_data_tx.send(updated_data).is_err() {
                             // UI channel closed, exit
                             break;
@@ -110,7 +296,7 @@ pub fn start_data_processor(
                     }
                 },
                 Err(mpsc::RecvTimeoutError::Timeout) => {
-                    // Continue checking running flag
+                    // Continue checking for a shutdown
                     continue;
                 },
                 Err(mpsc::RecvTimeoutError::Disconnected) => {
@@ -119,5 +305,99 @@ pub fn start_data_processor(
                 }
             }
         }
-    });
-}
\ No newline at end of file
+
+        // let the sinks deliver what is still queued
+        sinks.shutdown();
+        processor.summaries()
+    })
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use chrono::TimeZone;
+    use crate::health::HealthState;
+    use crate::outage::MAX_OUTAGE_HISTORY;
+    use crate::sinks::Sink;
+
+    // keeps the transitions it is sent
+    struct TransitionSink(Arc<Mutex<Vec<(HealthState, HealthState)>>>);
+
+    impl Sink for TransitionSink {
+        fn handle(&mut self, event: &SinkEvent) {
+            if let SinkEvent::Transition { transition, .. } = event {
+                self.0.lock().unwrap().push((transition.from, transition.to));
+            }
+        }
+
+        fn wants_updates(&self) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn targets_recover_after_a_loss_burst() {
+        let config = ProcessorConfig {
+            outage_threshold: 3,
+            health_rules: HealthRules::new(&[], 0.0, Duration::ZERO),
+            anomaly_sensitivity: 3.0,
+            baseline_warmup: 20,
+            outage_history: MAX_OUTAGE_HISTORY,
+        };
+        let (addr, ip) = ("example.com".to_string(), "192.0.2.1".to_string());
+        let processor = DataProcessor::new(&[(addr.clone(), ip.clone())], "graph", &config);
+        let transitions = Arc::new(Mutex::new(Vec::new()));
+        let mut sinks = SinkHub::new();
+        sinks.add("transitions", Box::new(TransitionSink(transitions.clone())), Duration::from_secs(60));
+
+        let (ping_event_tx, ping_event_rx) = mpsc::sync_channel(100);
+        let (ui_data_tx, _ui_data_rx) = mpsc::sync_channel(100);
+        let (_updates_tx, updates_rx) = mpsc::channel();
+        let handle = start_data_processor(ping_event_rx, ui_data_tx, updates_rx, processor, sinks, Shutdown::new());
+
+        // two replies, three timeouts and then enough replies to push them out of the recent records
+        for seq in 0..15 {
+            let timestamp = Local.timestamp_opt(1_700_000_000 + seq as i64, 0).unwrap();
+            let event = if (2..5).contains(&seq) {
+                PingEvent::Timeout { addr: addr.clone(), ip: ip.clone(), seq, timestamp }
+            } else {
+                PingEvent::Success { addr: addr.clone(), ip: ip.clone(), rtt: 20.0, seq, timestamp }
+            };
+            ping_event_tx.send(event).unwrap();
+        }
+        drop(ping_event_tx);
+        let summaries = handle.join().unwrap();
+
+        assert_eq!(summaries[0].loss, 20.0);
+        assert_eq!(*transitions.lock().unwrap(), [
+            (HealthState::Ok, HealthState::Warn),
+            (HealthState::Warn, HealthState::Crit),
+            (HealthState::Crit, HealthState::Warn),
+            (HealthState::Warn, HealthState::Ok),
+        ]);
+    }
+
+    #[test]
+    fn renamed_targets_keep_their_data() {
+        let config = ProcessorConfig {
+            outage_threshold: 3,
+            health_rules: HealthRules::new(&[], 0.0, Duration::ZERO),
+            anomaly_sensitivity: 3.0,
+            baseline_warmup: 20,
+            outage_history: MAX_OUTAGE_HISTORY,
+        };
+        let targets = [("router".to_string(), "192.0.2.1".to_string()), ("dns".to_string(), "192.0.2.53".to_string())];
+        let mut processor = DataProcessor::new(&targets, "graph", &config);
+        let timestamp = Local.timestamp_opt(1_700_000_000, 0).unwrap();
+        processor.process_event(PingEvent::Success { addr: "router".into(), ip: "192.0.2.1".into(), rtt: 20.0, seq: 1, timestamp });
+
+        processor.rename_target("router", "gateway");
+        let event = PingEvent::Success { addr: "gateway".into(), ip: "192.0.2.1".into(), rtt: 30.0, seq: 2, timestamp };
+        let data = processor.process_event(event).unwrap();
+        assert_eq!((data.addr.as_str(), data.received), ("gateway", 2));
+        // the order of the targets stays
+        let addrs: Vec<String> = processor.snapshot().into_iter().map(|d| d.addr).collect();
+        assert_eq!(addrs, ["gateway", "dns"]);
+    }
+}
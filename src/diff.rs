@@ -0,0 +1,474 @@
+use std::collections::VecDeque;
+use std::io::Write;
+use clap::Args;
+use serde_json::json;
+use crate::csv_writer::field;
+use crate::error::NpingError;
+use crate::output::OutputFormat;
+use crate::recording::{load_recording, Recording};
+use crate::report::{analyze, TargetReport};
+use crate::stats::{calculate_jitter, mann_whitney_u_test, poisson_rate_test, two_proportion_z_test, welch_t_test};
+use crate::ui::utils::format_duration;
+
+#[derive(Args, Debug)]
+pub struct DiffArgs {
+    #[arg(help = "Recording before the change, JSONL or CSV output file or SQLite database")]
+    before: String,
+
+    #[arg(help = "Recording after the change")]
+    after: String,
+
+    #[arg(long, help = "Session of a SQLite recording before the change, defaults to the latest")]
+    before_session: Option<i64>,
+
+    #[arg(long, help = "Session of a SQLite recording after the change, defaults to the latest")]
+    after_session: Option<i64>,
+
+    #[arg(long, default_value_t = 0.05, help = "Significance level of the tests")]
+    alpha: f64,
+
+    #[arg(
+        long,
+        default_value_t = 5.0,
+        help = "Percent avg, p95 or jitter have to change by to count, tiny shifts are significant in long recordings"
+    )]
+    min_change: f64,
+
+    #[arg(long, default_value_t = 3, help = "Number of consecutive timeouts counted as an outage")]
+    outage_threshold: usize,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text, help = "Output format text/jsonl/csv")]
+    format: OutputFormat,
+}
+
+// whether a metric got better or worse, lower is better for all of them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Verdict {
+    Regression,
+    Improved,
+    // not significant, or too little data to tell
+    Unchanged,
+}
+
+impl Verdict {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Verdict::Regression => "regression",
+            Verdict::Improved => "improved",
+            Verdict::Unchanged => "",
+        }
+    }
+}
+
+struct MetricDiff {
+    name: &'static str,
+    before: f64,
+    after: f64,
+    // None when there is too little data for the test
+    p_value: Option<f64>,
+    verdict: Verdict,
+}
+
+impl MetricDiff {
+    fn format_value(&self, value: f64) -> String {
+        match self.name {
+            "loss" => format!("{:.2}%", value),
+            "outages" => format!("{}", value),
+            _ => format!("{:.2}ms", value),
+        }
+    }
+
+    // loss in percentage points, outages as a count, rtts relative to before
+    fn format_change(&self) -> String {
+        let change = self.after - self.before;
+        match self.name {
+            "loss" => format!("{:+.2}pp", change),
+            "outages" => format!("{:+}", change),
+            _ if self.before > 0.0 => format!("{:+.1}%", change / self.before * 100.0),
+            _ => format!("{:+.2}ms", change),
+        }
+    }
+
+    fn format_p_value(&self) -> String {
+        match self.p_value {
+            Some(p) if p < 0.001 => "<0.001".to_string(),
+            Some(p) => format!("{:.3}", p),
+            None => "-".to_string(),
+        }
+    }
+}
+
+struct TargetDiff {
+    addr: String,
+    before_ip: String,
+    after_ip: String,
+    metrics: Vec<MetricDiff>,
+}
+
+// what the tests need of one target
+struct Sample {
+    sent: usize,
+    lost: usize,
+    loss: f64,
+    avg: f64,
+    p95: f64,
+    outages: usize,
+    rtts: Vec<f64>,
+    // differences between consecutive rtts, jitter is their mean
+    rtt_steps: Vec<f64>,
+}
+
+impl Sample {
+    fn new(report: &TargetReport) -> Self {
+        let rtts: Vec<f64> = report.results.iter().filter_map(|(_, rtt)| *rtt).collect();
+        let rtt_steps = rtts.windows(2).map(|w| (w[1] - w[0]).abs()).collect();
+        let s = &report.summary;
+        Self {
+            sent: s.sent,
+            lost: s.sent - s.received,
+            loss: s.loss,
+            avg: s.avg,
+            p95: s.p95,
+            outages: s.outages,
+            rtts,
+            rtt_steps,
+        }
+    }
+
+    fn jitter(&self) -> f64 {
+        calculate_jitter(&self.rtts.iter().copied().collect::<VecDeque<f64>>())
+    }
+}
+
+fn compare(before: &Sample, after: &Sample, args: &DiffArgs) -> Vec<MetricDiff> {
+    let verdict = |before: f64, after: f64, p_value: Option<f64>, min_change: f64| {
+        let big_enough = (after - before).abs() > before * min_change / 100.0;
+        match p_value {
+            Some(p) if p < args.alpha && big_enough && after > before => Verdict::Regression,
+            Some(p) if p < args.alpha && big_enough && after < before => Verdict::Improved,
+            _ => Verdict::Unchanged,
+        }
+    };
+    let metric = |name, before: f64, after: f64, p_value: Option<f64>, min_change: f64| MetricDiff {
+        name,
+        before,
+        after,
+        p_value,
+        verdict: verdict(before, after, p_value, min_change),
+    };
+
+    let loss_p = two_proportion_z_test(before.lost, before.sent, after.lost, after.sent);
+    let avg_p = welch_t_test(&before.rtts, &after.rtts);
+    let jitter_p = welch_t_test(&before.rtt_steps, &after.rtt_steps);
+    // a mean test misses a slower tail, rank the whole distributions instead
+    let p95_p = mann_whitney_u_test(&before.rtts, &after.rtts);
+    // outages per probe sent, the recordings may differ in length
+    let outages_p = poisson_rate_test(before.outages, before.sent as f64, after.outages, after.sent as f64);
+    vec![
+        metric("loss", before.loss, after.loss, loss_p, 0.0),
+        metric("avg", before.avg, after.avg, avg_p, args.min_change),
+        metric("p95", before.p95, after.p95, p95_p, args.min_change),
+        metric("jitter", before.jitter(), after.jitter(), jitter_p, args.min_change),
+        metric("outages", before.outages as f64, after.outages as f64, outages_p, 0.0),
+    ]
+}
+
+// pair the targets of both recordings by address, with several IPs per address
+// the same IP is preferred and the rest are paired in order
+fn align(before: &[TargetReport], after: &[TargetReport]) -> (Vec<(usize, usize)>, Vec<usize>, Vec<usize>) {
+    let mut pairs = Vec::new();
+    let mut matched = vec![false; after.len()];
+    let mut unmatched = Vec::new();
+    for (i, b) in before.iter().enumerate() {
+        let same_ip = after
+            .iter()
+            .enumerate()
+            .position(|(j, a)| !matched[j] && a.summary.addr == b.summary.addr && a.summary.ip == b.summary.ip);
+        match same_ip {
+            Some(j) => {
+                matched[j] = true;
+                pairs.push((i, j));
+            }
+            None => unmatched.push(i),
+        }
+    }
+    let mut only_before = Vec::new();
+    for i in unmatched {
+        let addr = &before[i].summary.addr;
+        match after.iter().enumerate().position(|(j, a)| !matched[j] && &a.summary.addr == addr) {
+            Some(j) => {
+                matched[j] = true;
+                pairs.push((i, j));
+            }
+            None => only_before.push(i),
+        }
+    }
+    pairs.sort();
+    let only_after = (0..after.len()).filter(|&j| !matched[j]).collect();
+    (pairs, only_before, only_after)
+}
+
+/// compare two recordings and print the changes, returns whether any target regressed
+pub fn run_diff(args: DiffArgs) -> Result<bool, NpingError> {
+    let before = load_recording(&args.before, args.before_session).map_err(|e| NpingError::Output(format!("{}: {}", args.before, e)))?;
+    let after = load_recording(&args.after, args.after_session).map_err(|e| NpingError::Output(format!("{}: {}", args.after, e)))?;
+    let before_reports = analyze(&before, args.outage_threshold);
+    let after_reports = analyze(&after, args.outage_threshold);
+
+    let (pairs, only_before, only_after) = align(&before_reports, &after_reports);
+    let diffs: Vec<TargetDiff> = pairs
+        .iter()
+        .map(|&(i, j)| {
+            let (b, a) = (&before_reports[i], &after_reports[j]);
+            TargetDiff {
+                addr: b.summary.addr.clone(),
+                before_ip: b.summary.ip.clone(),
+                after_ip: a.summary.ip.clone(),
+                metrics: compare(&Sample::new(b), &Sample::new(a), &args),
+            }
+        })
+        .collect();
+    let only_before: Vec<&TargetReport> = only_before.iter().map(|&i| &before_reports[i]).collect();
+    let only_after: Vec<&TargetReport> = only_after.iter().map(|&j| &after_reports[j]).collect();
+
+    let mut out = std::io::stdout().lock();
+    match args.format {
+        OutputFormat::Text => print_text(&mut out, &args, (&before, &after), &diffs, &only_before, &only_after),
+        OutputFormat::Jsonl => print_jsonl(&mut out, &diffs, &only_before, &only_after),
+        OutputFormat::Csv => print_csv(&mut out, &diffs),
+    }
+    .map_err(|e| NpingError::Output(format!("failed to write the comparison: {}", e)))?;
+    Ok(diffs.iter().flat_map(|d| &d.metrics).any(|m| m.verdict == Verdict::Regression))
+}
+
+fn describe(path: &str, recording: &Recording) -> String {
+    format!(
+        "{} ({}, {})",
+        path,
+        recording.started_at.format("%Y-%m-%d %H:%M"),
+        format_duration((recording.finished_at() - recording.started_at).to_std().unwrap_or_default())
+    )
+}
+
+fn print_text(
+    out: &mut impl Write,
+    args: &DiffArgs,
+    (before, after): (&Recording, &Recording),
+    diffs: &[TargetDiff],
+    only_before: &[&TargetReport],
+    only_after: &[&TargetReport],
+) -> std::io::Result<()> {
+    writeln!(out, "--- nping diff: {} -> {} ---", describe(&args.before, before), describe(&args.after, after))?;
+    for diff in diffs {
+        writeln!(out)?;
+        if diff.before_ip == diff.after_ip {
+            writeln!(out, "{} ({})", diff.addr, diff.before_ip)?;
+        } else {
+            writeln!(out, "{} ({} -> {})", diff.addr, diff.before_ip, diff.after_ip)?;
+        }
+        writeln!(out, "  {:<8} {:>10} {:>10} {:>10} {:>8}", "METRIC", "BEFORE", "AFTER", "CHANGE", "P-VALUE")?;
+        for m in &diff.metrics {
+            let verdict = match m.verdict {
+                Verdict::Regression => "  REGRESSION",
+                Verdict::Improved => "  improved",
+                Verdict::Unchanged => "",
+            };
+            writeln!(
+                out,
+                "  {:<8} {:>10} {:>10} {:>10} {:>8}{}",
+                m.name,
+                m.format_value(m.before),
+                m.format_value(m.after),
+                m.format_change(),
+                m.format_p_value(),
+                verdict
+            )?;
+        }
+    }
+    if !only_before.is_empty() || !only_after.is_empty() {
+        writeln!(out)?;
+    }
+    for r in only_before {
+        writeln!(out, "only before: {} ({})", r.summary.addr, r.summary.ip)?;
+    }
+    for r in only_after {
+        writeln!(out, "only after: {} ({})", r.summary.addr, r.summary.ip)?;
+    }
+
+    let regressions: Vec<String> = diffs
+        .iter()
+        .flat_map(|d| d.metrics.iter().filter(|m| m.verdict == Verdict::Regression).map(move |m| format!("{} {}", d.addr, m.name)))
+        .collect();
+    writeln!(out)?;
+    if regressions.is_empty() {
+        writeln!(out, "no significant regressions (alpha {})", args.alpha)
+    } else {
+        writeln!(out, "{} significant regressions (alpha {}): {}", regressions.len(), args.alpha, regressions.join(", "))
+    }
+}
+
+fn print_jsonl(
+    out: &mut impl Write,
+    diffs: &[TargetDiff],
+    only_before: &[&TargetReport],
+    only_after: &[&TargetReport],
+) -> std::io::Result<()> {
+    for diff in diffs {
+        for m in &diff.metrics {
+            let record = json!({
+                "type": "diff",
+                "target": diff.addr,
+                "ip_before": diff.before_ip,
+                "ip_after": diff.after_ip,
+                "metric": m.name,
+                "before": m.before,
+                "after": m.after,
+                "p_value": m.p_value,
+                "verdict": m.verdict.as_str(),
+            });
+            writeln!(out, "{}", record)?;
+        }
+    }
+    for (side, reports) in [("before", only_before), ("after", only_after)] {
+        for r in reports {
+            writeln!(out, "{}", json!({ "type": "unmatched", "only_in": side, "target": r.summary.addr, "ip": r.summary.ip }))?;
+        }
+    }
+    Ok(())
+}
+
+fn print_csv(out: &mut impl Write, diffs: &[TargetDiff]) -> std::io::Result<()> {
+    writeln!(out, "target,ip_before,ip_after,metric,before,after,p_value,verdict")?;
+    for diff in diffs {
+        for m in &diff.metrics {
+            writeln!(
+                out,
+                "{},{},{},{},{},{},{},{}",
+                field(&diff.addr),
+                field(&diff.before_ip),
+                field(&diff.after_ip),
+                m.name,
+                m.before,
+                m.after,
+                m.p_value.map(|p| p.to_string()).unwrap_or_default(),
+                m.verdict.as_str()
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::summary::TargetSummary;
+
+    fn args() -> DiffArgs {
+        DiffArgs {
+            before: "before.jsonl".into(),
+            after: "after.jsonl".into(),
+            before_session: None,
+            after_session: None,
+            alpha: 0.05,
+            min_change: 5.0,
+            outage_threshold: 3,
+            format: OutputFormat::Text,
+        }
+    }
+
+    // `sent` probes with the given rtts as replies and the rest lost
+    fn sample(sent: usize, rtts: Vec<f64>, outages: usize) -> Sample {
+        let mut sorted = rtts.clone();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let lost = sent - rtts.len();
+        Sample {
+            sent,
+            lost,
+            loss: lost as f64 / sent as f64 * 100.0,
+            avg: rtts.iter().sum::<f64>() / rtts.len() as f64,
+            p95: sorted[(sorted.len() * 95).div_ceil(100) - 1],
+            outages,
+            rtt_steps: rtts.windows(2).map(|w| (w[1] - w[0]).abs()).collect(),
+            rtts,
+        }
+    }
+
+    fn verdicts(metrics: &[MetricDiff]) -> Vec<(&'static str, Verdict)> {
+        metrics.iter().map(|m| (m.name, m.verdict)).collect()
+    }
+
+    fn steady() -> Vec<f64> {
+        (0..1000).map(|i| 20.0 + (i % 7) as f64 * 0.5).collect()
+    }
+
+    fn report(addr: &str, ip: &str) -> TargetReport {
+        TargetReport {
+            summary: TargetSummary { addr: addr.to_string(), ip: ip.to_string(), ..Default::default() },
+            results: Vec::new(),
+            outages: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn nothing_changed() {
+        let metrics = compare(&sample(1000, steady(), 0), &sample(1000, steady(), 0), &args());
+        assert!(metrics.iter().all(|m| m.verdict == Verdict::Unchanged));
+        // no outage on either side leaves nothing to test
+        assert_eq!(metrics[4].p_value, None);
+    }
+
+    #[test]
+    fn slower_replies_and_more_loss_are_regressions() {
+        let after: Vec<f64> = steady().iter().map(|rtt| rtt + 10.0).collect();
+        let metrics = compare(&sample(1000, steady(), 0), &sample(1050, after, 0), &args());
+        assert_eq!(verdicts(&metrics)[..2], [("loss", Verdict::Regression), ("avg", Verdict::Regression)]);
+        assert_eq!(metrics[1].format_change(), "+46.5%");
+    }
+
+    #[test]
+    fn slower_tail_is_a_p95_regression() {
+        // every tenth reply takes 80ms longer, the mean moves less than the tail
+        let after: Vec<f64> = steady().iter().enumerate().map(|(i, rtt)| if i % 10 == 0 { rtt + 80.0 } else { *rtt }).collect();
+        let metrics = compare(&sample(1000, steady(), 0), &sample(1000, after, 0), &args());
+        assert_eq!(metrics[2].name, "p95");
+        assert!(metrics[2].p_value.unwrap() < 0.05);
+        assert_eq!(metrics[2].verdict, Verdict::Regression);
+    }
+
+    #[test]
+    fn new_outages_are_a_regression() {
+        let metrics = compare(&sample(1000, steady(), 0), &sample(1050, steady(), 50), &args());
+        assert_eq!(verdicts(&metrics)[4], ("outages", Verdict::Regression));
+        assert_eq!(verdicts(&metrics)[0], ("loss", Verdict::Regression));
+
+        // the same outage rate in a longer recording
+        let metrics = compare(&sample(1000, steady(), 5), &sample(2000, [steady(), steady()].concat(), 10), &args());
+        assert_eq!(metrics[4].verdict, Verdict::Unchanged);
+    }
+
+    #[test]
+    fn faster_replies_are_an_improvement() {
+        let after: Vec<f64> = steady().iter().map(|rtt| rtt - 10.0).collect();
+        let metrics = compare(&sample(1000, steady(), 0), &sample(1000, after, 0), &args());
+        assert_eq!(verdicts(&metrics)[1..3], [("avg", Verdict::Improved), ("p95", Verdict::Improved)]);
+    }
+
+    #[test]
+    fn small_shifts_are_ignored() {
+        // significant in a long recording, but below --min-change
+        let after: Vec<f64> = steady().iter().map(|rtt| rtt + 0.5).collect();
+        let metrics = compare(&sample(1000, steady(), 0), &sample(1000, after, 0), &args());
+        assert!(metrics[1].p_value.unwrap() < 0.05);
+        assert_eq!(metrics[1].verdict, Verdict::Unchanged);
+    }
+
+    #[test]
+    fn targets_are_paired_by_address() {
+        let before = [report("a.example", "192.0.2.1"), report("b.example", "192.0.2.2"), report("gone.example", "192.0.2.3")];
+        let after = [report("b.example", "192.0.2.9"), report("a.example", "192.0.2.1"), report("new.example", "192.0.2.4")];
+        let (pairs, only_before, only_after) = align(&before, &after);
+        assert_eq!(pairs, [(0, 1), (1, 0)]);
+        assert_eq!(only_before, [2]);
+        assert_eq!(only_after, [2]);
+    }
+}
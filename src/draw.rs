@@ -1,19 +1,29 @@
 use ratatui::backend::{Backend, CrosstermBackend};
-use ratatui::{Terminal};
+use ratatui::{Frame, Terminal};
+use ratatui::layout::Rect;
 use crate::ip_data::IpData;
 use std::io::{self, Stdout};
 use std::error::Error;
+use ratatui::crossterm::cursor::Show;
 use ratatui::crossterm::execute;
 use ratatui::crossterm::terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
-use crate::ui::{draw_graph_view, draw_point_view, draw_table_view, draw_sparkline_view};
+use crate::ui::{draw_graph_view, draw_point_view, draw_table_view, draw_sparkline_view, draw_outage_view};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex, mpsc};
 use std::time::Duration;
 use ratatui::crossterm::event;
 use ratatui::crossterm::event::{Event, KeyCode, KeyModifiers};
+use crate::output::{apply_update, OutputFile};
+use crate::shutdown::Shutdown;
+use crate::error::lock;
+
+// set while the ui owns the terminal, so a panic only restores it when there is something to restore
+static TERMINAL_ACTIVE: AtomicBool = AtomicBool::new(false);
 
 /// init terminal
 pub fn init_terminal() -> Result<Terminal<CrosstermBackend<Stdout>>, Box<dyn Error>> {
     enable_raw_mode()?;
+    TERMINAL_ACTIVE.store(true, Ordering::SeqCst);
     let mut stdout = io::stdout();
     // enter alternate screen
     execute!(stdout, EnterAlternateScreen)?;
@@ -26,6 +36,7 @@ pub fn init_terminal() -> Result<Terminal<CrosstermBackend<Stdout>>, Box<dyn Err
 
 // restore terminal and show cursor
 pub fn restore_terminal(terminal: &mut Terminal<CrosstermBackend<Stdout>>) -> Result<(), Box<dyn Error>> {
+    TERMINAL_ACTIVE.store(false, Ordering::SeqCst);
     disable_raw_mode()?;
     terminal.show_cursor()?;
     // leave alternate screen
@@ -33,6 +44,25 @@ pub fn restore_terminal(terminal: &mut Terminal<CrosstermBackend<Stdout>>) -> Re
     Ok(())
 }
 
+// restore the terminal without its handle, e.g. from a panic or when exiting from another thread
+pub fn reset_terminal() {
+    if !TERMINAL_ACTIVE.swap(false, Ordering::SeqCst) {
+        return;
+    }
+    disable_raw_mode().ok();
+    execute!(io::stdout(), LeaveAlternateScreen, Show).ok();
+}
+
+// a panic leaves the terminal in raw mode, restore it before the message is printed and stop the run
+pub fn set_panic_hook(shutdown: Shutdown) {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        reset_terminal();
+        default_hook(info);
+        shutdown.cancel();
+    }));
+}
+
 
 /// draw ui interface
 pub fn draw_interface<B: Backend>(
@@ -42,59 +72,35 @@ pub fn draw_interface<B: Backend>(
     errs: &[String],
 ) -> Result<(), Box<dyn Error>> {
     terminal.draw(|f| {
-        match view_type {
-            "graph" => {
-                draw_graph_view::<B>(f, ip_data, errs);
-            }
-            "table" => {
-                let size = f.area();
-                draw_table_view::<B>(f, ip_data, errs, size);
-            }
-            "point" => {
-                let size = f.area();
-                draw_point_view::<B>(f, ip_data, errs, size);
-            }
-            "sparkline" => {
-                let size = f.area();
-                draw_sparkline_view::<B>(f, ip_data, errs, size);
-            }
-            _ => {
-                draw_graph_view::<B>(f, ip_data, errs);
-            }
-        }
+        let size = f.area();
+        draw_view(f, view_type, ip_data, errs, size);
     })?;
     Ok(())
 }
 
+/// draw the selected view into a part of the frame
+pub fn draw_view(f: &mut Frame, view_type: &str, ip_data: &[IpData], errs: &[String], area: Rect) {
+    match view_type {
+        "table" => draw_table_view(f, ip_data, errs, area),
+        "point" => draw_point_view(f, ip_data, errs, area),
+        "sparkline" => draw_sparkline_view(f, ip_data, errs, area),
+        "outage" => draw_outage_view(f, ip_data, errs, area),
+        _ => draw_graph_view(f, ip_data, errs, area),
+    }
+}
+
 /// draw ui interface with event loop
 pub fn draw_interface_with_updates<B: Backend>(
     terminal: &mut Terminal<B>,
     view_type: &Arc<String>,
     ip_data: &Arc<Mutex<Vec<IpData>>>,
     ping_update_rx: mpsc::Receiver<IpData>,
-    running: Arc<Mutex<bool>>,
+    shutdown: Shutdown,
     errs: Arc<Mutex<Vec<String>>>,
-    output_file: Option<String>,
+    mut output_file: Option<OutputFile>,
 ) -> Result<(), Box<dyn Error>> {
-    let mut output_file_handle = if let Some(ref output_path) = output_file {
-        match std::fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(output_path)
-        {
-            Ok(file) => Some(file),
-            Err(e) => {
-                let mut errs = errs.lock().unwrap();
-                errs.push(format!("Failed to create output file: {}", e));
-                None
-            }
-        }
-    } else {
-        None
-    };
-
     loop {
-        if !*running.lock().unwrap() {
+        if shutdown.is_cancelled() {
             break Ok(());
         }
 
@@ -103,11 +109,11 @@ pub fn draw_interface_with_updates<B: Backend>(
             if let Ok(Event::Key(key)) = event::read() {
                 match key.code {
                     KeyCode::Char('q') | KeyCode::Esc => {
-                        *running.lock().unwrap() = false;
+                        shutdown.cancel();
                         break Ok(());
                     },
                     KeyCode::Char('c') if key.modifiers == KeyModifiers::CONTROL => {
-                        *running.lock().unwrap() = false;
+                        shutdown.cancel();
                         break Ok(());
                     },
                     _ => {}
@@ -116,40 +122,20 @@ pub fn draw_interface_with_updates<B: Backend>(
         }
 
         if let Ok(updated_data) = ping_update_rx.recv_timeout(Duration::from_millis(50)) {
-            let mut ip_data = ip_data.lock().unwrap();
+            let mut ip_data = lock(ip_data);
 
-            let last_attr = updated_data.last_attr.clone();
-            let addr = updated_data.addr.clone();
-            let ip = updated_data.ip.clone();
+            let changes = apply_update(&mut ip_data, updated_data.clone());
 
-            if let Some(pos) = ip_data.iter().position(|d| d.addr == updated_data.addr && d.ip == updated_data.ip) {
-                ip_data[pos] = updated_data;
-            }
-
-            if let Some(ref mut file) = output_file_handle {
-                use std::io::Write;
-
-                let latency_str = if last_attr == -1.0 {
-                    "timeout".to_string()
-                } else {
-                    format!("{:.2}ms", last_attr)
-                };
-
-                if let Err(e) = writeln!(file, "{} {} {}",
-                                         addr,
-                                         ip,
-                                         latency_str
-                ) {
-                    let mut errs = errs.lock().unwrap();
-                    errs.push(format!("Failed to write to output file: {}", e));
-                }
+            if let Some(ref mut file) = output_file {
+                file.write_update(&updated_data, &changes);
+                file.write_stats(&ip_data);
             }
 
             draw_interface(
                 terminal,
                 view_type,
                 &ip_data,
-                &mut errs.lock().unwrap(),
+                &lock(&errs),
             ).ok();
         }
     }
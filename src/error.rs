@@ -0,0 +1,93 @@
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use thiserror::Error;
+
+/// Why a run failed, each kind exits with its own code so scripts can tell them apart
+#[derive(Debug, Error)]
+pub enum NpingError {
+    #[error("could not resolve {host}: {reason}, check the name and your DNS, or use -6 for hosts with only IPv6 addresses")]
+    Resolve { host: String, reason: String },
+
+    #[error("could not ping {ip}: {reason}, check that ping is installed and in PATH")]
+    Probe { ip: String, reason: String },
+
+    // output files, recordings and sinks
+    #[error("{0}")]
+    Output(String),
+
+    #[error("terminal error: {0}, use --no-tui when not running in a terminal")]
+    Terminal(String),
+
+    // --assert-loss, --assert-avg or --assert-p99 missed at the end of a run
+    #[error("{failed} of {targets} targets failed their assertions")]
+    Assertions { failed: usize, targets: usize },
+
+    // nping diff found a significant change for the worse
+    #[error("the second recording regressed")]
+    Regression,
+
+    // a task panicked or could not be joined
+    #[error("internal error: {0}, this is a bug, please report it with the output above")]
+    Internal(String),
+}
+
+impl NpingError {
+    /// config errors are not a `NpingError` and exit with 1, clap exits with 2 on bad options
+    /// before any of this runs
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            NpingError::Resolve { .. } => 3,
+            NpingError::Probe { .. } => 4,
+            NpingError::Output(_) => 5,
+            NpingError::Terminal(_) => 6,
+            NpingError::Assertions { .. } => 7,
+            NpingError::Regression => 8,
+            NpingError::Internal(_) => 101,
+        }
+    }
+}
+
+impl From<tokio::task::JoinError> for NpingError {
+    fn from(err: tokio::task::JoinError) -> Self {
+        NpingError::Internal(err.to_string())
+    }
+}
+
+// a panic elsewhere is reported by the panic hook, keep going with the data it left behind
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_kind_has_its_own_exit_code() {
+        let errors = [
+            NpingError::Resolve { host: "nope.invalid".into(), reason: "no address".into() },
+            NpingError::Probe { ip: "192.0.2.1".into(), reason: "not found".into() },
+            NpingError::Output("disk full".into()),
+            NpingError::Terminal("not a tty".into()),
+            NpingError::Assertions { failed: 1, targets: 2 },
+            NpingError::Regression,
+            NpingError::Internal("task panicked".into()),
+        ];
+        let codes: Vec<i32> = errors.iter().map(NpingError::exit_code).collect();
+        assert_eq!(codes, [3, 4, 5, 6, 7, 8, 101]);
+        assert!(errors[0].to_string().starts_with("could not resolve nope.invalid: no address"));
+    }
+
+    #[test]
+    fn lock_survives_a_panic_while_locked() {
+        let mutex = std::sync::Arc::new(Mutex::new(1));
+        let poisoner = mutex.clone();
+        let res = std::thread::spawn(move || {
+            let _guard = poisoner.lock().unwrap();
+            panic!("poisoned on purpose");
+        })
+        .join();
+        assert!(res.is_err() && mutex.is_poisoned());
+        *lock(&mutex) += 1;
+        assert_eq!(*lock(&mutex), 2);
+    }
+}
@@ -0,0 +1,210 @@
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex, mpsc};
+use std::time::{Duration, Instant};
+use chrono::Local;
+use crate::ip_data::IpData;
+use crate::csv_writer::CsvWriter;
+use crate::output::{apply_update, event_records, stats_record, write_lines, Changes, OutputFile, OutputFormat, StatsTimer};
+use crate::shutdown::Shutdown;
+use crate::stats::{calculate_avg_rtt, calculate_loss_pkg};
+use crate::ui::utils::format_duration;
+use crate::error::lock;
+
+pub struct HeadlessOptions {
+    pub(crate) format: OutputFormat,
+    // print a table every refresh instead of one line per result, text only
+    pub(crate) refresh: Duration,
+    // print stats records every stats_interval, jsonl only
+    pub(crate) stats_interval: Duration,
+    // print summary rows every summary_interval instead of a row per result, csv only
+    pub(crate) summary_interval: Duration,
+}
+
+/// print results to stdout instead of drawing the ui, for scripts, cron and non-tty sessions
+pub fn run_headless(
+    ip_data: &Arc<Mutex<Vec<IpData>>>,
+    ping_update_rx: mpsc::Receiver<IpData>,
+    shutdown: Shutdown,
+    errs: Arc<Mutex<Vec<String>>>,
+    mut output_file: Option<OutputFile>,
+    options: HeadlessOptions,
+) {
+    let jsonl = options.format == OutputFormat::Jsonl;
+    let csv = options.format == OutputFormat::Csv;
+    let mut csv_writer = CsvWriter::new(options.summary_interval);
+    let refresh = if options.format == OutputFormat::Text { options.refresh } else { Duration::ZERO };
+    let mut last_refresh = Instant::now();
+    let mut stats_timer = StatsTimer::new(if jsonl { options.stats_interval } else { Duration::ZERO });
+    let mut printed_errs = 0;
+
+    while !shutdown.is_cancelled() {
+        let mut res = Ok(());
+        let mut out = io::stdout().lock();
+
+        if let Ok(updated_data) = ping_update_rx.recv_timeout(Duration::from_millis(100)) {
+            let mut ip_data = lock(ip_data);
+            let changes = apply_update(&mut ip_data, updated_data.clone());
+
+            if let Some(ref mut file) = output_file {
+                file.write_update(&updated_data, &changes);
+            }
+            if csv {
+                res = csv_writer.write_update(&mut out, &updated_data);
+            } else if jsonl {
+                res = write_lines(&mut out, &event_records(&updated_data, &changes));
+            } else if refresh.is_zero() {
+                res = print_event(&mut out, &updated_data, &changes);
+            }
+        }
+
+        if let Some(ref mut file) = output_file {
+            file.write_stats(&lock(ip_data));
+        }
+        if csv {
+            res = res.and_then(|_| csv_writer.write_summary(&mut out, &lock(ip_data)));
+        }
+        if stats_timer.due() {
+            let records: Vec<_> = lock(ip_data).iter().map(stats_record).collect();
+            res = res.and_then(|_| write_lines(&mut out, &records));
+        }
+
+        if !refresh.is_zero() && last_refresh.elapsed() >= refresh {
+            last_refresh = Instant::now();
+            res = res.and_then(|_| print_table(&mut out, &lock(ip_data)));
+        }
+
+        // errors go to stderr so they do not end up in piped results
+        let errs = lock(&errs);
+        for err in errs.iter().skip(printed_errs) {
+            eprintln!("{}", err);
+        }
+        printed_errs = errs.len();
+
+        // stdout was closed, e.g. piped into head
+        if res.and_then(|_| out.flush()).is_err() {
+            shutdown.cancel();
+        }
+    }
+}
+
+// one ping like line per reply or timeout, plus outages and health changes
+fn print_event(out: &mut impl Write, data: &IpData, changes: &Changes) -> io::Result<()> {
+    let time = data.last_timestamp.unwrap_or_else(Local::now).format("%Y-%m-%d %H:%M:%S");
+    if data.last_attr < 0.0 {
+        writeln!(out, "{} {} ({}): seq={} timeout", time, data.addr, data.ip, data.last_seq)?;
+    } else {
+        writeln!(out, "{} {} ({}): seq={} time={:.2} ms", time, data.addr, data.ip, data.last_seq, data.last_attr)?;
+    }
+
+    if let Some(ref outage) = changes.outage {
+        writeln!(
+            out,
+            "{} {} ({}): outage ended after {}, {} lost",
+            time,
+            data.addr,
+            data.ip,
+            format_duration(outage.duration(Local::now())),
+            outage.lost
+        )?;
+    }
+    if let Some(ref event) = changes.transition {
+        writeln!(out, "{} {} ({}): state {} -> {} ({})", time, data.addr, data.ip, event.from, event.to, event.reason)?;
+    }
+    Ok(())
+}
+
+// compact table of all targets
+fn print_table(out: &mut impl Write, ip_data: &[IpData]) -> io::Result<()> {
+    writeln!(
+        out,
+        "\n{} {:<24} {:<16} {:>6} {:>8} {:>10} {:>10} {:>10} {:>10} {:>6} {:>8}",
+        Local::now().format("%H:%M:%S"),
+        "TARGET", "IP", "SENT", "LOSS", "LAST", "AVG", "MIN", "MAX", "STATE", "OUTAGES"
+    )?;
+    for data in ip_data {
+        let last = if data.last_attr < 0.0 { "timeout".to_string() } else { format!("{:.2}ms", data.last_attr) };
+        writeln!(
+            out,
+            "         {:<24} {:<16} {:>6} {:>7.2}% {:>10} {:>8.2}ms {:>8.2}ms {:>8.2}ms {:>6} {:>8}",
+            data.addr,
+            data.ip,
+            data.received + data.timeout,
+            calculate_loss_pkg(data.timeout, data.received),
+            last,
+            calculate_avg_rtt(&data.rtts),
+            data.min_rtt,
+            data.max_rtt,
+            data.health,
+            data.outage.count,
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, TimeZone};
+    use crate::health::{HealthState, HealthTransition};
+    use crate::outage::Outage;
+
+    fn at(secs: i64) -> DateTime<Local> {
+        Local.timestamp_opt(1_700_000_000 + secs, 0).unwrap()
+    }
+
+    fn result(seq: usize, rtt: f64) -> IpData {
+        let mut data = IpData::new("example.com".to_string(), "192.0.2.1".to_string());
+        data.last_seq = seq;
+        data.last_attr = rtt;
+        data.last_timestamp = Some(at(0));
+        data
+    }
+
+    fn printed(data: &IpData, changes: &Changes) -> Vec<String> {
+        let mut out = Vec::new();
+        print_event(&mut out, data, changes).unwrap();
+        String::from_utf8(out).unwrap().lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn one_line_per_reply_or_timeout() {
+        let time = at(0).format("%Y-%m-%d %H:%M:%S");
+        assert_eq!(
+            printed(&result(1, 12.345), &Changes::default()),
+            [format!("{} example.com (192.0.2.1): seq=1 time=12.35 ms", time)]
+        );
+        assert_eq!(
+            printed(&result(2, -1.0), &Changes::default()),
+            [format!("{} example.com (192.0.2.1): seq=2 timeout", time)]
+        );
+    }
+
+    #[test]
+    fn outages_and_state_changes_get_a_line_of_their_own() {
+        let changes = Changes {
+            outage: Some(Outage { start: at(-10), end: Some(at(0)), lost: 5 }),
+            transition: Some(HealthTransition {
+                addr: "example.com".to_string(),
+                ip: "192.0.2.1".to_string(),
+                from: HealthState::Crit,
+                to: HealthState::Ok,
+                at: at(0),
+                reason: "recovered".to_string(),
+            }),
+        };
+        let lines = printed(&result(3, 10.0), &changes);
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].ends_with("example.com (192.0.2.1): outage ended after 10.0s, 5 lost"), "{}", lines[1]);
+        assert!(lines[2].ends_with("example.com (192.0.2.1): state CRIT -> OK (recovered)"), "{}", lines[2]);
+    }
+
+    #[test]
+    fn table_has_a_row_per_target() {
+        let mut out = Vec::new();
+        print_table(&mut out, &[result(1, 10.0), result(2, -1.0)]).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let rows: Vec<&str> = text.lines().filter(|l| l.contains("example.com")).collect();
+        assert_eq!(rows.len(), 2);
+        assert!(rows[1].contains("timeout"));
+    }
+}
@@ -0,0 +1,396 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+use chrono::{DateTime, Local};
+use crate::ip_data::IpData;
+use crate::stats::{calculate_avg_rtt, calculate_jitter, calculate_loss_pkg, calculate_percentile};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum HealthState {
+    #[default]
+    Ok,
+    Warn,
+    Crit,
+}
+
+impl HealthState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HealthState::Ok => "OK",
+            HealthState::Warn => "WARN",
+            HealthState::Crit => "CRIT",
+        }
+    }
+}
+
+impl fmt::Display for HealthState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Metric {
+    // packet loss of the recent records, in percent
+    Loss,
+    // average rtt of the recent records, in ms
+    Avg,
+    // percentile rtt of the recent records, in ms
+    Percentile(f64),
+    // jitter of the recent records, in ms
+    Jitter,
+    // duration of the outage in progress, in seconds
+    Outage,
+}
+
+impl Metric {
+    fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "loss" => Ok(Metric::Loss),
+            "avg" => Ok(Metric::Avg),
+            "jitter" => Ok(Metric::Jitter),
+            "outage" => Ok(Metric::Outage),
+            p if p.starts_with('p') => p[1..]
+                .parse::<f64>()
+                .ok()
+                .filter(|v| *v > 0.0 && *v <= 100.0)
+                .map(Metric::Percentile)
+                .ok_or_else(|| format!("invalid percentile metric: {}", s)),
+            _ => Err(format!("unknown metric: {}, expected loss/avg/pNN/jitter/outage", s)),
+        }
+    }
+
+    /// current value of the metric for a target
+    pub fn value(&self, data: &IpData, now: DateTime<Local>) -> f64 {
+        match self {
+            Metric::Loss => {
+                // timeouts are kept as -1 in the rtts
+                let lost = data.rtts.iter().filter(|&&rtt| rtt < 0.0).count();
+                calculate_loss_pkg(lost, data.rtts.len() - lost)
+            }
+            Metric::Avg => calculate_avg_rtt(&data.rtts),
+            Metric::Percentile(p) => calculate_percentile(&data.rtts, *p),
+            Metric::Jitter => calculate_jitter(&data.rtts),
+            Metric::Outage => data.outage.current_duration(now).map(|d| d.as_secs_f64()).unwrap_or(0.0),
+        }
+    }
+
+    fn format_value(&self, value: f64) -> String {
+        match self {
+            Metric::Loss => format!("{:.2}%", value),
+            Metric::Outage => format!("{:.1}s", value),
+            _ => format!("{:.2}ms", value),
+        }
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Metric::Loss => f.write_str("loss"),
+            Metric::Avg => f.write_str("avg"),
+            Metric::Percentile(p) => write!(f, "p{}", p),
+            Metric::Jitter => f.write_str("jitter"),
+            Metric::Outage => f.write_str("outage"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Threshold {
+    pub(crate) metric: Metric,
+    pub(crate) warn: Option<f64>,
+    pub(crate) crit: Option<f64>,
+}
+
+/// A threshold given on the command line, optionally bound to one target
+#[derive(Debug, Clone)]
+pub struct ThresholdRule {
+    pub(crate) target: Option<String>,
+    pub(crate) threshold: Threshold,
+}
+
+/// parse `[TARGET:]METRIC=WARN/CRIT`, either bound can be left empty
+pub fn parse_threshold_rule(s: &str) -> Result<ThresholdRule, String> {
+    let (key, bounds) = s
+        .split_once('=')
+        .ok_or_else(|| format!("invalid threshold: {}, expected [TARGET:]METRIC=WARN/CRIT", s))?;
+    let (target, metric) = match key.rsplit_once(':') {
+        Some((target, metric)) => (Some(target.to_string()), metric),
+        None => (None, key),
+    };
+    let metric = Metric::parse(metric)?;
+
+    let (warn, crit) = bounds.split_once('/').unwrap_or((bounds, ""));
+    let parse_bound = |v: &str| -> Result<Option<f64>, String> {
+        if v.is_empty() {
+            Ok(None)
+        } else {
+            v.parse::<f64>().map(Some).map_err(|_| format!("invalid threshold value: {}", v))
+        }
+    };
+
+    Ok(ThresholdRule {
+        target,
+        threshold: Threshold {
+            metric,
+            warn: parse_bound(warn)?,
+            crit: parse_bound(crit)?,
+        },
+    })
+}
+
+#[derive(Debug, Clone)]
+pub struct HealthRules {
+    global: Vec<Threshold>,
+    per_target: HashMap<String, Vec<Threshold>>,
+    // fraction of a threshold a metric has to fall below to leave a state
+    hysteresis: f64,
+    // how long a new state has to hold before it is entered
+    min_duration: Duration,
+}
+
+impl HealthRules {
+    pub fn new(rules: &[ThresholdRule], hysteresis_pct: f64, min_duration: Duration) -> Self {
+        // same colours as before thresholds were configurable: any loss warns, over 50% is critical
+        let mut global = vec![Threshold { metric: Metric::Loss, warn: Some(0.0), crit: Some(50.0) }];
+        let mut per_target: HashMap<String, Vec<Threshold>> = HashMap::new();
+
+        for rule in rules {
+            let thresholds = match rule.target {
+                Some(ref target) => per_target.entry(target.clone()).or_default(),
+                None => &mut global,
+            };
+            thresholds.retain(|t| t.metric != rule.threshold.metric);
+            thresholds.push(rule.threshold);
+        }
+
+        Self {
+            global,
+            per_target,
+            hysteresis: (hysteresis_pct / 100.0).clamp(0.0, 1.0),
+            min_duration,
+        }
+    }
+
+    /// thresholds of a target, per target thresholds override global ones for the same metric
+    pub fn thresholds_for(&self, addr: &str, ip: &str) -> Vec<Threshold> {
+        let mut thresholds = self.global.clone();
+        for key in [addr, ip] {
+            if let Some(overrides) = self.per_target.get(key) {
+                for t in overrides {
+                    thresholds.retain(|g| g.metric != t.metric);
+                    thresholds.push(*t);
+                }
+            }
+        }
+        thresholds
+    }
+}
+
+/// A timestamped change of the health state of a target
+#[derive(Debug, Clone)]
+pub struct HealthTransition {
+    pub(crate) addr: String,
+    pub(crate) ip: String,
+    pub(crate) from: HealthState,
+    pub(crate) to: HealthState,
+    pub(crate) at: DateTime<Local>,
+    pub(crate) reason: String,
+}
+
+/// OK/WARN/CRIT state machine of one target
+#[derive(Debug)]
+pub struct HealthMachine {
+    thresholds: Vec<Threshold>,
+    hysteresis: f64,
+    min_duration: Duration,
+    state: HealthState,
+    // candidate state and since when its conditions hold
+    pending: Option<(HealthState, DateTime<Local>)>,
+}
+
+impl HealthMachine {
+    pub fn new(rules: &HealthRules, addr: &str, ip: &str) -> Self {
+        Self {
+            thresholds: rules.thresholds_for(addr, ip),
+            hysteresis: rules.hysteresis,
+            min_duration: rules.min_duration,
+            state: HealthState::Ok,
+            pending: None,
+        }
+    }
+
+    pub fn state(&self) -> HealthState {
+        self.state
+    }
+
+    /// use new thresholds from now on, the current state is kept
+    pub fn set_rules(&mut self, rules: &HealthRules, addr: &str, ip: &str) {
+        self.thresholds = rules.thresholds_for(addr, ip);
+        self.hysteresis = rules.hysteresis;
+        self.min_duration = rules.min_duration;
+        self.pending = None;
+    }
+
+    /// evaluate the thresholds against the latest data
+    /// returns the transition if the state changed
+    pub fn evaluate(&mut self, data: &IpData, now: DateTime<Local>) -> Option<HealthTransition> {
+        let mut target = HealthState::Ok;
+        let mut reasons = Vec::new();
+
+        for threshold in &self.thresholds {
+            let value = threshold.metric.value(data, now);
+            let level = self.level(threshold, value);
+            if level > HealthState::Ok {
+                let bound = if level == HealthState::Crit { threshold.crit } else { threshold.warn };
+                reasons.push(format!(
+                    "{} {} > {}",
+                    threshold.metric,
+                    threshold.metric.format_value(value),
+                    bound.unwrap_or_default()
+                ));
+            }
+            target = target.max(level);
+        }
+
+        if target == self.state {
+            self.pending = None;
+            return None;
+        }
+
+        // the new state has to hold for min_duration before we switch
+        let since = match self.pending {
+            Some((state, since)) if state == target => since,
+            _ => {
+                self.pending = Some((target, now));
+                now
+            }
+        };
+        if (now - since).to_std().unwrap_or_default() < self.min_duration {
+            return None;
+        }
+
+        let transition = HealthTransition {
+            addr: data.addr.clone(),
+            ip: data.ip.clone(),
+            from: self.state,
+            to: target,
+            at: now,
+            reason: if reasons.is_empty() { "recovered".to_string() } else { reasons.join(", ") },
+        };
+        self.state = target;
+        self.pending = None;
+        Some(transition)
+    }
+
+    // level of one metric, a state already entered is only left once the
+    // value falls below the threshold reduced by the hysteresis
+    fn level(&self, threshold: &Threshold, value: f64) -> HealthState {
+        let exceeds = |bound: Option<f64>, state: HealthState| match bound {
+            Some(bound) if self.state >= state => value > bound * (1.0 - self.hysteresis),
+            Some(bound) => value > bound,
+            None => false,
+        };
+
+        if exceeds(threshold.crit, HealthState::Crit) {
+            HealthState::Crit
+        } else if exceeds(threshold.warn, HealthState::Warn) {
+            HealthState::Warn
+        } else {
+            HealthState::Ok
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(secs: i64) -> DateTime<Local> {
+        Local.timestamp_opt(1_700_000_000 + secs, 0).unwrap()
+    }
+
+    fn data(rtts: &[f64]) -> IpData {
+        let mut data = IpData::new("example.com".to_string(), "192.0.2.1".to_string());
+        data.rtts = rtts.iter().copied().collect();
+        data.received = rtts.iter().filter(|&&rtt| rtt >= 0.0).count();
+        data.timeout = rtts.len() - data.received;
+        data
+    }
+
+    fn rules(thresholds: &[&str], hysteresis: f64, min_duration: u64) -> HealthRules {
+        let rules: Vec<ThresholdRule> = thresholds.iter().map(|t| parse_threshold_rule(t).unwrap()).collect();
+        HealthRules::new(&rules, hysteresis, Duration::from_secs(min_duration))
+    }
+
+    #[test]
+    fn parse_threshold_rules() {
+        let rule = parse_threshold_rule("avg=100/200").unwrap();
+        assert_eq!(rule.target, None);
+        assert_eq!(rule.threshold, Threshold { metric: Metric::Avg, warn: Some(100.0), crit: Some(200.0) });
+
+        let rule = parse_threshold_rule("8.8.8.8:p95=/300").unwrap();
+        assert_eq!(rule.target.as_deref(), Some("8.8.8.8"));
+        assert_eq!(rule.threshold, Threshold { metric: Metric::Percentile(95.0), warn: None, crit: Some(300.0) });
+
+        assert_eq!(parse_threshold_rule("jitter=5").unwrap().threshold.warn, Some(5.0));
+        assert!(parse_threshold_rule("avg").is_err());
+        assert!(parse_threshold_rule("rtt=1/2").is_err());
+        assert!(parse_threshold_rule("p0=1/2").is_err());
+        assert!(parse_threshold_rule("avg=fast/2").is_err());
+    }
+
+    #[test]
+    fn per_target_thresholds_override_global_ones() {
+        let rules = rules(&["avg=100/200", "192.0.2.1:avg=10/20"], 0.0, 0);
+        let avg = |addr, ip| rules.thresholds_for(addr, ip).into_iter().find(|t| t.metric == Metric::Avg).unwrap();
+        assert_eq!(avg("example.com", "192.0.2.1").warn, Some(10.0));
+        assert_eq!(avg("example.org", "192.0.2.2").warn, Some(100.0));
+    }
+
+    #[test]
+    fn hysteresis_delays_recovery() {
+        let rules = rules(&["loss=/", "avg=100/200"], 10.0, 0);
+        let mut machine = HealthMachine::new(&rules, "example.com", "192.0.2.1");
+
+        let transition = machine.evaluate(&data(&[150.0]), at(0)).unwrap();
+        assert_eq!((transition.from, transition.to), (HealthState::Ok, HealthState::Warn));
+        // 95 is below the threshold but not below 100 - 10%
+        assert!(machine.evaluate(&data(&[95.0]), at(1)).is_none());
+        let transition = machine.evaluate(&data(&[85.0]), at(2)).unwrap();
+        assert_eq!(transition.to, HealthState::Ok);
+        assert_eq!(transition.reason, "recovered");
+    }
+
+    #[test]
+    fn new_state_has_to_hold_for_min_duration() {
+        let rules = rules(&["loss=/", "avg=100/200"], 0.0, 5);
+        let mut machine = HealthMachine::new(&rules, "example.com", "192.0.2.1");
+        assert!(machine.evaluate(&data(&[250.0]), at(0)).is_none());
+        assert!(machine.evaluate(&data(&[250.0]), at(3)).is_none());
+        assert_eq!(machine.evaluate(&data(&[250.0]), at(5)).unwrap().to, HealthState::Crit);
+        assert_eq!(machine.state(), HealthState::Crit);
+    }
+
+    #[test]
+    fn timeouts_do_not_count_as_jitter() {
+        let rules = rules(&["loss=/", "jitter=5/10"], 0.0, 0);
+        let mut machine = HealthMachine::new(&rules, "example.com", "192.0.2.1");
+        assert_eq!(Metric::Jitter.value(&data(&[20.0, -1.0, 21.0, -1.0, 20.0]), at(0)), 1.0);
+        assert!(machine.evaluate(&data(&[20.0, -1.0, 21.0, -1.0, 20.0]), at(0)).is_none());
+    }
+
+    #[test]
+    fn loss_recovers_once_the_timeouts_leave_the_window() {
+        let mut machine = HealthMachine::new(&rules(&[], 0.0, 0), "example.com", "192.0.2.1");
+        assert_eq!(machine.evaluate(&data(&[20.0, -1.0, 21.0]), at(0)).unwrap().to, HealthState::Warn);
+
+        // the timeout still counts since start, but no longer in the recent records
+        let mut clean = data(&[21.0, 20.0, 22.0]);
+        clean.timeout = 1;
+        assert_eq!(Metric::Loss.value(&clean, at(1)), 0.0);
+        assert_eq!(machine.evaluate(&clean, at(1)).unwrap().to, HealthState::Ok);
+    }
+}
@@ -1,4 +1,9 @@
 use std::collections::VecDeque;
+use chrono::{DateTime, Local};
+use crate::outage::OutageStats;
+use crate::health::{HealthState, HealthTransition};
+use crate::baseline::BaselineStats;
+use crate::rolling::RollingStats;
 
 #[derive(Debug, Clone)]
 pub struct IpData {
@@ -10,5 +15,46 @@ pub struct IpData {
     pub(crate) max_rtt: f64,
     pub(crate) timeout: usize,
     pub(crate) received: usize,
+    // failures of ping itself
+    pub(crate) errors: usize,
     pub(crate) pop_count: usize,
+    // sequence number and time of the latest reply or timeout
+    pub(crate) last_seq: usize,
+    pub(crate) last_timestamp: Option<DateTime<Local>>,
+    // sum of all rtts since start, for the overall average
+    pub(crate) rtt_sum: f64,
+    pub(crate) outage: OutageStats,
+    pub(crate) health: HealthState,
+    pub(crate) health_events: VecDeque<HealthTransition>,
+    // one flag per entry of rtts, true when the rtt is far above the baseline
+    pub(crate) anomalies: VecDeque<bool>,
+    pub(crate) baseline: BaselineStats,
+    pub(crate) rolling: RollingStats,
+}
+
+impl IpData {
+    /// a target without any results yet
+    pub fn new(addr: String, ip: String) -> Self {
+        Self {
+            addr,
+            ip,
+            rtts: VecDeque::new(),
+            last_attr: 0.0,
+            min_rtt: 0.0,
+            max_rtt: 0.0,
+            timeout: 0,
+            received: 0,
+            errors: 0,
+            pop_count: 0,
+            last_seq: 0,
+            last_timestamp: None,
+            rtt_sum: 0.0,
+            outage: OutageStats::default(),
+            health: HealthState::Ok,
+            health_events: VecDeque::new(),
+            anomalies: VecDeque::new(),
+            baseline: BaselineStats::default(),
+            rolling: RollingStats::default(),
+        }
+    }
 }
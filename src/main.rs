@@ -5,24 +5,69 @@ mod ip_data;
 mod ui;
 mod ping_event;
 mod data_processor;
-
-use clap::Parser;
-use std::collections::{HashSet, VecDeque};
+mod outage;
+mod stats;
+mod health;
+mod sinks;
+mod baseline;
+mod rolling;
+mod output;
+mod headless;
+mod summary;
+mod csv_writer;
+mod assertions;
+mod check;
+mod record;
+mod recording;
+mod replay;
+mod report;
+mod diff;
+mod config;
+mod reload;
+mod shutdown;
+mod error;
+
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+use ratatui::style::Color;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::io::Write;
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use tokio::{task, runtime::Builder};
 use crate::ip_data::IpData;
 use crate::ping_event::PingEvent;
-use crate::data_processor::start_data_processor;
+use crate::data_processor::{start_data_processor, DataProcessor, ProcessorConfig, TargetUpdate};
 use std::sync::mpsc;
-use crate::network::send_ping;
+use crate::health::{parse_threshold_rule, HealthRules, ThresholdRule};
+use crate::sinks::{parse_mqtt_url, parse_tag, AlertSink, InfluxSink, MqttBroker, MqttSink, OtlpSink, PrometheusSink, SinkHub, StatsdSink, TargetGroups};
+use crate::output::{expand_path, parse_path_template, parse_size, OpenMode, OutputFile, OutputFormat, OutputOptions};
+use crate::headless::HeadlessOptions;
+use crate::assertions::{check_assertions, parse_assert_avg, parse_assert_loss, parse_assert_p99, Assertion};
+use crate::outage::MAX_OUTAGE_HISTORY;
+use crate::summary::TargetSummary;
+use crate::record::RecordSink;
+use crate::ui::utils::{parse_color, set_theme};
+use crate::reload::{interval_ms, start_reloader, PingTasks, ReloadSettings};
+use crate::shutdown::{Shutdown, HANGUP_EXIT_CODE};
+use crate::error::{lock, NpingError};
+use crate::config::TargetSettings;
+
+// how long the ui gets to stop once its terminal was closed
+const HANGUP_UI_WAIT: std::time::Duration = std::time::Duration::from_secs(1);
 
 #[derive(Parser, Debug)]
 #[command(
     version = "v0.5.0",
     author = "hanshuaikang<https://github.com/hanshuaikang>",
-    about = "🏎  Nping mean NB Ping, A Ping Tool in Rust with Real-Time Data and Visualizations"
+    about = "🏎  Nping mean NB Ping, A Ping Tool in Rust with Real-Time Data and Visualizations",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
 )]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
     /// Target IP address or hostname to ping
     #[arg(help = "target IP address or hostname to ping", required = true)]
     target: Vec<String>,
@@ -46,36 +91,253 @@ struct Args {
     )]
     multiple: i32,
 
-    #[arg(short, long, default_value = "graph", help = "View mode graph/table/point/sparkline")]
+    #[arg(short, long, default_value = "graph", help = "View mode graph/table/point/sparkline/outage")]
     view_type: String,
 
-    #[arg(short = 'o', long = "output", help = "Output file to save ping results")]
+    #[arg(long, value_name = "FILE", help = "Config file with default options, targets and profiles, defaults to ~/.config/nping/config.toml")]
+    config: Option<String>,
+
+    #[arg(long, help = "Apply a profile of the config file, e.g. --profile office")]
+    profile: Option<String>,
+
+    #[arg(long = "color", value_parser = parse_color, help = "Ui colour NAME=COLOR, NAME is ok/warn/crit/anomaly/title, COLOR a name like light-red, an index or #rrggbb, can be repeated")]
+    colors: Vec<(String, Color)>,
+
+    // display names of targets from the config file, key: host
+    #[arg(skip)]
+    names: HashMap<String, String>,
+    // interval, count, ipv6 and group of single targets from the config file, key: host
+    #[arg(skip)]
+    settings: HashMap<String, TargetSettings>,
+
+    // the command line and config file of the run, read again on a reload
+    #[arg(skip)]
+    command_line: Vec<OsString>,
+    #[arg(skip)]
+    config_file: Option<std::path::PathBuf>,
+
+    #[arg(short = 'o', long = "output", value_parser = parse_path_template, help = "Output file to save ping results, strftime fields like nping-%Y%m%d-%H.log start a new file each period")]
     output: Option<String>,
+
+    #[arg(long, default_value_t = false, conflicts_with = "overwrite", help = "Append to an existing output file")]
+    append: bool,
+
+    #[arg(long, default_value_t = false, help = "Overwrite an existing output file")]
+    overwrite: bool,
+
+    #[arg(long, value_parser = parse_size, help = "Start a new output file once it reaches this size, e.g. 10M, the full one is kept as FILE.1, FILE.2, ...")]
+    rotate_size: Option<u64>,
+
+    #[arg(long, default_value_t = 3, help = "Number of consecutive timeouts counted as an outage")]
+    outage_threshold: usize,
+
+    #[arg(
+        long = "threshold",
+        value_parser = parse_threshold_rule,
+        help = "Health threshold [TARGET:]METRIC=WARN/CRIT, METRIC is loss/avg/p95/jitter/outage, can be repeated"
+    )]
+    thresholds: Vec<ThresholdRule>,
+
+    #[arg(long, default_value_t = 10.0, help = "Percent a metric has to fall below a threshold to leave a health state")]
+    hysteresis: f64,
+
+    #[arg(long, default_value_t = 0, help = "Seconds a new health state has to hold before it is entered")]
+    state_min_duration: u64,
+
+    #[arg(long, help = "Command run on health state changes, details are passed in NPING_* environment variables")]
+    on_alert: Option<String>,
+
+    #[arg(long, help = "URL receiving a JSON POST on health state changes")]
+    webhook: Option<String>,
+
+    #[arg(long, default_value_t = 60, help = "Minimum seconds between two alerts of the same target, recoveries are always sent")]
+    alert_interval: u64,
+
+    #[arg(long, help = "Serve Prometheus metrics on http://ADDR/metrics, e.g. 127.0.0.1:9898")]
+    prometheus_listen: Option<String>,
+
+    #[arg(long, default_value = "default", help = "Group label attached to exported metrics, to tell several nping instances apart, targets in a config file group get that one")]
+    group: String,
+
+    #[arg(long, help = "Push results as InfluxDB line protocol to this write URL, e.g. http://localhost:8086/api/v2/write?org=o&bucket=b or udp://localhost:8089")]
+    influx_url: Option<String>,
+
+    #[arg(long, help = "API token sent with InfluxDB HTTP writes")]
+    influx_token: Option<String>,
+
+    #[arg(long, help = "Push results as StatsD metrics to HOST:PORT over UDP")]
+    statsd: Option<String>,
+
+    #[arg(long, default_value = "nping", help = "Prefix of the StatsD metric names")]
+    statsd_prefix: String,
+
+    #[arg(long = "metrics-tag", value_parser = parse_tag, help = "KEY=VALUE tag added to pushed metrics, StatsD uses the DogStatsD tag format, can be repeated")]
+    metrics_tags: Vec<(String, String)>,
+
+    #[arg(long, help = "Export metrics to an OpenTelemetry collector over OTLP/HTTP JSON, e.g. http://localhost:4318")]
+    otlp_endpoint: Option<String>,
+
+    #[arg(long, value_parser = parse_mqtt_url, help = "Publish status, stats and health of the targets to mqtt://[user[:password]@]host[:port]")]
+    mqtt: Option<MqttBroker>,
+
+    #[arg(long, help = "Base MQTT topic, defaults to nping/<hostname>")]
+    mqtt_topic: Option<String>,
+
+    #[arg(long, num_args = 0..=1, default_missing_value = "/dev/log", value_name = "SOCKET", help = "Log health changes, outages, DNS changes and ping errors to syslog (RFC 5424), optionally over another socket than /dev/log")]
+    syslog: Option<String>,
+
+    #[arg(long, default_value_t = false, help = "Log health changes, outages, DNS changes and ping errors to the systemd journal with structured fields")]
+    journald: bool,
+
+    #[arg(long, default_value_t = 10, help = "Seconds between two pushes of InfluxDB, StatsD and OTLP metrics and MQTT stats, failed pushes are retried")]
+    metrics_flush_interval: u64,
+
+    #[arg(long, default_value_t = false, help = "Push one aggregate per target and flush interval instead of every result")]
+    metrics_aggregate: bool,
+
+    #[arg(long, default_value_t = false, help = "Print results to stdout instead of drawing the ui, for scripts and non-tty sessions")]
+    no_tui: bool,
+
+    #[arg(long, default_value_t = 0, help = "With --no-tui, print a table of all targets every N seconds instead of one line per reply")]
+    refresh: u64,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text, help = "Format of the results on stdout with --no-tui and in the output file")]
+    format: OutputFormat,
+
+    #[arg(long, default_value_t = 10, help = "With --format jsonl, seconds between stats snapshot records, 0 disables them")]
+    stats_interval: u64,
+
+    #[arg(long, default_value_t = 0, help = "With --format csv, write one summary row per target every N seconds instead of one row per packet")]
+    summary_interval: u64,
+
+    #[arg(long, help = "Write the exit summary as JSON to this file")]
+    summary_file: Option<String>,
+
+    #[arg(long, value_name = "FILE", help = "On SIGUSR1, write the statistics so far as JSON to this file instead of stderr")]
+    dump_file: Option<String>,
+
+    #[arg(long, value_name = "SECS", default_value_t = 0, help = "Resolve hostnames again every SECS seconds and follow address changes, 0 only on a reload")]
+    resolve_interval: u64,
+
+    #[arg(long, value_name = "FILE", help = "Record every result, health change and session metadata to a SQLite database")]
+    record: Option<String>,
+
+    #[arg(long, value_parser = parse_assert_loss, help = "Exit with code 7 if the packet loss of a target exceeds [TARGET=]PERCENT at the end of the run, can be repeated")]
+    assert_loss: Vec<Assertion>,
+
+    #[arg(long, value_parser = parse_assert_avg, help = "Exit with code 7 if the average rtt of a target exceeds [TARGET=]MS at the end of the run, can be repeated")]
+    assert_avg: Vec<Assertion>,
+
+    #[arg(long, value_parser = parse_assert_p99, help = "Exit with code 7 if the p99 rtt of a target exceeds [TARGET=]MS at the end of the run, can be repeated")]
+    assert_p99: Vec<Assertion>,
+
+    #[arg(long, default_value_t = 3.0, help = "Standard deviations above the learned baseline that flag an rtt as anomalous, 0 disables")]
+    anomaly_sensitivity: f64,
+
+    #[arg(long, default_value_t = 20, help = "Replies used to learn the baseline before anomalies are flagged")]
+    baseline_warmup: usize,
 }
 
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Ping the targets a few times and print a Nagios/Icinga plugin status line, exits 0-3
+    Check(check::CheckArgs),
+    /// Play a JSONL/CSV output file or a SQLite recording back in the ui
+    Replay(replay::ReplayArgs),
+    /// Write a self-contained HTML report with charts of a JSONL/CSV output file or a SQLite recording
+    Report(report::ReportArgs),
+    /// Compare two recordings per target and flag significant regressions, exits 8 on a regression
+    Diff(diff::DiffArgs),
+    /// Inspect the config file
+    Config(config::ConfigArgs),
+}
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // parse command line arguments
-    let args = Args::parse();
+fn main() {
+    // parse command line arguments, the targets may also come from the config file
+    let argv: Vec<OsString> = std::env::args_os().collect();
+    let cli = parse_args(Args::command().mut_arg("target", |arg| arg.required(false)), &argv);
+    let (argv, config) = if cli.subcommand().is_none() {
+        config::apply_config(&Args::command(), &cli, &argv).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        })
+    } else {
+        (argv, None)
+    };
+    let mut args = Args::from_arg_matches(&parse_args(Args::command(), &argv)).unwrap_or_else(|err| err.exit());
+    args.target = config::expand_targets(&args.target, config.as_ref()).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+    args.names = config.as_ref().map(|config| config.names()).unwrap_or_default();
+    args.settings = config.as_ref().map(|config| config.settings()).unwrap_or_default();
+    args.config_file = config.map(|config| config.path().to_path_buf());
+    args.command_line = std::env::args_os().collect();
+    set_theme(&args.colors);
+
+    match args.command {
+        Some(Commands::Check(check_args)) => std::process::exit(check::run_check(check_args)),
+        Some(Commands::Replay(replay_args)) => {
+            if let Err(err) = replay::run_replay(replay_args) {
+                eprintln!("{}", err);
+                std::process::exit(err.exit_code());
+            }
+            return;
+        }
+        Some(Commands::Report(report_args)) => {
+            match report::run_report(report_args) {
+                Ok(path) => println!("report written to {}", path),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    std::process::exit(err.exit_code());
+                }
+            }
+            return;
+        }
+        Some(Commands::Config(config_args)) => {
+            if let Err(err) = config::run_config(config_args, Args::command()) {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Commands::Diff(diff_args)) => {
+            match diff::run_diff(diff_args) {
+                Ok(true) => std::process::exit(NpingError::Regression.exit_code()),
+                Ok(false) => {}
+                Err(err) => {
+                    eprintln!("{}", err);
+                    std::process::exit(err.exit_code());
+                }
+            }
+            return;
+        }
+        None => {}
+    }
 
-    // set Ctrl+C and q and esc to exit
-    let running = Arc::new(Mutex::new(true));
+    // stopped by q, esc, Ctrl+C, SIGTERM or SIGQUIT, or once all pings are done
+    let shutdown = Shutdown::new();
 
     // check output file
     if let Some(ref output_path) = args.output {
-        if std::path::Path::new(output_path).exists() {
-            eprintln!("Output file already exists: {}", output_path);
-            std::process::exit(1);
+        let output_path = match expand_path(output_path, chrono::Local::now()) {
+            Ok(path) => path,
+            Err(e) => {
+                let err = NpingError::Output(e);
+                eprintln!("{}", err);
+                std::process::exit(err.exit_code());
+            }
+        };
+        if !args.append && !args.overwrite && std::path::Path::new(&output_path).exists() {
+            let err = NpingError::Output(format!("output file already exists: {}, use --append or --overwrite", output_path));
+            eprintln!("{}", err);
+            std::process::exit(err.exit_code());
         }
     }
 
 
 
-    // after de-duplication, the original order is still preserved
-    let mut seen = HashSet::new();
-    let targets: Vec<String> = args.target.into_iter()
-        .filter(|item| seen.insert(item.clone()))
-        .collect();
+    let targets = unique_targets(&args.target);
 
     // Calculate worker threads based on IP count
     let ip_count = if targets.len() == 1 && args.multiple > 0 {
@@ -83,42 +345,87 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     } else {
         targets.len()
     };
-    let worker_threads = (ip_count +  1).max(1);
+    // ping tasks and the ui block their worker, keep one more free for the signal handlers
+    let worker_threads = ip_count + 2;
 
     // Create tokio runtime with specific worker thread count
     let rt = Builder::new_multi_thread()
         .worker_threads(worker_threads)
         .enable_all()
-        .build()?;
+        .build()
+        .unwrap_or_else(|e| {
+            let err = NpingError::Internal(format!("failed to start the runtime: {}", e));
+            eprintln!("{}", err);
+            std::process::exit(err.exit_code());
+        });
 
-    let res = rt.block_on(run_app(targets, args.count, args.interval, running.clone(), args.force_ipv6, args.multiple, args.view_type, args.output));
+    let res = rt.block_on(run_app(targets, &args, shutdown.clone()));
+    // the results are written by now, the ui may still hang on the closed terminal
+    if shutdown.is_hung_up() {
+        std::process::exit(HANGUP_EXIT_CODE);
+    }
 
     // if error print error message and exit
-    if let Err(err) = res {
+    let summaries = match res {
+        Ok(summaries) => summaries,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(err.exit_code());
+        }
+    };
+
+    let assertions: Vec<Assertion> = args.assert_loss.iter()
+        .chain(&args.assert_avg)
+        .chain(&args.assert_p99)
+        .cloned()
+        .collect();
+    let violations = check_assertions(&assertions, &summaries);
+    if !violations.is_empty() {
+        for violation in &violations {
+            eprintln!("assertion failed: {}", violation);
+        }
+        let failed = summaries.iter()
+            .filter(|s| violations.iter().any(|v| v.addr == s.addr && v.ip == s.ip))
+            .count();
+        let err = NpingError::Assertions { failed, targets: summaries.len() };
         eprintln!("{}", err);
-        std::process::exit(1);
+        std::process::exit(err.exit_code());
     }
-    Ok(())
+}
+
+// parse a command line, usage errors are printed and exit like clap does
+fn parse_args(cmd: clap::Command, argv: &[OsString]) -> ArgMatches {
+    cmd.try_get_matches_from(argv).unwrap_or_else(|err| {
+        // monitoring plugins report bad arguments as UNKNOWN
+        let check = std::env::args().nth(1).is_some_and(|arg| arg == "check");
+        let code = if check && err.use_stderr() { check::UNKNOWN_EXIT_CODE } else { err.exit_code() };
+        err.print().ok();
+        std::process::exit(code);
+    })
 }
 
 async fn run_app(
     targets: Vec<String>,
-    count: usize,
-    interval: i32,
-    running: Arc<Mutex<bool>>,
-    force_ipv6: bool,
-    multiple: i32,
-    view_type: String,
-    output_file: Option<String>,
-) -> Result<(), Box<dyn std::error::Error>> {
-
-    // init terminal
-    draw::init_terminal()?;
-
-    // Create terminal instance
-    let terminal = draw::init_terminal().unwrap();
-    let terminal_guard = Arc::new(Mutex::new(terminal::TerminalGuard::new(terminal)));
+    args: &Args,
+    shutdown: Shutdown,
+) -> Result<Vec<TargetSummary>, NpingError> {
+    let count = args.count;
+    let force_ipv6 = args.force_ipv6;
+    let multiple = args.multiple;
+    let view_type = args.view_type.clone();
+
+    let started = Instant::now();
+    let started_at = chrono::Local::now();
+
+    // the terminal is only touched when the ui is drawn
+    let terminal_guard = if args.no_tui {
+        None
+    } else {
+        let terminal = draw::init_terminal().map_err(|e| NpingError::Terminal(e.to_string()))?;
+        Some(Arc::new(Mutex::new(terminal::TerminalGuard::new(terminal))))
+    };
 
+    draw::set_panic_hook(shutdown.clone());
 
     // ping event channel (network -> data processor)
     let (ping_event_tx, ping_event_rx) = mpsc::sync_channel::<PingEvent>(0);
@@ -129,118 +436,406 @@ async fn run_app(
     let ping_event_tx = Arc::new(ping_event_tx);
 
 
+    // a target of the config file may have its own ipv6 setting
+    let ipv6 = |target: &String| args.settings.get(target).and_then(|s| s.ipv6).unwrap_or(force_ipv6);
     let mut ips = Vec::new();
     // if multiple is set, get multiple IP addresses for each target
     if targets.len() == 1 && multiple > 0 {
         // get multiple IP addresses for the target
-        ips = network::get_multiple_host_ipaddr(&targets[0], force_ipv6, multiple as usize)?;
+        ips = network::get_multiple_host_ipaddr(&targets[0], ipv6(&targets[0]), multiple as usize)?;
     } else {
         // get IP address for each target
         for target in &targets {
-            let ip = network::get_host_ipaddr(target, force_ipv6)?;
+            let ip = network::get_host_ipaddr(target, ipv6(target))?;
             ips.push(ip);
         }
     }
 
+    // targets named in the config file are shown by their name
+    let label = |target: &String| args.names.get(target).cloned().unwrap_or_else(|| target.clone());
+
     // Define initial data for UI
-    let ip_data = Arc::new(Mutex::new(ips.iter().enumerate().map(|(i, _)| IpData {
-        ip: String::new(),
-        addr: if targets.len() == 1 { targets[0].clone() } else { targets[i].clone() },
-        rtts: VecDeque::new(),
-        last_attr: 0.0,
-        min_rtt: 0.0,
-        max_rtt: 0.0,
-        timeout: 0,
-        received: 0,
-        pop_count: 0,
+    let ip_data = Arc::new(Mutex::new(ips.iter().enumerate().map(|(i, ip)| {
+        IpData::new(label(if targets.len() == 1 { &targets[0] } else { &targets[i] }), ip.clone())
     }).collect::<Vec<_>>()));
 
     // Start data processor
     let targets_for_processor: Vec<(String, String)> = ips.iter().enumerate().map(|(i, ip)| {
-        let addr = if targets.len() == 1 { targets[0].clone() } else { targets[i].clone() };
+        let addr = label(if targets.len() == 1 { &targets[0] } else { &targets[i] });
         (addr, ip.clone())
     }).collect();
     
-    start_data_processor(
+    let errs = Arc::new(Mutex::new(Vec::new()));
+
+    let stats_interval = std::time::Duration::from_secs(args.stats_interval);
+    let summary_interval = std::time::Duration::from_secs(args.summary_interval);
+    let output_file = args.output.as_ref().and_then(|path| {
+        let options = OutputOptions {
+            path: path.clone(),
+            format: args.format,
+            mode: if args.append {
+                OpenMode::Append
+            } else if args.overwrite {
+                OpenMode::Overwrite
+            } else {
+                OpenMode::Create
+            },
+            rotate_size: args.rotate_size,
+            stats_interval,
+            summary_interval,
+            metadata: output_metadata(args, &targets),
+        };
+        OutputFile::create(options, errs.clone())
+    });
+
+    // filled in as the ping tasks start
+    let groups = TargetGroups::new(&args.group);
+
+    // sinks run on their own threads so a slow one never stalls the data processor
+    let mut sinks = SinkHub::new();
+    if args.on_alert.is_some() || args.webhook.is_some() {
+        let alert = AlertSink::new(
+            args.on_alert.clone(),
+            args.webhook.clone(),
+            std::time::Duration::from_secs(args.alert_interval),
+            errs.clone(),
+        );
+        sinks.add("alert", Box::new(alert), std::time::Duration::from_secs(1));
+    }
+    if let Some(ref listen) = args.prometheus_listen {
+        let prometheus = PrometheusSink::new(listen, groups.clone(), errs.clone())
+            .map_err(|e| NpingError::Output(format!("failed to listen on {} for prometheus: {}, is another nping using the port?", listen, e)))?;
+        sinks.add("prometheus", Box::new(prometheus), std::time::Duration::from_secs(1));
+    }
+    let metrics_flush = std::time::Duration::from_secs(args.metrics_flush_interval.max(1));
+    if let Some(ref url) = args.influx_url {
+        let influx = InfluxSink::new(url, args.influx_token.clone(), groups.clone(), &args.metrics_tags, args.metrics_aggregate, errs.clone())
+            .map_err(|e| NpingError::Output(format!("failed to open a socket for influxdb: {}", e)))?;
+        sinks.add("influxdb", Box::new(influx), metrics_flush);
+    }
+    if let Some(ref addr) = args.statsd {
+        let statsd = StatsdSink::new(addr, &args.statsd_prefix, &args.metrics_tags, groups.clone(), args.metrics_aggregate, errs.clone())
+            .map_err(|e| NpingError::Output(format!("failed to open a socket for statsd: {}", e)))?;
+        sinks.add("statsd", Box::new(statsd), metrics_flush);
+    }
+    if let Some(ref endpoint) = args.otlp_endpoint {
+        let otlp = OtlpSink::new(endpoint, groups.clone(), hostname(), errs.clone());
+        sinks.add("otlp", Box::new(otlp), metrics_flush);
+    }
+    if let Some(ref broker) = args.mqtt {
+        let base_topic = args.mqtt_topic.clone().unwrap_or_else(|| format!("nping/{}", hostname()));
+        let client_id = format!("nping-{}-{}", hostname(), std::process::id());
+        let mqtt = MqttSink::new(broker.clone(), base_topic, client_id, errs.clone());
+        sinks.add("mqtt", Box::new(mqtt), metrics_flush);
+    }
+    add_log_sinks(args, &mut sinks, &errs)?;
+    if let Some(ref path) = args.record {
+        let record = RecordSink::new(path, &targets_for_processor, &output_metadata(args, &targets), started_at, errs.clone())
+            .map_err(NpingError::Output)?;
+        sinks.add("record", Box::new(record), std::time::Duration::from_secs(1));
+    }
+
+    let processor_config = ProcessorConfig {
+        outage_threshold: args.outage_threshold,
+        health_rules: health_rules(args),
+        anomaly_sensitivity: args.anomaly_sensitivity,
+        baseline_warmup: args.baseline_warmup,
+        outage_history: MAX_OUTAGE_HISTORY,
+    };
+    // target changes of a reload and stats requests (reloader, SIGUSR1 -> data processor)
+    let (updates_tx, updates_rx) = mpsc::channel::<TargetUpdate>();
+    let processor = start_data_processor(
         ping_event_rx,
         ui_data_tx,
-        targets_for_processor,
-        view_type.clone(),
-        running.clone(),
+        updates_rx,
+        DataProcessor::new(&targets_for_processor, &view_type, &processor_config),
+        sinks,
+        shutdown.clone(),
     );
 
     let view_type = Arc::new(view_type);
 
-    let errs = Arc::new(Mutex::new(Vec::new()));
-
-    let interval = if interval == 0 { 500 } else { interval * 1000 };
-    let mut tasks = Vec::new();
-
+    let tasks = Arc::new(Mutex::new(
+        PingTasks::new(count, interval_ms(args.interval), (force_ipv6, multiple), shutdown.clone(), errs.clone(), ping_event_tx.clone())
+            .with_targets(args.settings.clone(), groups),
+    ));
 
     // first draw ui
-    {
-        let mut guard = terminal_guard.lock().unwrap();
-        let ip_data = ip_data.lock().unwrap();
-
-        draw::draw_interface(
-            &mut guard.terminal.as_mut().unwrap(),
-            &view_type,
-            &ip_data,
-            &mut errs.lock().unwrap(),
-        ).ok();
-    }
-    for (i, ip) in ips.iter().enumerate() {
-        let ip = ip.clone();
-        let running = running.clone();
-        let errs = errs.clone();
-        let task = task::spawn({
-            let errs = errs.clone();
-            let ping_event_tx = ping_event_tx.clone();
-            let ip_data = ip_data.clone();
-            let mut data = ip_data.lock().unwrap();
-            // update the ip
-            data[i].ip = ip.clone();
-            let addr = data[i].addr.clone();
-            async move {
-                send_ping(addr, ip, errs.clone(), count, interval, running.clone(), ping_event_tx).await.unwrap();
-            }
-        });
-        tasks.push(task)
+    if let Some(ref terminal_guard) = terminal_guard {
+        if let Some(terminal) = lock(terminal_guard).terminal.as_mut() {
+            draw::draw_interface(terminal, &view_type, &lock(&ip_data), &lock(&errs)).ok();
+        }
+    }
+    for (i, (addr, ip)) in targets_for_processor.iter().enumerate() {
+        let host = if targets.len() == 1 { &targets[0] } else { &targets[i] };
+        lock(&tasks).start(host, addr, ip, false);
     }
 
+    // reload the targets and thresholds when the config file changes or on SIGHUP, also with the ui
+    let command_line = args.command_line.clone();
+    let reload_tx = start_reloader(
+        move || load_args(&command_line).map(|args| reload_settings(&args)),
+        (args.config_file.clone(), std::time::Duration::from_secs(args.resolve_interval)),
+        tasks.clone(),
+        ip_data.clone(),
+        updates_tx.clone(),
+        shutdown.clone(),
+        errs.clone(),
+    );
+
     // Spawn UI task in background
-    let running_for_ui = running.clone();
+    let shutdown_for_ui = shutdown.clone();
     let terminal_guard_for_ui = terminal_guard.clone();
     let view_type_for_ui = view_type.clone();
     let ip_data_for_ui = ip_data.clone();
     let errs_for_ui = errs.clone();
-    
+    let headless_options = HeadlessOptions {
+        format: args.format,
+        refresh: std::time::Duration::from_secs(args.refresh),
+        stats_interval,
+        summary_interval,
+    };
+
     let ui_task = task::spawn(async move {
-        let mut guard = terminal_guard_for_ui.lock().unwrap();
-        draw::draw_interface_with_updates(
-            &mut guard.terminal.as_mut().unwrap(),
-            &view_type_for_ui,
-            &ip_data_for_ui,
-            ui_data_rx,
-            running_for_ui,
-            errs_for_ui,
-            output_file,
-        ).ok();
+        match terminal_guard_for_ui {
+            Some(terminal_guard) => {
+                let mut guard = lock(&terminal_guard);
+                let terminal = guard.terminal.as_mut().ok_or_else(|| NpingError::Terminal("the terminal is gone".to_string()))?;
+                draw::draw_interface_with_updates(
+                    terminal,
+                    &view_type_for_ui,
+                    &ip_data_for_ui,
+                    ui_data_rx,
+                    shutdown_for_ui,
+                    errs_for_ui,
+                    output_file,
+                ).map_err(|e| NpingError::Terminal(e.to_string()))
+            }
+            None => {
+                headless::run_headless(
+                    &ip_data_for_ui,
+                    ui_data_rx,
+                    shutdown_for_ui,
+                    errs_for_ui,
+                    output_file,
+                    headless_options,
+                );
+                Ok(())
+            }
+        }
     });
 
-    // Wait for all ping tasks to complete
-    for task in tasks {
-        task.await?;
+    // the ui gets Ctrl+C as a key press, signals from elsewhere have to stop it as well
+    let tui = terminal_guard.is_some();
+    shutdown::handle_signals(shutdown.clone());
+    task::spawn(reload_signal(reload_tx, tui, shutdown.clone()));
+
+    // write the statistics so far on SIGUSR1, stderr would draw over the ui
+    let dump_file = args.dump_file.clone();
+    let errs_for_dump = errs.clone();
+    task::spawn(dump_signal(updates_tx, move |summaries| {
+        let res = match dump_file {
+            Some(ref path) => summary::write_summary_file(path, &summaries, started_at, started.elapsed()),
+            None if !tui => summary::print_summary(&mut std::io::stderr(), &summaries, started.elapsed(), OutputFormat::Text),
+            None => {
+                lock(&errs_for_dump).push("statistics are written to stderr only with --no-tui, use --dump-file".to_string());
+                Ok(())
+            }
+        };
+        if let Err(err) = res {
+            lock(&errs_for_dump).push(format!("failed to write statistics: {}", err));
+        }
+    }));
+
+    // Wait for all ping tasks to complete, a reload may stop some and start others on the way
+    while !lock(&tasks).is_finished() {
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    // a failed task does not stop the others, the first error is returned once everything is flushed
+    let mut failure = None;
+    let finished = lock(&tasks).take_tasks();
+    for task in finished {
+        if let Err(err) = task.await.map_err(NpingError::from).and_then(|res| res) {
+            failure.get_or_insert(err);
+        }
     }
     
     // All ping tasks completed, signal UI to exit
-    *running.lock().unwrap() = false;
+    shutdown.cancel();
     
-    // Wait for UI task to finish
-    ui_task.await?;
+    // Wait for UI task to finish, crossterm may never return from a closed terminal
+    let ui_res = if shutdown.is_hung_up() {
+        tokio::time::timeout(HANGUP_UI_WAIT, ui_task).await.ok()
+    } else {
+        Some(ui_task.await)
+    };
+    if let Some(Err(err)) = ui_res.map(|res| res.map_err(NpingError::from).and_then(|res| res)) {
+        failure.get_or_insert(err);
+    }
     
-    // restore terminal
-    draw::restore_terminal(&mut terminal_guard.lock().unwrap().terminal.as_mut().unwrap())?;
+    // restore terminal, there is none to restore after a hang up
+    if let Some(terminal_guard) = terminal_guard.as_ref().filter(|_| !shutdown.is_hung_up()) {
+        if let Some(terminal) = lock(terminal_guard).terminal.as_mut() {
+            if let Err(err) = draw::restore_terminal(terminal) {
+                failure.get_or_insert(NpingError::Terminal(err.to_string()));
+            }
+        }
+    }
+
+    // wait for the sinks to flush
+    let summaries = processor.join().unwrap_or_else(|_| {
+        failure.get_or_insert(NpingError::Internal("the data processor stopped unexpectedly".to_string()));
+        Vec::new()
+    });
 
+    // the ui is gone now, leave the statistics on the screen like ping does
+    let format = if terminal_guard.is_none() { args.format } else { OutputFormat::Text };
+    if format == OutputFormat::Csv {
+        // stdout only carries csv rows, the table goes to stderr like the errors
+        summary::print_summary(&mut std::io::stderr(), &summaries, started.elapsed(), OutputFormat::Text).ok();
+    } else {
+        let mut stdout = std::io::stdout();
+        summary::print_summary(&mut stdout, &summaries, started.elapsed(), format).and_then(|_| stdout.flush()).ok();
+    }
+
+    if let Some(ref path) = args.summary_file {
+        if let Err(err) = summary::write_summary_file(path, &summaries, started_at, started.elapsed()) {
+            failure.get_or_insert(NpingError::Output(format!("failed to write summary file {}: {}", path, err)));
+        }
+    }
+
+    match failure {
+        Some(err) => Err(err),
+        None => Ok(summaries),
+    }
+}
+
+#[cfg(unix)]
+fn add_log_sinks(args: &Args, sinks: &mut SinkHub, errs: &Arc<Mutex<Vec<String>>>) -> Result<(), NpingError> {
+    use crate::sinks::{LogTarget, SyslogSink, JOURNALD_SOCKET};
+    if let Some(ref socket) = args.syslog {
+        let syslog = SyslogSink::new(LogTarget::Syslog, socket, hostname(), errs.clone())
+            .map_err(|e| NpingError::Output(format!("failed to open syslog socket {}: {}", socket, e)))?;
+        sinks.add("syslog", Box::new(syslog), std::time::Duration::from_secs(1));
+    }
+    if args.journald {
+        let journald = SyslogSink::new(LogTarget::Journald, JOURNALD_SOCKET, hostname(), errs.clone())
+            .map_err(|e| NpingError::Output(format!("failed to open the systemd journal: {}", e)))?;
+        sinks.add("journald", Box::new(journald), std::time::Duration::from_secs(1));
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(not(unix))]
+fn add_log_sinks(args: &Args, _sinks: &mut SinkHub, _errs: &Arc<Mutex<Vec<String>>>) -> Result<(), NpingError> {
+    if args.syslog.is_some() || args.journald {
+        return Err(NpingError::Output("--syslog and --journald are only supported on unix".to_string()));
+    }
+    Ok(())
+}
+
+// resolves the reload channel on every SIGHUP, never on other platforms. With the ui it may
+// also come from a closed terminal, the run then ends as on SIGTERM and exits with 129
+async fn reload_signal(reload_tx: mpsc::Sender<()>, tui: bool, shutdown: Shutdown) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        if let Ok(mut hangup) = signal(SignalKind::hangup()) {
+            while hangup.recv().await.is_some() {
+                if tui && std::fs::File::open("/dev/tty").is_err() {
+                    shutdown.hang_up();
+                    break;
+                }
+                if reload_tx.send(()).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    drop((reload_tx, tui, shutdown));
+}
+
+// asks the data processor for the statistics on every SIGUSR1, never on other platforms
+async fn dump_signal(updates_tx: mpsc::Sender<TargetUpdate>, dump: impl Fn(Vec<TargetSummary>)) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        if let Ok(mut user1) = signal(SignalKind::user_defined1()) {
+            while user1.recv().await.is_some() {
+                let (summaries_tx, summaries_rx) = tokio::sync::oneshot::channel();
+                if updates_tx.send(TargetUpdate::Summaries(summaries_tx)).is_err() {
+                    break;
+                }
+                if let Ok(summaries) = summaries_rx.await {
+                    dump(summaries);
+                }
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    drop((updates_tx, dump));
+}
+
+fn health_rules(args: &Args) -> HealthRules {
+    HealthRules::new(&args.thresholds, args.hysteresis, std::time::Duration::from_secs(args.state_min_duration))
+}
+
+// the options of a run with the config file applied, as main parses them but without exiting
+fn load_args(argv: &[OsString]) -> Result<Args, String> {
+    let cli = Args::command()
+        .mut_arg("target", |arg| arg.required(false))
+        .try_get_matches_from(argv)
+        .map_err(|e| e.to_string())?;
+    let (argv, config) = config::apply_config(&Args::command(), &cli, argv)?;
+    let mut args = Args::command()
+        .try_get_matches_from(&argv)
+        .and_then(|matches| Args::from_arg_matches(&matches))
+        .map_err(|e| e.to_string())?;
+    args.target = config::expand_targets(&args.target, config.as_ref())?;
+    args.names = config.as_ref().map(|config| config.names()).unwrap_or_default();
+    args.settings = config.as_ref().map(|config| config.settings()).unwrap_or_default();
+    args.config_file = config.map(|config| config.path().to_path_buf());
+    Ok(args)
+}
+
+fn reload_settings(args: &Args) -> ReloadSettings {
+    ReloadSettings {
+        targets: unique_targets(&args.target)
+            .into_iter()
+            .map(|host| (host.clone(), args.names.get(&host).cloned().unwrap_or(host)))
+            .collect(),
+        settings: args.settings.clone(),
+        rules: health_rules(args),
+        config_path: args.config_file.clone(),
+        resolve_interval: std::time::Duration::from_secs(args.resolve_interval),
+    }
+}
+
+// after de-duplication, the original order is still preserved
+fn unique_targets(targets: &[String]) -> Vec<String> {
+    let mut seen = HashSet::new();
+    targets.iter().filter(|item| seen.insert((*item).clone())).cloned().collect()
+}
+
+// describes the run at the top of every output file
+fn output_metadata(args: &Args, targets: &[String]) -> Vec<(&'static str, String)> {
+    vec![
+        ("nping_version", env!("CARGO_PKG_VERSION").to_string()),
+        ("hostname", hostname()),
+        ("start_time", chrono::Local::now().to_rfc3339()),
+        ("command_line", std::env::args().collect::<Vec<_>>().join(" ")),
+        ("targets", targets.iter().map(|t| args.names.get(t).unwrap_or(t).as_str()).collect::<Vec<_>>().join(" ")),
+        ("probe", "icmp".to_string()),
+        ("count", args.count.to_string()),
+        ("interval_s", if args.interval == 0 { "0.5".to_string() } else { args.interval.to_string() }),
+        ("ipv6", args.force_ipv6.to_string()),
+        ("multiple", args.multiple.to_string()),
+    ]
+}
+
+fn hostname() -> String {
+    hostname::get()
+        .map(|h| h.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
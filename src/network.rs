@@ -1,25 +1,27 @@
-use std::error::Error;
 use std::net::{IpAddr, ToSocketAddrs};
 use std::sync::{Arc, Mutex};
 use std::sync::mpsc::SyncSender;
 use std::time::Duration;
-use anyhow::{anyhow, Context};
+use chrono::Local;
 
 use pinger::{ping, PingOptions, PingResult};
+use crate::error::{lock, NpingError};
 use crate::ping_event::PingEvent;
+use crate::shutdown::Shutdown;
 
 // get host ip address default to ipv4
-pub(crate) fn resolve_host_ips(host: &str, force_ipv6: bool) -> Result<Vec<IpAddr>, Box<dyn Error>> {
+pub(crate) fn resolve_host_ips(host: &str, force_ipv6: bool) -> Result<Vec<IpAddr>, NpingError> {
+    let resolve_error = |reason: String| NpingError::Resolve { host: host.to_string(), reason };
 
     // get ip address
     let ipaddr: Vec<_> = (host, 80)
         .to_socket_addrs()
-        .with_context(|| format!("failed to resolve host: {}", host))?
+        .map_err(|e| resolve_error(e.to_string()))?
         .map(|s| s.ip())
         .collect();
 
     if ipaddr.is_empty() {
-        return Err(anyhow!("Could not resolve host: {}", host).into());
+        return Err(resolve_error("no addresses found".to_string()));
     }
 
     // filter ipv4 or ipv6
@@ -34,18 +36,19 @@ pub(crate) fn resolve_host_ips(host: &str, force_ipv6: bool) -> Result<Vec<IpAdd
     };
 
     if filtered_ips.is_empty() {
-        return Err(anyhow!("Could not resolve host: {}", host).into());
+        let family = if force_ipv6 { "IPv6" } else { "IPv4" };
+        return Err(resolve_error(format!("no {} address", family)));
     }
 
     Ok(filtered_ips)
 }
 
-pub(crate) fn get_host_ipaddr(host: &str, force_ipv6: bool) -> Result<String, Box<dyn Error>> {
+pub(crate) fn get_host_ipaddr(host: &str, force_ipv6: bool) -> Result<String, NpingError> {
     let ips = resolve_host_ips(host, force_ipv6)?;
     Ok(ips[0].to_string())
 }
 
-pub(crate) fn get_multiple_host_ipaddr(host: &str, force_ipv6: bool, multiple: usize) -> Result<Vec<String>, Box<dyn Error>> {
+pub(crate) fn get_multiple_host_ipaddr(host: &str, force_ipv6: bool, multiple: usize) -> Result<Vec<String>, NpingError> {
     let ips = resolve_host_ips(host, force_ipv6)?;
     Ok(ips.into_iter()
         .take(multiple)
@@ -59,7 +62,9 @@ pub struct PingTask {
     ip: String,
     count: usize,
     interval: u64,
-    running: Arc<Mutex<bool>>,
+    shutdown: Shutdown,
+    // cancelled when a reload removes the target
+    stop: Shutdown,
     errs: Arc<Mutex<Vec<String>>>,
 }
 
@@ -69,7 +74,7 @@ impl PingTask {
         ip: String,
         count: usize,
         interval: u64,
-        running: Arc<Mutex<bool>>,
+        shutdown: Shutdown,
         errs: Arc<Mutex<Vec<String>>>,
     ) -> Self {
         Self {
@@ -77,12 +82,34 @@ impl PingTask {
             ip,
             count,
             interval,
-            running,
+            shutdown,
+            stop: Shutdown::new(),
             errs,
         }
     }
 
-    pub async fn run(&self, ping_event_tx: Arc<SyncSender<PingEvent>>) -> Result<(), Box<dyn Error>>
+    /// stop the task once `stop` is cancelled, e.g. by a reload
+    pub fn with_stop(mut self, stop: Shutdown) -> Self {
+        self.stop = stop;
+        self
+    }
+
+    fn is_running(&self) -> bool {
+        !self.shutdown.is_cancelled() && !self.stop.is_cancelled()
+    }
+
+    // show the error and count it for the target
+    fn report_error(&self, ping_event_tx: &SyncSender<PingEvent>, err: String) {
+        set_error(self.errs.clone(), err.clone());
+        let event = PingEvent::Error {
+            addr: self.addr.clone(),
+            ip: self.ip.clone(),
+            message: err,
+        };
+        ping_event_tx.send(event).ok();
+    }
+
+    pub async fn run(&self, ping_event_tx: Arc<SyncSender<PingEvent>>) -> Result<(), NpingError>
     {
         // interval defined 0.5s/every ping
         let interval = Duration::from_millis(self.interval);
@@ -93,25 +120,44 @@ impl PingTask {
         );
 
         // star ping
-        let stream = ping(options)?;
-
-        for _ in 0..self.count {
-            // if ctrl+c is pressed, break the loop
-            if !*self.running.lock().unwrap() {
-                break;
+        let stream = ping(options).map_err(|e| NpingError::Probe { ip: self.ip.clone(), reason: e.to_string() })?;
+        // the results come from a blocking channel, a thread hands them over so that waiting
+        // for one can be cancelled; it ends with the ping process or at its next result
+        let (result_tx, mut results_rx) = tokio::sync::mpsc::unbounded_channel();
+        std::thread::spawn(move || {
+            while let Ok(result) = stream.recv() {
+                if result_tx.send(result).is_err() {
+                    break;
+                }
             }
-            match stream.recv() {
-                Ok(result) => {
+        });
+
+        // sequence number of the replies and timeouts of this target
+        let mut seq = 0;
+        let mut results = 0;
+        while results < self.count {
+            // if ctrl+c is pressed or the target was removed, stop waiting for the next reply
+            let result = tokio::select! {
+                _ = self.shutdown.cancelled() => break,
+                _ = self.stop.cancelled() => break,
+                result = results_rx.recv() => result,
+            };
+            match result {
+                Some(result) => {
+                    results += 1;
                     match result {
                         PingResult::Pong(duration, _size) => {
                             // calculate rtt
                             let rtt = duration.as_secs_f64() * 1000.0;
-                            let rtt_display: f64 = format!("{:.2}", rtt).parse().unwrap();
+                            let rtt_display = (rtt * 100.0).round() / 100.0;
                             
+                            seq += 1;
                             let event = PingEvent::Success {
                                 addr: self.addr.clone(),
                                 ip: self.ip.clone(),
                                 rtt: rtt_display,
+                                seq,
+                                timestamp: Local::now(),
                             };
                             
                             if ping_event_tx.send(event).is_err() {
@@ -119,9 +165,12 @@ impl PingTask {
                             }
                         }
                         PingResult::Timeout(_) => {
+                            seq += 1;
                             let event = PingEvent::Timeout {
                                 addr: self.addr.clone(),
                                 ip: self.ip.clone(),
+                                seq,
+                                timestamp: Local::now(),
                             };
                             
                             if ping_event_tx.send(event).is_err() {
@@ -129,20 +178,26 @@ impl PingTask {
                             }
                         }
                         PingResult::PingExited(status, err) => {
-                            if status.code() != Option::from(0) {
+                            // the ping process also gets Ctrl+C when nping runs without the ui
+                            if status.code() != Option::from(0) && !killed_by_signal(&status) {
                                 let err = format!("host({}) ping err, reason: ping excited, status: {} err: {}", self.ip, err, status);
-                                set_error(self.errs.clone(), err);
+                                self.report_error(&ping_event_tx, err);
                             }
+                            break;
                         }
                         PingResult::Unknown(msg) => {
                             let err = format!("host({}) ping err, reason:unknown, err: {}", self.ip, msg);
-                            set_error(self.errs.clone(), err);
+                            self.report_error(&ping_event_tx, err);
                         }
                     }
                 }
-                Err(err) => {
-                    let err = format!("host({}) ping err, reason: unknown, err: {}", self.ip, err);
-                    set_error(self.errs.clone(), err);
+                None => {
+                    // the ping process is gone, every further recv would fail the same way
+                    if self.is_running() {
+                        let err = format!("host({}) ping err, reason: unknown, err: the ping process is gone", self.ip);
+                        self.report_error(&ping_event_tx, err);
+                    }
+                    break;
                 }
             }
 
@@ -160,9 +215,9 @@ pub async fn send_ping(
     errs: Arc<Mutex<Vec<String>>>,
     count: usize,
     interval: i32,
-    running: Arc<Mutex<bool>>,
+    shutdown: Shutdown,
     ping_event_tx: Arc<SyncSender<PingEvent>>,
-) -> Result<(), Box<dyn Error>>
+) -> Result<(), NpingError>
 {
     // draw ui first
     let task = PingTask::new(
@@ -170,14 +225,25 @@ pub async fn send_ping(
         ip,
         count,
         interval as u64,
-        running,
+        shutdown,
         errs,
     );
-    Ok(task.run(ping_event_tx).await?)
+    task.run(ping_event_tx).await
+}
+
+
+#[cfg(unix)]
+fn killed_by_signal(status: &std::process::ExitStatus) -> bool {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal().is_some()
 }
 
+#[cfg(not(unix))]
+fn killed_by_signal(_status: &std::process::ExitStatus) -> bool {
+    false
+}
 
 fn set_error(errs: Arc<Mutex<Vec<String>>>, err: String) {
-    let mut err_list = errs.lock().unwrap();
+    let mut err_list = lock(&errs);
     err_list.push(err)
 }
\ No newline at end of file
@@ -0,0 +1,210 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+use chrono::{DateTime, Local};
+
+// keep only the most recent outages in memory, count and total_downtime still cover all of them
+pub const MAX_OUTAGE_HISTORY: usize = 100;
+
+/// A run of consecutive timeouts that reached the outage threshold
+#[derive(Debug, Clone)]
+pub struct Outage {
+    pub(crate) start: DateTime<Local>,
+    pub(crate) end: Option<DateTime<Local>>,
+    pub(crate) lost: usize,
+}
+
+impl Outage {
+    /// duration of the outage, an ongoing outage is measured up to `now`
+    pub fn duration(&self, now: DateTime<Local>) -> Duration {
+        let end = self.end.unwrap_or(now);
+        (end - self.start).to_std().unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct OutageStats {
+    // the outage currently in progress, if any
+    pub(crate) current: Option<Outage>,
+    // finished outages, oldest first
+    pub(crate) history: VecDeque<Outage>,
+    // finished outages that no longer fit in the history
+    pub(crate) dropped: usize,
+    // number of outages since start, including the current one
+    pub(crate) count: usize,
+    // longest run of consecutive timeouts, even below the outage threshold
+    pub(crate) longest_burst: usize,
+    // sum of the durations of all finished outages
+    pub(crate) total_downtime: Duration,
+    // mean time between failures, None until the first outage
+    pub(crate) mtbf: Option<Duration>,
+}
+
+impl OutageStats {
+    /// duration of the outage in progress
+    pub fn current_duration(&self, now: DateTime<Local>) -> Option<Duration> {
+        self.current.as_ref().map(|o| o.duration(now))
+    }
+}
+
+/// Detect outages from the stream of ping results of one target
+#[derive(Debug)]
+pub struct OutageTracker {
+    threshold: usize,
+    started_at: DateTime<Local>,
+    history_limit: usize,
+    burst: usize,
+    burst_start: Option<DateTime<Local>>,
+    stats: OutageStats,
+}
+
+impl OutageTracker {
+    pub fn new(threshold: usize, started_at: DateTime<Local>) -> Self {
+        Self {
+            threshold: threshold.max(1),
+            started_at,
+            history_limit: MAX_OUTAGE_HISTORY,
+            burst: 0,
+            burst_start: None,
+            stats: OutageStats::default(),
+        }
+    }
+
+    /// keep up to `limit` finished outages instead of the most recent ones
+    pub fn with_history_limit(mut self, limit: usize) -> Self {
+        self.history_limit = limit;
+        self
+    }
+
+    pub fn stats(&self) -> &OutageStats {
+        &self.stats
+    }
+
+    /// record a reply, this ends the outage in progress
+    /// returns the outage that just finished
+    pub fn on_success(&mut self, at: DateTime<Local>) -> Option<Outage> {
+        self.burst = 0;
+        self.burst_start = None;
+
+        let finished = self.stats.current.take().map(|mut outage| {
+            outage.end = Some(at);
+            self.stats.total_downtime += outage.duration(at);
+            self.stats.history.push_back(outage.clone());
+            if self.stats.history.len() > self.history_limit {
+                self.stats.history.pop_front();
+                self.stats.dropped += 1;
+            }
+            outage
+        });
+        self.update_mtbf(at);
+        finished
+    }
+
+    /// record a timeout, an outage starts once the threshold is reached
+    /// returns true when this timeout started a new outage
+    pub fn on_timeout(&mut self, at: DateTime<Local>) -> bool {
+        if self.burst == 0 {
+            self.burst_start = Some(at);
+        }
+        self.burst += 1;
+        self.stats.longest_burst = self.stats.longest_burst.max(self.burst);
+
+        let started = match self.stats.current {
+            Some(ref mut outage) => {
+                outage.lost = self.burst;
+                false
+            }
+            None if self.burst >= self.threshold => {
+                // the outage started with the first timeout of the burst
+                self.stats.current = Some(Outage {
+                    start: self.burst_start.unwrap_or(at),
+                    end: None,
+                    lost: self.burst,
+                });
+                self.stats.count += 1;
+                true
+            }
+            None => false,
+        };
+        self.update_mtbf(at);
+        started
+    }
+
+    // mtbf = time spent up / number of failures
+    fn update_mtbf(&mut self, now: DateTime<Local>) {
+        if self.stats.count == 0 {
+            return;
+        }
+        let elapsed = (now - self.started_at).to_std().unwrap_or_default();
+        let downtime = self.stats.total_downtime + self.stats.current_duration(now).unwrap_or_default();
+        let uptime = elapsed.saturating_sub(downtime);
+        self.stats.mtbf = Some(uptime / self.stats.count as u32);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(secs: i64) -> DateTime<Local> {
+        Local.timestamp_opt(1_700_000_000 + secs, 0).unwrap()
+    }
+
+    #[test]
+    fn outage_starts_at_threshold_and_ends_on_reply() {
+        let mut tracker = OutageTracker::new(3, at(0));
+        assert!(!tracker.on_timeout(at(1)));
+        assert!(!tracker.on_timeout(at(2)));
+        assert!(tracker.on_timeout(at(3)));
+        assert!(!tracker.on_timeout(at(4)));
+        assert_eq!(tracker.stats().count, 1);
+        assert_eq!(tracker.stats().current.as_ref().unwrap().start, at(1));
+
+        let outage = tracker.on_success(at(5)).unwrap();
+        assert_eq!(outage.lost, 4);
+        assert_eq!(outage.duration(at(5)), Duration::from_secs(4));
+        assert!(tracker.stats().current.is_none());
+        assert_eq!(tracker.stats().total_downtime, Duration::from_secs(4));
+        assert_eq!(tracker.stats().longest_burst, 4);
+    }
+
+    #[test]
+    fn burst_below_threshold_is_not_an_outage() {
+        let mut tracker = OutageTracker::new(3, at(0));
+        tracker.on_timeout(at(1));
+        tracker.on_timeout(at(2));
+        assert!(tracker.on_success(at(3)).is_none());
+        assert_eq!(tracker.stats().count, 0);
+        assert_eq!(tracker.stats().longest_burst, 2);
+        assert!(tracker.stats().mtbf.is_none());
+    }
+
+    #[test]
+    fn live_duration_and_mtbf() {
+        let mut tracker = OutageTracker::new(2, at(0));
+        tracker.on_timeout(at(1));
+        tracker.on_timeout(at(2));
+        // the ongoing outage counts as downtime up to now
+        assert_eq!(tracker.stats().current_duration(at(10)), Some(Duration::from_secs(9)));
+        tracker.on_success(at(5));
+        assert_eq!(tracker.stats().current_duration(at(10)), None);
+        tracker.on_success(at(25));
+        // 25s run, 4s of them down, one failure
+        assert_eq!(tracker.stats().mtbf, Some(Duration::from_secs(21)));
+    }
+
+    #[test]
+    fn totals_cover_outages_dropped_from_history() {
+        let mut tracker = OutageTracker::new(1, at(0)).with_history_limit(2);
+        for i in 0..5 {
+            tracker.on_timeout(at(i * 10));
+            tracker.on_success(at(i * 10 + 2));
+        }
+        let stats = tracker.stats();
+        assert_eq!(stats.count, 5);
+        assert_eq!(stats.history.len(), 2);
+        assert_eq!(stats.dropped, 3);
+        assert_eq!(stats.total_downtime, Duration::from_secs(10));
+        assert_eq!(stats.history.back().unwrap().start, at(40));
+    }
+}
@@ -0,0 +1,634 @@
+use std::fs::File;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local};
+use clap::ValueEnum;
+use serde_json::{json, Value};
+use crate::csv_writer::CsvWriter;
+use crate::health::HealthTransition;
+use crate::ip_data::IpData;
+use crate::outage::Outage;
+use crate::rolling::WINDOW_MINUTES;
+use crate::stats::{calculate_avg_rtt, calculate_jitter, calculate_loss_pkg, calculate_percentile};
+use crate::error::lock;
+
+/// Format of the results written to stdout and the output file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum OutputFormat {
+    // human readable lines
+    #[default]
+    Text,
+    // one JSON object per line
+    Jsonl,
+    // comma separated values with a header row
+    Csv,
+}
+
+/// What happened to a target between two updates besides the new ping result
+#[derive(Debug, Default)]
+pub struct Changes {
+    // an outage that just ended
+    pub(crate) outage: Option<Outage>,
+    // a health state change
+    pub(crate) transition: Option<HealthTransition>,
+}
+
+pub fn detect_changes(previous: &IpData, updated: &IpData) -> Changes {
+    let mut changes = Changes::default();
+
+    // a new entry in the outage history means an outage just ended
+    let last_start = previous.outage.history.back().map(|o| o.start);
+    if updated.outage.history.back().map(|o| o.start) != last_start {
+        changes.outage = updated.outage.history.back().cloned();
+    }
+    let last_event_at = previous.health_events.back().map(|e| e.at);
+    if updated.health_events.back().map(|e| e.at) != last_event_at {
+        changes.transition = updated.health_events.back().cloned();
+    }
+    changes
+}
+
+/// Replace the entry of a target with its update, returns what changed
+pub fn apply_update(ip_data: &mut [IpData], updated: IpData) -> Changes {
+    match ip_data.iter().position(|d| d.addr == updated.addr && d.ip == updated.ip) {
+        Some(pos) => {
+            let changes = detect_changes(&ip_data[pos], &updated);
+            ip_data[pos] = updated;
+            changes
+        }
+        None => Changes::default(),
+    }
+}
+
+/// JSON records of the latest result of a target: the ping itself, and
+/// an outage or state record when one ended or changed with it
+pub fn event_records(data: &IpData, changes: &Changes) -> Vec<Value> {
+    let timestamp = data.last_timestamp.unwrap_or_else(Local::now).to_rfc3339();
+    let mut records = Vec::new();
+
+    let mut ping = json!({
+        "type": "ping",
+        "timestamp": timestamp,
+        "target": data.addr,
+        "ip": data.ip,
+        "probe": "icmp",
+        "seq": data.last_seq,
+    });
+    if data.last_attr < 0.0 {
+        ping["rtt_ms"] = Value::Null;
+        ping["error"] = json!("timeout");
+    } else {
+        ping["rtt_ms"] = json!(data.last_attr);
+        ping["anomaly"] = json!(data.anomalies.back().copied().unwrap_or(false));
+    }
+    records.push(ping);
+
+    if let Some(ref outage) = changes.outage {
+        let end = outage.end.unwrap_or_else(Local::now);
+        records.push(json!({
+            "type": "outage",
+            "timestamp": timestamp,
+            "target": data.addr,
+            "ip": data.ip,
+            "start": outage.start.to_rfc3339(),
+            "end": end.to_rfc3339(),
+            "duration_s": outage.duration(end).as_secs_f64(),
+            "lost": outage.lost,
+        }));
+    }
+
+    if let Some(ref event) = changes.transition {
+        records.push(json!({
+            "type": "state",
+            "timestamp": event.at.to_rfc3339(),
+            "target": event.addr,
+            "ip": event.ip,
+            "from": event.from.as_str(),
+            "to": event.to.as_str(),
+            "reason": event.reason,
+        }));
+    }
+    records
+}
+
+/// JSON snapshot of the statistics of a target
+pub fn stats_record(data: &IpData) -> Value {
+    let rolling: serde_json::Map<String, Value> = WINDOW_MINUTES
+        .iter()
+        .zip(data.rolling.windows.iter())
+        .map(|(minutes, w)| (format!("{}m", minutes), json!({ "loss": w.loss, "avg_ms": w.avg, "p95_ms": w.p95 })))
+        .collect();
+
+    json!({
+        "type": "stats",
+        "timestamp": Local::now().to_rfc3339(),
+        "target": data.addr,
+        "ip": data.ip,
+        "sent": data.received + data.timeout,
+        "received": data.received,
+        "loss": calculate_loss_pkg(data.timeout, data.received),
+        "last_ms": if data.last_attr < 0.0 { Value::Null } else { json!(data.last_attr) },
+        "avg_ms": calculate_avg_rtt(&data.rtts),
+        "min_ms": data.min_rtt,
+        "max_ms": data.max_rtt,
+        "jitter_ms": calculate_jitter(&data.rtts),
+        "p95_ms": calculate_percentile(&data.rtts, 95.0),
+        "state": data.health.as_str(),
+        "outages": data.outage.count,
+        "anomalies": data.baseline.anomalies,
+        "rolling": rolling,
+    })
+}
+
+/// Decides when the next stats snapshot is due, a zero interval never fires
+pub struct StatsTimer {
+    interval: Duration,
+    last: Instant,
+}
+
+impl StatsTimer {
+    pub fn new(interval: Duration) -> Self {
+        Self { interval, last: Instant::now() }
+    }
+
+    pub fn due(&mut self) -> bool {
+        if self.interval.is_zero() || self.last.elapsed() < self.interval {
+            return false;
+        }
+        self.last = Instant::now();
+        true
+    }
+}
+
+/// How an existing output file is treated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenMode {
+    // refuse to start if the file exists
+    Create,
+    Append,
+    Overwrite,
+}
+
+/// Everything needed to open and rotate the -o file
+#[derive(Debug, Clone)]
+pub struct OutputOptions {
+    // may contain strftime fields like %Y%m%d-%H, a new file is started whenever the name changes
+    pub(crate) path: String,
+    pub(crate) format: OutputFormat,
+    pub(crate) mode: OpenMode,
+    // start a new file once the current one reaches this many bytes
+    pub(crate) rotate_size: Option<u64>,
+    // jsonl stats snapshots
+    pub(crate) stats_interval: Duration,
+    // csv summary rows
+    pub(crate) summary_interval: Duration,
+    // written at the top of every file, or next to it in <file>.meta for csv
+    pub(crate) metadata: Vec<(&'static str, String)>,
+}
+
+/// file name for a path template at the given time
+pub fn expand_path(template: &str, at: DateTime<Local>) -> Result<String, String> {
+    if !template.contains('%') {
+        return Ok(template.to_string());
+    }
+    let mut path = String::new();
+    write!(path, "{}", at.format(template)).map_err(|_| format!("invalid strftime field in output path {}", template))?;
+    Ok(path)
+}
+
+/// check the strftime fields of an output path template, bad ones would only fail at the first write
+pub fn parse_path_template(s: &str) -> Result<String, String> {
+    if StrftimeItems::new(s).any(|item| matches!(item, Item::Error)) {
+        return Err(format!("invalid strftime field in {}, a literal % is written as %%", s));
+    }
+    Ok(s.to_string())
+}
+
+/// parse a size like 512, 100K, 10M or 1G
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (num, unit) = match s.char_indices().find(|(_, c)| c.is_ascii_alphabetic()) {
+        Some((pos, _)) => s.split_at(pos),
+        None => (s, ""),
+    };
+    let multiplier = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1024,
+        "M" | "MB" => 1024 * 1024,
+        "G" | "GB" => 1024 * 1024 * 1024,
+        _ => return Err(format!("invalid size unit: {}, expected K/M/G", unit)),
+    };
+    num.trim()
+        .parse::<u64>()
+        .ok()
+        .filter(|n| *n > 0)
+        .map(|n| n * multiplier)
+        .ok_or_else(|| format!("invalid size: {}", s))
+}
+
+/// The file given with -o
+pub struct OutputFile {
+    file: File,
+    // name of the file currently written
+    current_path: String,
+    options: OutputOptions,
+    stats_timer: StatsTimer,
+    csv: CsvWriter,
+    errs: Arc<Mutex<Vec<String>>>,
+}
+
+impl OutputFile {
+    pub fn create(options: OutputOptions, errs: Arc<Mutex<Vec<String>>>) -> Option<Self> {
+        let path = match expand_path(&options.path, Local::now()) {
+            Ok(path) => path,
+            Err(e) => {
+                lock(&errs).push(e);
+                return None;
+            }
+        };
+        match open_file(&path, options.mode) {
+            Ok(file) => {
+                let mut output = Self {
+                    file,
+                    current_path: path,
+                    stats_timer: StatsTimer::new(options.stats_interval),
+                    csv: CsvWriter::new(options.summary_interval),
+                    options,
+                    errs,
+                };
+                let res = output.write_preamble();
+                output.report(res);
+                Some(output)
+            }
+            Err(e) => {
+                let mut errs = lock(&errs);
+                errs.push(format!("Failed to create output file {}: {}", path, e));
+                None
+            }
+        }
+    }
+
+    pub fn write_update(&mut self, data: &IpData, changes: &Changes) {
+        self.rotate();
+        let res = match self.options.format {
+            OutputFormat::Text => write_lines(&mut self.file, &text_lines(data, changes)),
+            OutputFormat::Jsonl => write_lines(&mut self.file, &event_records(data, changes)),
+            OutputFormat::Csv => self.csv.write_update(&mut self.file, data),
+        };
+        self.report(res);
+    }
+
+    /// write the periodic records: jsonl stats snapshots or csv summary rows
+    pub fn write_stats(&mut self, ip_data: &[IpData]) {
+        self.rotate();
+        let res = match self.options.format {
+            OutputFormat::Jsonl if self.stats_timer.due() => {
+                write_lines(&mut self.file, &ip_data.iter().map(stats_record).collect::<Vec<_>>())
+            }
+            OutputFormat::Csv => self.csv.write_summary(&mut self.file, ip_data),
+            _ => Ok(()),
+        };
+        self.report(res);
+    }
+
+    // switch to a new file when the name template moved on or the size limit is reached
+    fn rotate(&mut self) {
+        let path = match expand_path(&self.options.path, Local::now()) {
+            Ok(path) => path,
+            Err(e) => {
+                lock(&self.errs).push(e);
+                return;
+            }
+        };
+        let full = match self.options.rotate_size {
+            Some(limit) => self.file.metadata().map(|m| m.len() >= limit).unwrap_or(false),
+            None => false,
+        };
+        if path == self.current_path && !full {
+            return;
+        }
+
+        if full {
+            // keep the full file as path.1, path.2, ...
+            let rotated = (1..)
+                .map(|n| format!("{}.{}", self.current_path, n))
+                .find(|p| !Path::new(p).exists())
+                .unwrap();
+            if let Err(e) = std::fs::rename(&self.current_path, &rotated) {
+                self.report(Err(e));
+            }
+            if self.options.format == OutputFormat::Csv {
+                std::fs::rename(meta_path(&self.current_path), meta_path(&rotated)).ok();
+            }
+        }
+
+        // a file of a later period may already exist from an earlier run, never clobber it
+        let mode = if self.options.mode == OpenMode::Overwrite { OpenMode::Overwrite } else { OpenMode::Append };
+        match open_file(&path, mode) {
+            Ok(file) => {
+                self.file = file;
+                self.current_path = path;
+                let res = self.write_preamble();
+                self.report(res);
+            }
+            Err(e) => {
+                let mut errs = lock(&self.errs);
+                errs.push(format!("Failed to rotate output file to {}: {}", path, e));
+            }
+        }
+    }
+
+    // metadata header, csv keeps it in a sidecar file so the header row stays the first line
+    fn write_preamble(&mut self) -> io::Result<()> {
+        match self.options.format {
+            OutputFormat::Jsonl => {
+                let mut record = json!({ "type": "meta" });
+                for (key, value) in &self.options.metadata {
+                    record[*key] = json!(value);
+                }
+                writeln!(self.file, "{}", record)
+            }
+            OutputFormat::Text => write_metadata(&mut self.file, &self.options.metadata),
+            OutputFormat::Csv => {
+                // one block per session when appending, like the header in text files
+                let mode = if self.options.mode == OpenMode::Overwrite { OpenMode::Overwrite } else { OpenMode::Append };
+                let mut meta = open_file(&meta_path(&self.current_path), mode)?;
+                write_metadata(&mut meta, &self.options.metadata)?;
+
+                let empty = self.file.metadata()?.len() == 0;
+                self.csv.start_file(!empty);
+                self.csv.write_header(&mut self.file)
+            }
+        }
+    }
+
+    fn report(&self, res: io::Result<()>) {
+        if let Err(e) = res {
+            let mut errs = lock(&self.errs);
+            errs.push(format!("Failed to write to output file: {}", e));
+        }
+    }
+}
+
+fn open_file(path: &str, mode: OpenMode) -> io::Result<File> {
+    let mut options = std::fs::OpenOptions::new();
+    match mode {
+        OpenMode::Create => options.write(true).create_new(true),
+        OpenMode::Append => options.append(true).create(true),
+        OpenMode::Overwrite => options.write(true).create(true).truncate(true),
+    };
+    options.open(path)
+}
+
+/// sidecar file with the metadata of a csv output file
+pub(crate) fn meta_path(path: &str) -> String {
+    format!("{}.meta", path)
+}
+
+fn write_metadata(out: &mut impl Write, metadata: &[(&'static str, String)]) -> io::Result<()> {
+    for (key, value) in metadata {
+        writeln!(out, "# {}: {}", key, value)?;
+    }
+    Ok(())
+}
+
+pub fn write_lines(out: &mut impl Write, lines: &[impl std::fmt::Display]) -> io::Result<()> {
+    for line in lines {
+        writeln!(out, "{}", line)?;
+    }
+    Ok(())
+}
+
+fn text_lines(data: &IpData, changes: &Changes) -> Vec<String> {
+    let latency_str = if data.last_attr == -1.0 {
+        "timeout".to_string()
+    } else {
+        format!("{:.2}ms", data.last_attr)
+    };
+    let mut lines = vec![format!("{} {} {}", data.addr, data.ip, latency_str)];
+
+    if let Some(ref outage) = changes.outage {
+        let end = outage.end.unwrap_or_else(Local::now);
+        lines.push(format!("{} {} outage start={} end={} duration={:.2}s lost={}",
+                           data.addr,
+                           data.ip,
+                           outage.start.to_rfc3339(),
+                           end.to_rfc3339(),
+                           outage.duration(end).as_secs_f64(),
+                           outage.lost
+        ));
+    }
+
+    if let Some(ref event) = changes.transition {
+        lines.push(format!("{} {} state {} -> {} at={} reason={}",
+                           event.addr,
+                           event.ip,
+                           event.from,
+                           event.to,
+                           event.at.to_rfc3339(),
+                           event.reason
+        ));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use crate::health::HealthState;
+
+    fn at(secs: i64) -> DateTime<Local> {
+        Local.timestamp_opt(1_700_000_000 + secs, 0).unwrap()
+    }
+
+    fn reply(seq: usize, rtt: f64) -> IpData {
+        let mut data = IpData::new("example.com".into(), "192.0.2.1".into());
+        data.last_seq = seq;
+        data.last_attr = rtt;
+        data.last_timestamp = Some(at(seq as i64));
+        data
+    }
+
+    fn options(path: &str, format: OutputFormat, mode: OpenMode) -> OutputOptions {
+        OutputOptions {
+            path: path.to_string(),
+            format,
+            mode,
+            rotate_size: None,
+            stats_interval: Duration::ZERO,
+            summary_interval: Duration::ZERO,
+            metadata: vec![("nping_version", "0.5.0".into()), ("hostname", "test".into())],
+        }
+    }
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("nping-output-{}-{}", std::process::id(), name));
+        let path = path.to_string_lossy().to_string();
+        for p in [path.clone(), format!("{}.1", path), format!("{}.2", path)] {
+            std::fs::remove_file(meta_path(&p)).ok();
+            std::fs::remove_file(p).ok();
+        }
+        path
+    }
+
+    fn write_one(options: OutputOptions, errs: &Arc<Mutex<Vec<String>>>) {
+        let mut output = OutputFile::create(options, errs.clone()).unwrap();
+        output.write_update(&reply(1, 12.5), &Changes::default());
+        output.write_update(&reply(2, -1.0), &Changes::default());
+    }
+
+    #[test]
+    fn csv_starts_with_the_header_and_keeps_metadata_aside() {
+        let path = temp_path("meta.csv");
+        let errs = Arc::new(Mutex::new(Vec::new()));
+        write_one(options(&path, OutputFormat::Csv, OpenMode::Create), &errs);
+        write_one(options(&path, OutputFormat::Csv, OpenMode::Append), &errs);
+        assert!(errs.lock().unwrap().is_empty());
+
+        let csv = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], "timestamp,target,ip,probe,seq,status,rtt_ms,state");
+        assert!(lines[1].ends_with(",example.com,192.0.2.1,icmp,1,ok,12.50,OK"));
+        assert!(lines[2].ends_with(",example.com,192.0.2.1,icmp,2,timeout,,OK"));
+        assert!(!csv.contains('#'));
+
+        // one metadata block per session
+        let meta = std::fs::read_to_string(meta_path(&path)).unwrap();
+        assert_eq!(meta.matches("# nping_version: 0.5.0").count(), 2);
+    }
+
+    #[test]
+    fn existing_file_is_kept_without_append() {
+        let path = temp_path("exists.csv");
+        std::fs::write(&path, "keep me\n").unwrap();
+        let errs = Arc::new(Mutex::new(Vec::new()));
+        assert!(OutputFile::create(options(&path, OutputFormat::Csv, OpenMode::Create), errs.clone()).is_none());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "keep me\n");
+
+        write_one(options(&path, OutputFormat::Csv, OpenMode::Overwrite), &errs);
+        assert!(std::fs::read_to_string(&path).unwrap().starts_with("timestamp,"));
+    }
+
+    #[test]
+    fn full_files_are_rotated_with_their_metadata() {
+        let path = temp_path("rotate.csv");
+        let errs = Arc::new(Mutex::new(Vec::new()));
+        let mut options = options(&path, OutputFormat::Csv, OpenMode::Create);
+        options.rotate_size = Some(1);
+        write_one(options, &errs);
+        assert!(errs.lock().unwrap().is_empty());
+
+        // the header alone fills the first file, every later one gets a single row
+        for (name, lines) in [(format!("{}.1", path), 1), (format!("{}.2", path), 2), (path.clone(), 2)] {
+            let csv = std::fs::read_to_string(&name).unwrap();
+            assert!(csv.starts_with("timestamp,"));
+            assert_eq!(csv.lines().count(), lines);
+            assert!(Path::new(&meta_path(&name)).exists());
+        }
+    }
+
+    #[test]
+    fn sizes_and_path_templates() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("100K"), Ok(100 * 1024));
+        assert_eq!(parse_size("10mb"), Ok(10 * 1024 * 1024));
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("10X").is_err());
+
+        let time = Local.with_ymd_and_hms(2024, 3, 1, 7, 0, 0).unwrap();
+        assert_eq!(expand_path("nping-%Y%m%d-%H.log", time).as_deref(), Ok("nping-20240301-07.log"));
+        assert_eq!(expand_path("plain.log", time).as_deref(), Ok("plain.log"));
+        assert!(expand_path("100%.log", time).is_err());
+
+        assert!(parse_path_template("nping-%Y%m%d.log").is_ok());
+        assert!(parse_path_template("100%%.log").is_ok());
+        assert!(parse_path_template("100%.log").is_err());
+        assert!(parse_path_template("nping-%Q.log").is_err());
+    }
+
+    #[test]
+    fn ping_and_timeout_records() {
+        let ping = &event_records(&reply(1, 12.5), &Changes::default())[0];
+        assert_eq!(ping["type"], "ping");
+        assert_eq!(ping["target"], "example.com");
+        assert_eq!(ping["ip"], "192.0.2.1");
+        assert_eq!(ping["probe"], "icmp");
+        assert_eq!(ping["seq"], 1);
+        assert_eq!(ping["rtt_ms"], 12.5);
+
+        let timeout = &event_records(&reply(2, -1.0), &Changes::default())[0];
+        assert_eq!(timeout["rtt_ms"], Value::Null);
+        assert_eq!(timeout["error"], "timeout");
+    }
+
+    #[test]
+    fn jsonl_records() {
+        let path = temp_path("records.jsonl");
+        let errs = Arc::new(Mutex::new(Vec::new()));
+        write_one(options(&path, OutputFormat::Jsonl, OpenMode::Create), &errs);
+
+        let lines: Vec<Value> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines[0]["type"], "meta");
+        assert_eq!(lines[0]["hostname"], "test");
+        assert_eq!(lines[1]["type"], "ping");
+        assert_eq!(lines[1]["rtt_ms"], 12.5);
+        assert_eq!(lines[2]["rtt_ms"], Value::Null);
+        assert_eq!(lines[2]["error"], "timeout");
+    }
+
+    #[test]
+    fn outage_and_state_records_follow_the_ping() {
+        let data = reply(5, 10.0);
+        let changes = Changes {
+            outage: Some(Outage { start: at(0), end: Some(at(4)), lost: 4 }),
+            transition: Some(HealthTransition {
+                addr: data.addr.clone(),
+                ip: data.ip.clone(),
+                from: HealthState::Crit,
+                to: HealthState::Ok,
+                at: at(5),
+                reason: "recovered".into(),
+            }),
+        };
+        let records = event_records(&data, &changes);
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[1]["type"], "outage");
+        assert_eq!(records[1]["duration_s"], 4.0);
+        assert_eq!(records[1]["lost"], 4);
+        assert_eq!(records[2]["from"], "CRIT");
+        assert_eq!(records[2]["to"], "OK");
+    }
+
+    #[test]
+    fn stats_record_has_every_window() {
+        let mut data = reply(3, 20.0);
+        data.received = 3;
+        data.timeout = 1;
+        let record = stats_record(&data);
+        assert_eq!(record["type"], "stats");
+        assert_eq!(record["sent"], 4);
+        assert_eq!(record["loss"], 25.0);
+        assert_eq!(record["last_ms"], 20.0);
+        for minutes in WINDOW_MINUTES {
+            assert!(record["rolling"][format!("{}m", minutes)].is_object());
+        }
+    }
+
+    #[test]
+    fn zero_stats_interval_never_fires() {
+        assert!(!StatsTimer::new(Duration::ZERO).due());
+        let mut timer = StatsTimer::new(Duration::from_millis(1));
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(timer.due());
+    }
+}
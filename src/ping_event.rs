@@ -1,12 +1,24 @@
+use chrono::{DateTime, Local};
+
 #[derive(Debug, Clone)]
 pub enum PingEvent {
     Success {
         addr: String,
         ip: String,
         rtt: f64,
+        seq: usize,
+        timestamp: DateTime<Local>,
     },
     Timeout {
         addr: String,
         ip: String,
+        seq: usize,
+        timestamp: DateTime<Local>,
     },
-}
\ No newline at end of file
+    // ping itself failed, not a lost packet
+    Error {
+        addr: String,
+        ip: String,
+        message: String,
+    },
+}
@@ -0,0 +1,343 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Local};
+use rusqlite::{params, Connection};
+use crate::sinks::{Sink, SinkEvent};
+use crate::error::lock;
+
+// bumped with every incompatible change of the schema, stored as PRAGMA user_version
+pub const SCHEMA_VERSION: i64 = 1;
+// rows kept while the database cannot be written, the oldest are dropped first
+const MAX_PENDING_ROWS: usize = 100_000;
+
+// see docs/record.md, timestamps are unix milliseconds
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS session (
+    id          INTEGER PRIMARY KEY,
+    started_at  INTEGER NOT NULL,
+    finished_at INTEGER
+);
+CREATE TABLE IF NOT EXISTS session_meta (
+    session_id  INTEGER NOT NULL REFERENCES session(id),
+    key         TEXT NOT NULL,
+    value       TEXT NOT NULL,
+    PRIMARY KEY (session_id, key)
+);
+CREATE TABLE IF NOT EXISTS target (
+    id          INTEGER PRIMARY KEY,
+    session_id  INTEGER NOT NULL REFERENCES session(id),
+    addr        TEXT NOT NULL,
+    ip          TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS probe (
+    target_id   INTEGER NOT NULL REFERENCES target(id),
+    ts          INTEGER NOT NULL,
+    seq         INTEGER NOT NULL,
+    rtt_ms      REAL,
+    anomaly     INTEGER NOT NULL DEFAULT 0
+);
+CREATE TABLE IF NOT EXISTS transition (
+    target_id   INTEGER NOT NULL REFERENCES target(id),
+    ts          INTEGER NOT NULL,
+    from_state  TEXT NOT NULL,
+    to_state    TEXT NOT NULL,
+    reason      TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS probe_error (
+    target_id   INTEGER NOT NULL REFERENCES target(id),
+    ts          INTEGER NOT NULL,
+    message     TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS target_session ON target(session_id, addr);
+CREATE INDEX IF NOT EXISTS probe_target_ts ON probe(target_id, ts);
+CREATE INDEX IF NOT EXISTS probe_ts ON probe(ts);
+CREATE INDEX IF NOT EXISTS transition_target_ts ON transition(target_id, ts);
+CREATE INDEX IF NOT EXISTS probe_error_target_ts ON probe_error(target_id, ts);
+";
+
+/// Open a recording, creating the schema in a new database
+pub fn open_database(path: &str) -> Result<Connection, String> {
+    let conn = Connection::open(path).map_err(|e| format!("failed to open {}: {}", path, e))?;
+    let version: i64 = conn
+        .query_row("PRAGMA user_version", [], |row| row.get(0))
+        .map_err(|e| format!("failed to read {}: {}", path, e))?;
+    if version > SCHEMA_VERSION {
+        return Err(format!(
+            "{} was recorded by a newer nping (schema version {}, this nping supports {})",
+            path, version, SCHEMA_VERSION
+        ));
+    }
+    conn.execute_batch(SCHEMA)
+        .and_then(|_| conn.pragma_update(None, "user_version", SCHEMA_VERSION))
+        .map_err(|e| format!("failed to create the schema in {}: {}", path, e))?;
+    Ok(conn)
+}
+
+// rows waiting for the next flush, written in one transaction
+enum Row {
+    Probe { target_id: i64, ts: i64, seq: i64, rtt: Option<f64>, anomaly: bool },
+    Transition { target_id: i64, ts: i64, from: String, to: String, reason: String },
+    Error { target_id: i64, ts: i64, message: String },
+}
+
+/// Store every result, health transition and ping error of a session in SQLite
+pub struct RecordSink {
+    conn: Connection,
+    session_id: i64,
+    target_ids: HashMap<String, i64>, // key: addr_ip
+    pending: Vec<Row>,
+    // rows given up on while the database could not be written, kept as dropped_rows in session_meta
+    dropped: usize,
+    failing: bool,
+    errs: Arc<Mutex<Vec<String>>>,
+}
+
+impl RecordSink {
+    pub fn new(
+        path: &str,
+        targets: &[(String, String)],
+        metadata: &[(&'static str, String)],
+        started_at: DateTime<Local>,
+        errs: Arc<Mutex<Vec<String>>>,
+    ) -> Result<Self, String> {
+        let mut conn = open_database(path)?;
+        // readers can query the recording while it is written
+        conn.pragma_update(None, "journal_mode", "WAL").ok();
+
+        let (session_id, target_ids) = (|| {
+            let tx = conn.transaction()?;
+            tx.execute("INSERT INTO session (started_at) VALUES (?1)", params![started_at.timestamp_millis()])?;
+            let session_id = tx.last_insert_rowid();
+            for (key, value) in metadata {
+                tx.execute(
+                    "INSERT INTO session_meta (session_id, key, value) VALUES (?1, ?2, ?3)",
+                    params![session_id, key, value],
+                )?;
+            }
+            let mut target_ids = HashMap::new();
+            for (addr, ip) in targets {
+                tx.execute(
+                    "INSERT INTO target (session_id, addr, ip) VALUES (?1, ?2, ?3)",
+                    params![session_id, addr, ip],
+                )?;
+                target_ids.insert(format!("{}_{}", addr, ip), tx.last_insert_rowid());
+            }
+            tx.commit()?;
+            Ok::<_, rusqlite::Error>((session_id, target_ids))
+        })()
+        .map_err(|e| format!("failed to start recording to {}: {}", path, e))?;
+
+        Ok(Self { conn, session_id, target_ids, pending: Vec::new(), dropped: 0, failing: false, errs })
+    }
+
+    // targets added by a reload get their row when they first show up
+    fn target_id(&mut self, addr: &str, ip: &str) -> Option<i64> {
+        let key = format!("{}_{}", addr, ip);
+        if let Some(id) = self.target_ids.get(&key) {
+            return Some(*id);
+        }
+        self.conn
+            .execute("INSERT INTO target (session_id, addr, ip) VALUES (?1, ?2, ?3)", params![self.session_id, addr, ip])
+            .ok()?;
+        let id = self.conn.last_insert_rowid();
+        self.target_ids.insert(key, id);
+        Some(id)
+    }
+
+    fn write_pending(&mut self) -> rusqlite::Result<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut probe = tx.prepare_cached("INSERT INTO probe (target_id, ts, seq, rtt_ms, anomaly) VALUES (?1, ?2, ?3, ?4, ?5)")?;
+            let mut transition = tx.prepare_cached(
+                "INSERT INTO transition (target_id, ts, from_state, to_state, reason) VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            let mut error = tx.prepare_cached("INSERT INTO probe_error (target_id, ts, message) VALUES (?1, ?2, ?3)")?;
+            for row in &self.pending {
+                match row {
+                    Row::Probe { target_id, ts, seq, rtt, anomaly } => {
+                        probe.execute(params![target_id, ts, seq, rtt, anomaly])?;
+                    }
+                    Row::Transition { target_id, ts, from, to, reason } => {
+                        transition.execute(params![target_id, ts, from, to, reason])?;
+                    }
+                    Row::Error { target_id, ts, message } => {
+                        error.execute(params![target_id, ts, message])?;
+                    }
+                }
+            }
+        }
+        if self.dropped > 0 {
+            write_dropped(&tx, self.session_id, self.dropped)?;
+        }
+        tx.commit()
+    }
+}
+
+impl Sink for RecordSink {
+    fn lossless(&self) -> bool {
+        true
+    }
+
+    fn handle(&mut self, event: &SinkEvent) {
+        let row = match event {
+            SinkEvent::Update { data } => self.target_id(&data.addr, &data.ip).map(|target_id| Row::Probe {
+                target_id,
+                ts: data.last_timestamp.unwrap_or_else(Local::now).timestamp_millis(),
+                seq: data.last_seq as i64,
+                rtt: (data.last_attr >= 0.0).then_some(data.last_attr),
+                anomaly: data.last_attr >= 0.0 && data.anomalies.back().copied().unwrap_or(false),
+            }),
+            SinkEvent::Transition { transition, .. } => {
+                self.target_id(&transition.addr, &transition.ip).map(|target_id| Row::Transition {
+                    target_id,
+                    ts: transition.at.timestamp_millis(),
+                    from: transition.from.as_str().to_string(),
+                    to: transition.to.as_str().to_string(),
+                    reason: transition.reason.clone(),
+                })
+            }
+            // the new address gets a target row of its own with its first result
+            SinkEvent::DnsChange { .. } => None,
+            SinkEvent::ProbeError { addr, ip, message } => self.target_id(addr, ip).map(|target_id| Row::Error {
+                target_id,
+                ts: Local::now().timestamp_millis(),
+                message: message.clone(),
+            }),
+        };
+        self.pending.extend(row);
+    }
+
+    fn flush(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        match self.write_pending() {
+            Ok(()) => {
+                self.pending.clear();
+                self.failing = false;
+            }
+            Err(err) => {
+                // keep the rows for the next try, the disk may be full for a moment
+                if !self.failing {
+                    lock(&self.errs).push(format!("recording failed: {}", err));
+                }
+                self.failing = true;
+                if self.pending.len() > MAX_PENDING_ROWS {
+                    let excess = self.pending.len() - MAX_PENDING_ROWS;
+                    self.pending.drain(..excess);
+                    self.dropped += excess;
+                }
+            }
+        }
+    }
+}
+
+impl Drop for RecordSink {
+    fn drop(&mut self) {
+        // the final flush failed as well
+        self.dropped += self.pending.len();
+        if self.dropped > 0 {
+            lock(&self.errs).push(format!("recording is missing {} rows, the database could not be written", self.dropped));
+            write_dropped(&self.conn, self.session_id, self.dropped).ok();
+        }
+        self.conn
+            .execute(
+                "UPDATE session SET finished_at = ?1 WHERE id = ?2",
+                params![Local::now().timestamp_millis(), self.session_id],
+            )
+            .ok();
+    }
+}
+
+fn write_dropped(conn: &Connection, session_id: i64, dropped: usize) -> rusqlite::Result<usize> {
+    conn.execute(
+        "INSERT OR REPLACE INTO session_meta (session_id, key, value) VALUES (?1, 'dropped_rows', ?2)",
+        params![session_id, dropped.to_string()],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use crate::health::{HealthState, HealthTransition};
+    use crate::ip_data::IpData;
+
+    fn temp_db(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("nping-record-{}-{}.db", std::process::id(), name));
+        for suffix in ["", "-wal", "-shm"] {
+            std::fs::remove_file(format!("{}{}", path.display(), suffix)).ok();
+        }
+        path.to_string_lossy().into_owned()
+    }
+
+    fn result(seq: usize, rtt: f64) -> SinkEvent {
+        let mut data = IpData::new("example.com".into(), "192.0.2.1".into());
+        data.last_seq = seq;
+        data.last_attr = rtt;
+        SinkEvent::Update { data }
+    }
+
+    fn sink(path: &str) -> RecordSink {
+        let targets = [("example.com".to_string(), "192.0.2.1".to_string())];
+        let started = Local.timestamp_opt(1_700_000_000, 0).unwrap();
+        RecordSink::new(path, &targets, &[("hostname", "probe-1".into())], started, Arc::new(Mutex::new(Vec::new()))).unwrap()
+    }
+
+    fn count(conn: &Connection, sql: &str) -> i64 {
+        conn.query_row(sql, [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn records_results_transitions_and_errors() {
+        let path = temp_db("session");
+        let mut sink = sink(&path);
+        sink.handle(&result(1, 12.5));
+        sink.handle(&result(2, -1.0));
+        let data = IpData::new("example.com".into(), "192.0.2.1".into());
+        let transition = HealthTransition {
+            addr: data.addr.clone(),
+            ip: data.ip.clone(),
+            from: HealthState::Ok,
+            to: HealthState::Warn,
+            at: Local::now(),
+            reason: "loss 50.00% > 20".into(),
+        };
+        sink.handle(&SinkEvent::Transition { transition, data });
+        sink.handle(&SinkEvent::ProbeError { addr: "new.example".into(), ip: "192.0.2.2".into(), message: "boom".into() });
+        sink.flush();
+        drop(sink);
+
+        let conn = open_database(&path).unwrap();
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM probe WHERE rtt_ms = 12.5"), 1);
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM probe WHERE rtt_ms IS NULL"), 1);
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM transition WHERE to_state = 'WARN'"), 1);
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM probe_error WHERE message = 'boom'"), 1);
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM target"), 2);
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM session WHERE finished_at IS NOT NULL"), 1);
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM session_meta WHERE value = 'probe-1'"), 1);
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM session_meta WHERE key = 'dropped_rows'"), 0);
+    }
+
+    #[test]
+    fn rows_are_kept_until_the_database_can_be_written() {
+        let path = temp_db("dropped");
+        let mut sink = sink(&path);
+        let other = Connection::open(&path).unwrap();
+        other.execute_batch("DROP TABLE probe").unwrap();
+
+        for seq in 0..MAX_PENDING_ROWS + 5 {
+            sink.handle(&result(seq, 10.0));
+        }
+        sink.flush();
+        assert_eq!(sink.pending.len(), MAX_PENDING_ROWS);
+
+        // the disk is back, the rest is written with the count of the lost ones
+        other.execute_batch(SCHEMA).unwrap();
+        sink.flush();
+        drop(sink);
+        assert_eq!(count(&other, "SELECT COUNT(*) FROM probe"), MAX_PENDING_ROWS as i64);
+        assert_eq!(count(&other, "SELECT MIN(seq) FROM probe"), 5);
+        assert_eq!(count(&other, "SELECT CAST(value AS INTEGER) FROM session_meta WHERE key = 'dropped_rows'"), 5);
+    }
+}
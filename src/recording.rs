@@ -0,0 +1,421 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use chrono::{DateTime, Local, TimeZone};
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use serde_json::Value;
+use crate::output::meta_path;
+use crate::ping_event::PingEvent;
+
+const SQLITE_MAGIC: &[u8] = b"SQLite format 3\0";
+
+/// The results of a past run, read back from an output file or a --record database
+pub struct Recording {
+    pub(crate) targets: Vec<(String, String)>, // addr, ip in the order they were given
+    // every result in time order
+    pub(crate) events: Vec<(DateTime<Local>, PingEvent)>,
+    pub(crate) started_at: DateTime<Local>,
+    // session metadata as written with the recording, may be empty
+    pub(crate) metadata: Vec<(String, String)>,
+}
+
+impl Recording {
+    pub fn finished_at(&self) -> DateTime<Local> {
+        self.events.last().map(|(at, _)| *at).unwrap_or(self.started_at)
+    }
+
+    // targets in the order they were given or first show up, and results sorted by time
+    fn from_events(mut events: Vec<(DateTime<Local>, PingEvent)>, metadata: Vec<(String, String)>) -> Result<Self, String> {
+        events.sort_by_key(|(at, _)| *at);
+        let mut targets: Vec<(String, String)> = Vec::new();
+        for (_, event) in &events {
+            let target = event_target(event);
+            if !targets.iter().any(|(addr, ip)| (addr.as_str(), ip.as_str()) == target) {
+                targets.push((target.0.to_string(), target.1.to_string()));
+            }
+        }
+        // results of several targets arrive in any order, the metadata knows how they were given
+        if let Some((_, given)) = metadata.iter().find(|(key, _)| key == "targets") {
+            let given: Vec<&str> = given.split_whitespace().collect();
+            targets.sort_by_key(|(addr, _)| given.iter().position(|g| g == addr).unwrap_or(usize::MAX));
+        }
+        let started_at = metadata
+            .iter()
+            .find(|(key, _)| key == "start_time")
+            .and_then(|(_, value)| DateTime::parse_from_rfc3339(value).ok())
+            .map(|at| at.with_timezone(&Local))
+            .or_else(|| events.first().map(|(at, _)| *at))
+            .ok_or("the recording has no results")?;
+        Ok(Self { targets, events, started_at, metadata })
+    }
+}
+
+fn event_target(event: &PingEvent) -> (&str, &str) {
+    match event {
+        PingEvent::Success { addr, ip, .. } | PingEvent::Timeout { addr, ip, .. } | PingEvent::Error { addr, ip, .. } => {
+            (addr, ip)
+        }
+    }
+}
+
+/// Read a JSONL or CSV output file or a SQLite recording, `session` picks one of the
+/// sessions of a SQLite recording, the latest by default
+pub fn load_recording(path: &str, session: Option<i64>) -> Result<Recording, String> {
+    let mut magic = [0u8; 16];
+    let is_sqlite = File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok_and(|_| magic == SQLITE_MAGIC);
+    if is_sqlite {
+        return load_sqlite(path, session);
+    }
+    if session.is_some() {
+        return Err("--session only applies to SQLite recordings".to_string());
+    }
+
+    let file = File::open(path).map_err(|e| format!("failed to open {}: {}", path, e))?;
+    let lines: Vec<String> = BufReader::new(file)
+        .lines()
+        .collect::<Result<_, _>>()
+        .map_err(|e| format!("failed to read {}: {}", path, e))?;
+    let first = lines.iter().map(|l| l.trim()).find(|l| !l.is_empty() && !l.starts_with('#')).unwrap_or("");
+    if first.starts_with('{') {
+        load_jsonl(&lines)
+    } else if first.starts_with("timestamp,") {
+        // the metadata of a csv file is kept next to it, older files have it inline
+        let meta = std::fs::read_to_string(meta_path(path)).unwrap_or_default();
+        let lines: Vec<String> = meta.lines().map(str::to_string).chain(lines).collect();
+        load_csv(&lines)
+    } else {
+        Err(format!("{} is not a recording, use --format jsonl or csv with --output, or --record", path))
+    }
+}
+
+fn parse_time(value: &str) -> Option<DateTime<Local>> {
+    DateTime::parse_from_rfc3339(value).ok().map(|at| at.with_timezone(&Local))
+}
+
+fn load_jsonl(lines: &[String]) -> Result<Recording, String> {
+    let mut events = Vec::new();
+    let mut metadata = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: Value = serde_json::from_str(line).map_err(|e| format!("line {}: {}", i + 1, e))?;
+        match record["type"].as_str() {
+            // appending adds a meta record per run, the first one describes the file
+            Some("meta") if metadata.is_empty() => {
+                if let Some(fields) = record.as_object() {
+                    metadata = fields
+                        .iter()
+                        .filter(|(key, _)| *key != "type")
+                        .map(|(key, value)| (key.clone(), value.as_str().unwrap_or_default().to_string()))
+                        .collect();
+                }
+            }
+            Some("ping") => {
+                let field = |name: &str| record[name].as_str().map(str::to_string);
+                let (Some(timestamp), Some(addr), Some(ip)) =
+                    (field("timestamp").as_deref().and_then(parse_time), field("target"), field("ip"))
+                else {
+                    return Err(format!("line {}: incomplete ping record", i + 1));
+                };
+                let seq = record["seq"].as_u64().unwrap_or_default() as usize;
+                let event = match record["rtt_ms"].as_f64() {
+                    Some(rtt) => PingEvent::Success { addr, ip, rtt, seq, timestamp },
+                    None => PingEvent::Timeout { addr, ip, seq, timestamp },
+                };
+                events.push((timestamp, event));
+            }
+            _ => {}
+        }
+    }
+    Recording::from_events(events, metadata)
+}
+
+fn load_csv(lines: &[String]) -> Result<Recording, String> {
+    let mut events = Vec::new();
+    let mut metadata = Vec::new();
+    let mut columns: HashMap<String, usize> = HashMap::new();
+    for (i, line) in lines.iter().enumerate() {
+        if let Some(meta) = line.strip_prefix("# ") {
+            if let Some((key, value)) = meta.split_once(": ") {
+                if !metadata.iter().any(|(k, _): &(String, String)| k == key) {
+                    metadata.push((key.to_string(), value.to_string()));
+                }
+            }
+            continue;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields = split_csv(line);
+        if fields.first().is_some_and(|f| f == "timestamp") {
+            if fields.iter().any(|f| f == "sent") {
+                return Err("csv files written with --summary-interval only hold aggregates and cannot be replayed".to_string());
+            }
+            columns = fields.iter().enumerate().map(|(i, f)| (f.clone(), i)).collect();
+            continue;
+        }
+        let get = |name: &str| columns.get(name).and_then(|&i| fields.get(i)).map(String::as_str);
+        let (Some(timestamp), Some(addr), Some(ip)) = (get("timestamp").and_then(parse_time), get("target"), get("ip")) else {
+            return Err(format!("line {}: incomplete row", i + 1));
+        };
+        let (addr, ip) = (addr.to_string(), ip.to_string());
+        let seq = get("seq").and_then(|s| s.parse().ok()).unwrap_or_default();
+        let event = match get("rtt_ms").and_then(|s| s.parse::<f64>().ok()) {
+            Some(rtt) if get("status") == Some("ok") => PingEvent::Success { addr, ip, rtt, seq, timestamp },
+            _ => PingEvent::Timeout { addr, ip, seq, timestamp },
+        };
+        events.push((timestamp, event));
+    }
+    Recording::from_events(events, metadata)
+}
+
+// fields of a csv row, quotes as written by CsvWriter
+fn split_csv(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+fn load_sqlite(path: &str, session: Option<i64>) -> Result<Recording, String> {
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| format!("failed to open {}: {}", path, e))?;
+    let read = |e: rusqlite::Error| format!("failed to read {}: {}", path, e);
+
+    let session_id: i64 = match session {
+        Some(id) => id,
+        None => conn
+            .query_row("SELECT max(id) FROM session", [], |row| row.get::<_, Option<i64>>(0))
+            .map_err(read)?
+            .ok_or_else(|| format!("{} holds no sessions", path))?,
+    };
+    let started_at: i64 = conn
+        .query_row("SELECT started_at FROM session WHERE id = ?1", params![session_id], |row| row.get(0))
+        .optional()
+        .map_err(read)?
+        .ok_or_else(|| format!("{} has no session {}", path, session_id))?;
+
+    let mut stmt = conn.prepare("SELECT key, value FROM session_meta WHERE session_id = ?1 ORDER BY rowid").map_err(read)?;
+    let metadata = stmt
+        .query_map(params![session_id], |row| Ok((row.get(0)?, row.get(1)?)))
+        .and_then(|rows| rows.collect::<Result<Vec<(String, String)>, _>>())
+        .map_err(read)?;
+
+    let mut stmt = conn.prepare("SELECT id, addr, ip FROM target WHERE session_id = ?1 ORDER BY id").map_err(read)?;
+    let rows = stmt
+        .query_map(params![session_id], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?)))
+        .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+        .map_err(read)?;
+    let targets: Vec<(String, String)> = rows.iter().map(|(_, addr, ip)| (addr.clone(), ip.clone())).collect();
+    let target_by_id: HashMap<i64, (String, String)> = rows.into_iter().map(|(id, addr, ip)| (id, (addr, ip))).collect();
+
+    let mut events = Vec::new();
+    let mut stmt = conn
+        .prepare(
+            "SELECT p.target_id, p.ts, p.seq, p.rtt_ms FROM probe p JOIN target t ON t.id = p.target_id
+             WHERE t.session_id = ?1 ORDER BY p.ts",
+        )
+        .map_err(read)?;
+    let probes = stmt
+        .query_map(params![session_id], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?, row.get::<_, i64>(2)?, row.get::<_, Option<f64>>(3)?))
+        })
+        .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+        .map_err(read)?;
+    for (target_id, ts, seq, rtt) in probes {
+        let (Some((addr, ip)), Some(timestamp)) = (target_by_id.get(&target_id).cloned(), from_millis(ts)) else {
+            continue;
+        };
+        let seq = seq as usize;
+        let event = match rtt {
+            Some(rtt) => PingEvent::Success { addr, ip, rtt, seq, timestamp },
+            None => PingEvent::Timeout { addr, ip, seq, timestamp },
+        };
+        events.push((timestamp, event));
+    }
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT e.target_id, e.ts, e.message FROM probe_error e JOIN target t ON t.id = e.target_id
+             WHERE t.session_id = ?1 ORDER BY e.ts",
+        )
+        .map_err(read)?;
+    let errors = stmt
+        .query_map(params![session_id], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?, row.get::<_, String>(2)?)))
+        .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+        .map_err(read)?;
+    for (target_id, ts, message) in errors {
+        let (Some((addr, ip)), Some(at)) = (target_by_id.get(&target_id).cloned(), from_millis(ts)) else {
+            continue;
+        };
+        events.push((at, PingEvent::Error { addr, ip, message }));
+    }
+
+    let mut recording = Recording::from_events(events, metadata)?;
+    // keep targets without a single result, and the order they were given in
+    recording.targets = targets;
+    recording.started_at = from_millis(started_at).unwrap_or(recording.started_at);
+    Ok(recording)
+}
+
+fn from_millis(ms: i64) -> Option<DateTime<Local>> {
+    Local.timestamp_millis_opt(ms).single()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use crate::ip_data::IpData;
+    use crate::output::{Changes, OpenMode, OutputFile, OutputFormat, OutputOptions};
+    use crate::record::RecordSink;
+    use crate::sinks::{Sink, SinkEvent};
+
+    fn at(secs: i64) -> DateTime<Local> {
+        Local.timestamp_opt(1_700_000_000 + secs, 0).unwrap()
+    }
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("nping-recording-{}-{}", std::process::id(), name));
+        let path = path.to_string_lossy().into_owned();
+        for suffix in ["", "-wal", "-shm", ".meta"] {
+            std::fs::remove_file(format!("{}{}", path, suffix)).ok();
+        }
+        path
+    }
+
+    // a reply of example.com at second 1, a timeout of example.org at 2 and a reply of example.com at 3
+    fn results() -> Vec<IpData> {
+        [("example.com", "192.0.2.1", 1, 12.5), ("example.org", "192.0.2.2", 2, -1.0), ("example.com", "192.0.2.1", 3, 20.0)]
+            .into_iter()
+            .map(|(addr, ip, secs, rtt)| {
+                let mut data = IpData::new(addr.to_string(), ip.to_string());
+                data.last_seq = secs as usize;
+                data.last_attr = rtt;
+                data.last_timestamp = Some(at(secs));
+                data
+            })
+            .collect()
+    }
+
+    fn check_events(recording: &Recording) {
+        assert_eq!(recording.events.len(), 3);
+        assert!(matches!(
+            &recording.events[0],
+            (time, PingEvent::Success { addr, rtt, seq: 1, .. }) if *time == at(1) && addr == "example.com" && *rtt == 12.5
+        ));
+        assert!(matches!(
+            &recording.events[1],
+            (time, PingEvent::Timeout { ip, seq: 2, .. }) if *time == at(2) && ip == "192.0.2.2"
+        ));
+        assert_eq!(recording.finished_at(), at(3));
+    }
+
+    #[test]
+    fn jsonl_round_trip() {
+        let path = temp_path("session.jsonl");
+        let options = OutputOptions {
+            path: path.clone(),
+            format: OutputFormat::Jsonl,
+            mode: OpenMode::Create,
+            rotate_size: None,
+            stats_interval: Duration::ZERO,
+            summary_interval: Duration::ZERO,
+            metadata: vec![("start_time", at(0).to_rfc3339())],
+        };
+        let mut output = OutputFile::create(options, Arc::new(Mutex::new(Vec::new()))).unwrap();
+        for data in results() {
+            output.write_update(&data, &Changes::default());
+        }
+        drop(output);
+
+        let recording = load_recording(&path, None).unwrap();
+        check_events(&recording);
+        assert_eq!(recording.targets, [
+            ("example.com".to_string(), "192.0.2.1".to_string()),
+            ("example.org".to_string(), "192.0.2.2".to_string()),
+        ]);
+        assert_eq!(recording.started_at, at(0));
+        assert!(load_recording(&path, Some(1)).is_err());
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let path = temp_path("session.csv");
+        let options = OutputOptions {
+            path: path.clone(),
+            format: OutputFormat::Csv,
+            mode: OpenMode::Create,
+            rotate_size: None,
+            stats_interval: Duration::ZERO,
+            summary_interval: Duration::ZERO,
+            metadata: vec![("start_time", at(0).to_rfc3339())],
+        };
+        let mut output = OutputFile::create(options, Arc::new(Mutex::new(Vec::new()))).unwrap();
+        for data in results() {
+            output.write_update(&data, &Changes::default());
+        }
+        drop(output);
+
+        let recording = load_recording(&path, None).unwrap();
+        check_events(&recording);
+        // the start time comes from the sidecar metadata file
+        assert_eq!(recording.started_at, at(0));
+        assert_eq!(recording.metadata, [("start_time".to_string(), at(0).to_rfc3339())]);
+    }
+
+    #[test]
+    fn sqlite_round_trip() {
+        let path = temp_path("session.db");
+        let targets = [
+            ("example.com".to_string(), "192.0.2.1".to_string()),
+            ("example.org".to_string(), "192.0.2.2".to_string()),
+            ("idle.example".to_string(), "192.0.2.3".to_string()),
+        ];
+        let mut sink = RecordSink::new(&path, &targets, &[], at(0), Arc::new(Mutex::new(Vec::new()))).unwrap();
+        for data in results() {
+            sink.handle(&SinkEvent::Update { data });
+        }
+        sink.flush();
+        drop(sink);
+
+        let recording = load_recording(&path, None).unwrap();
+        check_events(&recording);
+        // targets without results are kept, in the order they were given
+        assert_eq!(recording.targets, targets);
+        assert_eq!(recording.started_at, at(0));
+        assert!(load_recording(&path, Some(2)).is_err());
+    }
+
+    #[test]
+    fn other_files_are_refused() {
+        let path = temp_path("other.txt");
+        std::fs::write(&path, "example.com 192.0.2.1 12.50ms\n").unwrap();
+        assert!(load_recording(&path, None).err().unwrap().contains("is not a recording"));
+
+        std::fs::write(&path, "timestamp,target,ip,sent,received\n").unwrap();
+        assert!(load_recording(&path, None).err().unwrap().contains("cannot be replayed"));
+    }
+
+    #[test]
+    fn quoted_csv_fields() {
+        assert_eq!(split_csv(r#"a,"b,""c""",,d"#), ["a", "b,\"c\"", "", "d"]);
+    }
+}
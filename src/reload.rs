@@ -0,0 +1,501 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::task::{self, JoinHandle};
+use crate::config::TargetSettings;
+use crate::data_processor::TargetUpdate;
+use crate::error::{lock, NpingError};
+use crate::health::HealthRules;
+use crate::ip_data::IpData;
+use crate::network::{get_host_ipaddr, get_multiple_host_ipaddr, PingTask};
+use crate::ping_event::PingEvent;
+use crate::shutdown::Shutdown;
+use crate::sinks::TargetGroups;
+
+// how often the config file is checked for changes
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+// editors write a file in several steps, give them a moment before reading it
+const SETTLE_TIME: Duration = Duration::from_millis(200);
+
+// a ping task, it fails when ping itself cannot be started
+pub type PingHandle = JoinHandle<Result<(), NpingError>>;
+
+/// What a reload takes from the command line and the config file, other options need a restart
+pub struct ReloadSettings {
+    // host and shown name of every target
+    pub(crate) targets: Vec<(String, String)>,
+    // interval, count, ipv6 and group of targets in the config file, key: host
+    pub(crate) settings: HashMap<String, TargetSettings>,
+    pub(crate) rules: HealthRules,
+    // watched for changes
+    pub(crate) config_path: Option<PathBuf>,
+    // hostnames are resolved again this often, zero only on a reload
+    pub(crate) resolve_interval: Duration,
+}
+
+// a pinged IP, a host has several with --multiple
+struct PingTarget {
+    host: String,
+    addr: String,
+    ip: String,
+    stop: Shutdown,
+    task: PingHandle,
+}
+
+/// The ping tasks of a run, a reload stops some and starts others
+pub struct PingTasks {
+    count: usize,
+    interval: i32,
+    force_ipv6: bool,
+    multiple: i32,
+    // per target options of the config file, key: host
+    settings: HashMap<String, TargetSettings>,
+    groups: TargetGroups,
+    shutdown: Shutdown,
+    errs: Arc<Mutex<Vec<String>>>,
+    ping_event_tx: Arc<SyncSender<PingEvent>>,
+    targets: Vec<PingTarget>,
+    // tasks of removed targets, awaited at the end of the run
+    stopped: Vec<PingHandle>,
+}
+
+impl PingTasks {
+    pub fn new(
+        count: usize,
+        interval: i32,
+        (force_ipv6, multiple): (bool, i32),
+        shutdown: Shutdown,
+        errs: Arc<Mutex<Vec<String>>>,
+        ping_event_tx: Arc<SyncSender<PingEvent>>,
+    ) -> Self {
+        Self {
+            count,
+            interval,
+            force_ipv6,
+            multiple,
+            settings: HashMap::new(),
+            groups: TargetGroups::default(),
+            shutdown,
+            errs,
+            ping_event_tx,
+            targets: Vec::new(),
+            stopped: Vec::new(),
+        }
+    }
+
+    /// options of single targets from the config file, the group of each started target is set in `groups`
+    pub fn with_targets(mut self, settings: HashMap<String, TargetSettings>, groups: TargetGroups) -> Self {
+        self.settings = settings;
+        self.groups = groups;
+        self
+    }
+
+    // whether a host is resolved to an IPv6 address
+    fn ipv6(&self, host: &str) -> bool {
+        self.settings.get(host).and_then(|s| s.ipv6).unwrap_or(self.force_ipv6)
+    }
+
+    /// start pinging an IP of a host, targets given at start have a runtime worker of their own,
+    /// later ones get a blocking thread as the workers are all taken
+    pub fn start(&mut self, host: &str, addr: &str, ip: &str, later: bool) {
+        let settings = self.settings.get(host).cloned().unwrap_or_default();
+        let count = settings.count.unwrap_or(self.count);
+        let interval = settings.interval.map_or(self.interval, interval_ms);
+        self.groups.set(addr, settings.group.as_deref());
+
+        let stop = Shutdown::new();
+        let ping = PingTask::new(addr.to_string(), ip.to_string(), count, interval as u64, self.shutdown.clone(), self.errs.clone())
+            .with_stop(stop.clone());
+        let ping_event_tx = self.ping_event_tx.clone();
+        let task = if later {
+            let runtime = tokio::runtime::Handle::current();
+            task::spawn_blocking(move || runtime.block_on(ping.run(ping_event_tx)))
+        } else {
+            task::spawn(async move { ping.run(ping_event_tx).await })
+        };
+        self.targets.push(PingTarget { host: host.to_string(), addr: addr.to_string(), ip: ip.to_string(), stop, task });
+    }
+
+    /// stop the tasks of a target, or only the one of `ip`, returns the addr and ip of each
+    fn stop(&mut self, host: &str, addr: &str, ip: Option<&str>) -> Vec<(String, String)> {
+        let (stopped, kept) = std::mem::take(&mut self.targets)
+            .into_iter()
+            .partition(|t| t.host == host && t.addr == addr && ip.is_none_or(|ip| t.ip == ip));
+        self.targets = kept;
+        stopped
+            .into_iter()
+            .map(|target: PingTarget| {
+                target.stop.cancel();
+                self.stopped.push(target.task);
+                (target.addr, target.ip)
+            })
+            .collect()
+    }
+
+    // host and shown name of the targets, once per host
+    fn hosts(&self) -> Vec<(String, String)> {
+        let mut hosts: Vec<(String, String)> = Vec::new();
+        for target in &self.targets {
+            if !hosts.iter().any(|(host, addr)| *host == target.host && *addr == target.addr) {
+                hosts.push((target.host.clone(), target.addr.clone()));
+            }
+        }
+        hosts
+    }
+
+    // the pinged IPs of a target
+    fn ips(&self, host: &str, addr: &str) -> Vec<String> {
+        self.targets.iter().filter(|t| t.host == host && t.addr == addr).map(|t| t.ip.clone()).collect()
+    }
+
+    /// true once every task has ended
+    pub fn is_finished(&self) -> bool {
+        self.targets.iter().all(|t| t.task.is_finished())
+    }
+
+    /// the handles of all tasks, to await them at the end of the run
+    pub fn take_tasks(&mut self) -> Vec<PingHandle> {
+        let mut tasks: Vec<PingHandle> = std::mem::take(&mut self.targets).into_iter().map(|t| t.task).collect();
+        tasks.append(&mut self.stopped);
+        tasks
+    }
+}
+
+/// the ping interval in milliseconds of an interval in seconds, 0 pings twice a second
+pub fn interval_ms(secs: i32) -> i32 {
+    if secs == 0 {
+        500
+    } else {
+        secs * 1000
+    }
+}
+
+fn modified(path: Option<&Path>) -> Option<SystemTime> {
+    std::fs::metadata(path?).and_then(|m| m.modified()).ok()
+}
+
+/// Reload the targets and thresholds when a signal arrives on the returned sender or the
+/// config file changes, the data of unchanged targets is kept
+pub fn start_reloader(
+    load: impl Fn() -> Result<ReloadSettings, String> + Send + 'static,
+    (config_path, resolve_interval): (Option<PathBuf>, Duration),
+    tasks: Arc<Mutex<PingTasks>>,
+    ip_data: Arc<Mutex<Vec<IpData>>>,
+    updates_tx: Sender<TargetUpdate>,
+    shutdown: Shutdown,
+    errs: Arc<Mutex<Vec<String>>>,
+) -> Sender<()> {
+    let (reload_tx, reload_rx) = mpsc::channel::<()>();
+    let runtime = tokio::runtime::Handle::current();
+    std::thread::spawn(move || {
+        // new ping tasks are spawned on the runtime of the run
+        let _guard = runtime.enter();
+        let (mut config_path, mut resolve_interval) = (config_path, resolve_interval);
+        let mut last_modified = modified(config_path.as_deref());
+        let mut last_resolved = Instant::now();
+        while !shutdown.is_cancelled() {
+            match reload_rx.recv_timeout(WATCH_INTERVAL) {
+                Ok(()) => {}
+                Err(RecvTimeoutError::Timeout) => {
+                    if !resolve_interval.is_zero() && last_resolved.elapsed() >= resolve_interval {
+                        let hosts = lock(&tasks).hosts();
+                        let messages = follow_dns(&hosts, &tasks, &ip_data, &updates_tx);
+                        lock(&errs).extend(messages);
+                        last_resolved = Instant::now();
+                    }
+                    let now = modified(config_path.as_deref());
+                    if now.is_none() || now == last_modified {
+                        continue;
+                    }
+                    std::thread::sleep(SETTLE_TIME);
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            match load() {
+                Ok(settings) => {
+                    // new targets are started with their options, running ones keep theirs
+                    lock(&tasks).settings = settings.settings;
+                    let messages = apply(settings.targets, &tasks, &ip_data, &updates_tx);
+                    updates_tx.send(TargetUpdate::Rules(settings.rules)).ok();
+                    lock(&errs).extend(messages);
+                    config_path = settings.config_path;
+                    resolve_interval = settings.resolve_interval;
+                }
+                Err(err) => lock(&errs).push(format!("reload failed, nothing changed: {}", err)),
+            }
+            last_modified = modified(config_path.as_deref());
+        }
+    });
+    reload_tx
+}
+
+// stop the removed targets, start the new ones, rename those with a new name and follow
+// DNS changes of the others, targets are told apart by their host, returns what was done
+fn apply(
+    wanted: Vec<(String, String)>,
+    tasks: &Arc<Mutex<PingTasks>>,
+    ip_data: &Arc<Mutex<Vec<IpData>>>,
+    updates_tx: &Sender<TargetUpdate>,
+) -> Vec<String> {
+    let (current, multiple) = {
+        let tasks = lock(tasks);
+        (tasks.hosts(), tasks.multiple)
+    };
+    let has = |targets: &[(String, String)], host: &str| targets.iter().any(|(h, _)| h == host);
+    let removed: Vec<&(String, String)> = current.iter().filter(|(host, _)| !has(&wanted, host)).collect();
+    let added: Vec<&(String, String)> = wanted.iter().filter(|(host, _)| !has(&current, host)).collect();
+    let renamed: Vec<(&String, &String, &String)> = current
+        .iter()
+        .filter_map(|(host, addr)| wanted.iter().find(|(h, name)| h == host && name != addr).map(|(_, name)| (host, addr, name)))
+        .collect();
+
+    for (host, addr) in &removed {
+        stop(tasks, ip_data, updates_tx, host, addr, None);
+    }
+    for (host, addr, name) in &renamed {
+        rename(tasks, ip_data, updates_tx, host, addr, name);
+    }
+    let kept: Vec<(String, String)> = wanted.iter().filter(|(host, _)| has(&current, host)).cloned().collect();
+
+    let mut failed = Vec::new();
+    for (host, addr) in &added {
+        let force_ipv6 = lock(tasks).ipv6(host);
+        // resolving may take a while, the tasks are not locked meanwhile
+        let ips = if wanted.len() == 1 && multiple > 0 {
+            get_multiple_host_ipaddr(host, force_ipv6, multiple as usize)
+        } else {
+            get_host_ipaddr(host, force_ipv6).map(|ip| vec![ip])
+        };
+        let ips = match ips {
+            Ok(ips) => ips,
+            Err(err) => {
+                failed.push(format!("{} ({})", host, err));
+                continue;
+            }
+        };
+        for ip in ips {
+            start(tasks, ip_data, updates_tx, host, addr, &ip);
+        }
+    }
+
+    let mut message = format!("config reloaded, {} added, {} removed", added.len() - failed.len(), removed.len());
+    if !renamed.is_empty() {
+        message.push_str(&format!(", {} renamed", renamed.len()));
+    }
+    if !failed.is_empty() {
+        message.push_str(&format!(", failed to add {}", failed.join(", ")));
+    }
+    let mut messages = vec![message];
+    messages.extend(follow_dns(&kept, tasks, ip_data, updates_tx));
+    messages
+}
+
+/// resolve hostnames again and move their ping tasks to addresses they no longer have,
+/// returns a message per change
+fn follow_dns(
+    hosts: &[(String, String)],
+    tasks: &Arc<Mutex<PingTasks>>,
+    ip_data: &Arc<Mutex<Vec<IpData>>>,
+    updates_tx: &Sender<TargetUpdate>,
+) -> Vec<String> {
+    let mut messages = Vec::new();
+    for (host, addr) in hosts {
+        if host.parse::<IpAddr>().is_ok() {
+            continue;
+        }
+        let force_ipv6 = lock(tasks).ipv6(host);
+        // a failed lookup keeps the old addresses, the pings will tell whether they still work
+        let Ok(resolved) = get_multiple_host_ipaddr(host, force_ipv6, usize::MAX) else {
+            continue;
+        };
+        let old_ips = lock(tasks).ips(host, addr);
+        // hosts with several addresses hand them out in any order, only follow addresses that are gone
+        let kept: Vec<String> = old_ips.iter().filter(|ip| resolved.contains(ip)).cloned().collect();
+        if kept.len() == old_ips.len() {
+            continue;
+        }
+        let new_ips: Vec<String> = resolved
+            .into_iter()
+            .filter(|ip| !old_ips.contains(ip))
+            .take(old_ips.len() - kept.len())
+            .collect();
+
+        for ip in old_ips.iter().filter(|ip| !kept.contains(ip)) {
+            stop(tasks, ip_data, updates_tx, host, addr, Some(ip));
+        }
+        for ip in &new_ips {
+            start(tasks, ip_data, updates_tx, host, addr, ip);
+        }
+        let old_ips: Vec<String> = old_ips.into_iter().filter(|ip| !kept.contains(ip)).collect();
+        messages.push(format!("{} now resolves to {} instead of {}", host, new_ips.join(", "), old_ips.join(", ")));
+        updates_tx.send(TargetUpdate::DnsChange { addr: addr.clone(), old_ips, new_ips }).ok();
+    }
+    messages
+}
+
+fn start(
+    tasks: &Arc<Mutex<PingTasks>>,
+    ip_data: &Arc<Mutex<Vec<IpData>>>,
+    updates_tx: &Sender<TargetUpdate>,
+    host: &str,
+    addr: &str,
+    ip: &str,
+) {
+    // the processor and the ui know the target before its first result arrives
+    updates_tx.send(TargetUpdate::Add { addr: addr.to_string(), ip: ip.to_string() }).ok();
+    lock(ip_data).push(IpData::new(addr.to_string(), ip.to_string()));
+    lock(tasks).start(host, addr, ip, true);
+}
+
+// the pings of a host go on under its new name, the processor and ui keep its data
+fn rename(
+    tasks: &Arc<Mutex<PingTasks>>,
+    ip_data: &Arc<Mutex<Vec<IpData>>>,
+    updates_tx: &Sender<TargetUpdate>,
+    host: &str,
+    addr: &str,
+    name: &str,
+) {
+    updates_tx.send(TargetUpdate::Rename { addr: addr.to_string(), name: name.to_string() }).ok();
+    // results carry the name, the tasks start again with the new one
+    let stopped = lock(tasks).stop(host, addr, None);
+    for (_, ip) in stopped {
+        for data in lock(ip_data).iter_mut().filter(|d| d.addr == addr && d.ip == ip) {
+            data.addr = name.to_string();
+        }
+        lock(tasks).start(host, name, &ip, true);
+    }
+}
+
+fn stop(
+    tasks: &Arc<Mutex<PingTasks>>,
+    ip_data: &Arc<Mutex<Vec<IpData>>>,
+    updates_tx: &Sender<TargetUpdate>,
+    host: &str,
+    addr: &str,
+    ip: Option<&str>,
+) {
+    for (addr, ip) in lock(tasks).stop(host, addr, ip) {
+        lock(ip_data).retain(|d| !(d.addr == addr && d.ip == ip));
+        updates_tx.send(TargetUpdate::Remove { addr, ip }).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // host and shown name of a target
+    fn target(host: &str, name: &str) -> (String, String) {
+        (host.to_string(), name.to_string())
+    }
+
+    fn tasks_with(targets: &[(&str, &str)], shutdown: &Shutdown) -> (Arc<Mutex<PingTasks>>, Arc<Mutex<Vec<IpData>>>) {
+        let (ping_event_tx, _ping_event_rx) = mpsc::sync_channel(100);
+        let tasks = Arc::new(Mutex::new(PingTasks::new(
+            0,
+            1000,
+            (false, 0),
+            shutdown.clone(),
+            Arc::new(Mutex::new(Vec::new())),
+            Arc::new(ping_event_tx),
+        )));
+        let ip_data = Arc::new(Mutex::new(Vec::new()));
+        for (host, name) in targets {
+            tasks.lock().unwrap().start(host, name, host, false);
+            let mut data = IpData::new(name.to_string(), host.to_string());
+            data.received = 5;
+            ip_data.lock().unwrap().push(data);
+        }
+        (tasks, ip_data)
+    }
+
+    #[test]
+    fn added_targets_start_and_removed_ones_stop() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let _guard = runtime.enter();
+        let shutdown = Shutdown::new();
+        let (tasks, ip_data) = tasks_with(&[("127.0.0.1", "one"), ("127.0.0.2", "two")], &shutdown);
+
+        let (updates_tx, updates_rx) = mpsc::channel();
+        let messages = apply(vec![target("127.0.0.2", "two"), target("127.0.0.3", "three")], &tasks, &ip_data, &updates_tx);
+        assert_eq!(messages, ["config reloaded, 1 added, 1 removed"]);
+        assert_eq!(tasks.lock().unwrap().hosts(), [target("127.0.0.2", "two"), target("127.0.0.3", "three")]);
+
+        // the kept target still has its results
+        let data: Vec<(String, usize)> = ip_data.lock().unwrap().iter().map(|d| (d.ip.clone(), d.received)).collect();
+        assert_eq!(data, [("127.0.0.2".to_string(), 5), ("127.0.0.3".to_string(), 0)]);
+        let updates: Vec<TargetUpdate> = updates_rx.try_iter().collect();
+        assert!(matches!(&updates[..], [TargetUpdate::Remove { ip: removed, .. }, TargetUpdate::Add { ip: added, .. }]
+            if removed == "127.0.0.1" && added == "127.0.0.3"));
+
+        shutdown.cancel();
+        runtime.shutdown_background();
+    }
+
+    #[test]
+    fn renamed_targets_keep_their_data() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let _guard = runtime.enter();
+        let shutdown = Shutdown::new();
+        let (tasks, ip_data) = tasks_with(&[("127.0.0.1", "one"), ("127.0.0.2", "two")], &shutdown);
+
+        let (updates_tx, updates_rx) = mpsc::channel();
+        let messages = apply(vec![target("127.0.0.1", "one"), target("127.0.0.2", "gateway")], &tasks, &ip_data, &updates_tx);
+        assert_eq!(messages, ["config reloaded, 0 added, 0 removed, 1 renamed"]);
+        assert_eq!(tasks.lock().unwrap().hosts(), [target("127.0.0.1", "one"), target("127.0.0.2", "gateway")]);
+
+        let data: Vec<(String, usize)> = ip_data.lock().unwrap().iter().map(|d| (d.addr.clone(), d.received)).collect();
+        assert_eq!(data, [("one".to_string(), 5), ("gateway".to_string(), 5)]);
+        let updates: Vec<TargetUpdate> = updates_rx.try_iter().collect();
+        assert!(matches!(&updates[..], [TargetUpdate::Rename { addr, name }] if addr == "two" && name == "gateway"));
+
+        shutdown.cancel();
+        runtime.shutdown_background();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn moved_hosts_get_new_tasks_and_a_dns_change() {
+        let (ping_event_tx, _ping_event_rx) = mpsc::sync_channel(16);
+        let shutdown = Shutdown::new();
+        let errs = Arc::new(Mutex::new(Vec::new()));
+        let tasks = Arc::new(Mutex::new(PingTasks::new(1, 1000, (false, 0), shutdown.clone(), errs, Arc::new(ping_event_tx))));
+        let ip_data = Arc::new(Mutex::new(vec![
+            IpData::new("local".into(), "192.0.2.9".into()),
+            IpData::new("192.0.2.1".into(), "192.0.2.1".into()),
+        ]));
+        lock(&tasks).start("localhost", "local", "192.0.2.9", true);
+        lock(&tasks).start("192.0.2.1", "192.0.2.1", "192.0.2.1", true);
+        let (updates_tx, updates_rx) = mpsc::channel();
+
+        let hosts = lock(&tasks).hosts();
+        let messages = follow_dns(&hosts, &tasks, &ip_data, &updates_tx);
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].starts_with("localhost now resolves to "));
+
+        let new_ip = lock(&tasks).ips("localhost", "local");
+        assert_eq!(new_ip.len(), 1);
+        assert!(new_ip[0].parse::<IpAddr>().unwrap().is_loopback());
+        let ips: Vec<String> = lock(&ip_data).iter().map(|d| d.ip.clone()).collect();
+        assert_eq!(ips, ["192.0.2.1".to_string(), new_ip[0].clone()]);
+
+        let updates: Vec<TargetUpdate> = updates_rx.try_iter().collect();
+        assert!(matches!(&updates[0], TargetUpdate::Remove { ip, .. } if ip == "192.0.2.9"));
+        assert!(matches!(&updates[1], TargetUpdate::Add { ip, .. } if *ip == new_ip[0]));
+        assert!(matches!(&updates[2], TargetUpdate::DnsChange { addr, old_ips, new_ips }
+            if addr == "local" && *old_ips == ["192.0.2.9"] && *new_ips == new_ip));
+
+        // nothing moves while the address is still there
+        assert!(follow_dns(&hosts, &tasks, &ip_data, &updates_tx).is_empty());
+        assert_eq!(updates_rx.try_iter().count(), 0);
+
+        shutdown.cancel();
+        let handles = lock(&tasks).take_tasks();
+        for task in handles {
+            task.await.ok();
+        }
+    }
+}
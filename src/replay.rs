@@ -0,0 +1,339 @@
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Local, TimeDelta};
+use clap::{Args, ValueEnum};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::prelude::{Color, Line, Span, Style};
+use ratatui::widgets::Paragraph;
+use crate::data_processor::{DataProcessor, ProcessorConfig};
+use crate::draw;
+use crate::error::NpingError;
+use crate::health::{parse_threshold_rule, HealthRules, ThresholdRule};
+use crate::outage::MAX_OUTAGE_HISTORY;
+use crate::recording::{load_recording, Recording};
+use crate::shutdown::{handle_signals, Shutdown};
+use crate::terminal::TerminalGuard;
+use crate::ui::utils::format_duration;
+
+// results processed per frame at max speed
+const MAX_EVENTS_PER_FRAME: usize = 2000;
+const SHORT_SEEK: TimeDelta = TimeDelta::seconds(10);
+const LONG_SEEK: TimeDelta = TimeDelta::minutes(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Speed {
+    #[value(name = "1x")]
+    Normal,
+    #[value(name = "10x")]
+    Fast,
+    #[value(name = "max")]
+    Max,
+}
+
+impl Speed {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Speed::Normal => "1x",
+            Speed::Fast => "10x",
+            Speed::Max => "max",
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct ReplayArgs {
+    #[arg(help = "JSONL or CSV output file, or SQLite database written with --record")]
+    file: String,
+
+    #[arg(long, help = "Session of a SQLite recording to replay, defaults to the latest")]
+    session: Option<i64>,
+
+    #[arg(short, long, default_value = "graph", help = "View mode graph/table/point/sparkline/outage")]
+    view_type: String,
+
+    #[arg(long, value_enum, default_value_t = Speed::Normal, help = "Playback speed to start with")]
+    speed: Speed,
+
+    #[arg(long, default_value_t = 3, help = "Number of consecutive timeouts counted as an outage")]
+    outage_threshold: usize,
+
+    #[arg(
+        long = "threshold",
+        value_parser = parse_threshold_rule,
+        help = "Health threshold [TARGET:]METRIC=WARN/CRIT, METRIC is loss/avg/p95/jitter/outage, can be repeated"
+    )]
+    thresholds: Vec<ThresholdRule>,
+
+    #[arg(long, default_value_t = 10.0, help = "Percent a metric has to fall below a threshold to leave a health state")]
+    hysteresis: f64,
+
+    #[arg(long, default_value_t = 0, help = "Seconds a new health state has to hold before it is entered")]
+    state_min_duration: u64,
+}
+
+// feeds the recorded results through a data processor as the playback time advances
+struct Player<'a> {
+    recording: &'a Recording,
+    config: &'a ProcessorConfig,
+    view_type: &'a str,
+    processor: DataProcessor,
+    // index of the next result to process
+    next: usize,
+    position: DateTime<Local>,
+    playing: bool,
+    speed: Speed,
+}
+
+impl<'a> Player<'a> {
+    fn new(recording: &'a Recording, config: &'a ProcessorConfig, view_type: &'a str, speed: Speed) -> Self {
+        Self {
+            recording,
+            config,
+            view_type,
+            processor: DataProcessor::new_at(&recording.targets, view_type, config, recording.started_at),
+            next: 0,
+            position: recording.started_at,
+            playing: true,
+            speed,
+        }
+    }
+
+    fn process_next(&mut self) {
+        let (_, event) = &self.recording.events[self.next];
+        self.processor.process_event(event.clone());
+        // nothing listens for them here
+        self.processor.take_transitions();
+        self.next += 1;
+    }
+
+    fn advance_to(&mut self, to: DateTime<Local>) {
+        while self.next < self.recording.events.len() && self.recording.events[self.next].0 <= to {
+            self.process_next();
+        }
+        self.position = to;
+    }
+
+    // going back means processing everything again from the start
+    fn seek(&mut self, to: DateTime<Local>) {
+        let to = to.clamp(self.recording.started_at, self.recording.finished_at());
+        if to < self.position {
+            self.processor = DataProcessor::new_at(&self.recording.targets, self.view_type, self.config, self.recording.started_at);
+            self.next = 0;
+        }
+        self.advance_to(to);
+    }
+
+    fn tick(&mut self, elapsed: Duration) {
+        if !self.playing {
+            return;
+        }
+        match self.speed {
+            Speed::Max => {
+                let end = (self.next + MAX_EVENTS_PER_FRAME).min(self.recording.events.len());
+                while self.next < end {
+                    self.process_next();
+                }
+                if let Some((at, _)) = self.recording.events.get(self.next.saturating_sub(1)) {
+                    self.position = self.position.max(*at);
+                }
+            }
+            speed => {
+                let factor = if speed == Speed::Fast { 10 } else { 1 };
+                let step = TimeDelta::from_std(elapsed * factor).unwrap_or_default();
+                self.advance_to(self.position + step);
+            }
+        }
+        if self.next >= self.recording.events.len() {
+            self.playing = false;
+            self.position = self.recording.finished_at();
+        }
+    }
+
+    // false when the user quits
+    fn handle_key(&mut self, code: KeyCode, modifiers: KeyModifiers) -> bool {
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Char('c') if modifiers == KeyModifiers::CONTROL => return false,
+            KeyCode::Char(' ') | KeyCode::Char('p') => {
+                // play again from the start once the end was reached
+                if !self.playing && self.next >= self.recording.events.len() {
+                    self.seek(self.recording.started_at);
+                }
+                self.playing = !self.playing;
+            }
+            KeyCode::Char('1') => self.speed = Speed::Normal,
+            KeyCode::Char('2') => self.speed = Speed::Fast,
+            KeyCode::Char('3') => self.speed = Speed::Max,
+            KeyCode::Right => self.seek(self.position + SHORT_SEEK),
+            KeyCode::Left => self.seek(self.position - SHORT_SEEK),
+            KeyCode::PageDown => self.seek(self.position + LONG_SEEK),
+            KeyCode::PageUp => self.seek(self.position - LONG_SEEK),
+            KeyCode::Home => self.seek(self.recording.started_at),
+            KeyCode::End => self.seek(self.recording.finished_at()),
+            _ => {}
+        }
+        true
+    }
+
+    fn status_line(&self) -> Line<'static> {
+        let total = (self.recording.finished_at() - self.recording.started_at).to_std().unwrap_or_default();
+        let done = (self.position - self.recording.started_at).to_std().unwrap_or_default();
+        let percent = if total.is_zero() { 100.0 } else { done.as_secs_f64() / total.as_secs_f64() * 100.0 };
+        let state = if self.playing { "▶" } else { "⏸" };
+        Line::from(vec![
+            Span::styled(format!(" {} {:>3} ", state, self.speed.as_str()), Style::default().fg(Color::Black).bg(Color::Cyan)),
+            Span::raw(format!(
+                " {}  {} / {} ({:.0}%)   ",
+                self.position.format("%Y-%m-%d %H:%M:%S"),
+                format_duration(done),
+                format_duration(total),
+                percent
+            )),
+            Span::styled(
+                "space play/pause  1/2/3 speed 1x/10x/max  ←/→ 10s  PgUp/PgDn 5m  Home/End  q quit",
+                Style::default().fg(Color::DarkGray),
+            ),
+        ])
+    }
+}
+
+/// play a recording back in the ui
+pub fn run_replay(args: ReplayArgs) -> Result<(), NpingError> {
+    let recording = load_recording(&args.file, args.session).map_err(NpingError::Output)?;
+    let config = ProcessorConfig {
+        outage_threshold: args.outage_threshold,
+        health_rules: HealthRules::new(&args.thresholds, args.hysteresis, Duration::from_secs(args.state_min_duration)),
+        anomaly_sensitivity: 3.0,
+        baseline_warmup: 20,
+        outage_history: MAX_OUTAGE_HISTORY,
+    };
+    let mut player = Player::new(&recording, &config, &args.view_type, args.speed);
+
+    // Ctrl+C is a key press in the ui, SIGINT and SIGTERM from elsewhere stop the replay as well
+    let shutdown = Shutdown::new();
+    let signals = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_all()
+        .build()
+        .map_err(|e| NpingError::Internal(format!("failed to start the runtime: {}", e)))?;
+    {
+        let _runtime = signals.enter();
+        handle_signals(shutdown.clone());
+    }
+
+    let terminal = draw::init_terminal().map_err(|e| NpingError::Terminal(e.to_string()))?;
+    let mut guard = TerminalGuard::new(terminal);
+    draw::set_panic_hook(shutdown.clone());
+    let terminal = guard.terminal.as_mut().ok_or_else(|| NpingError::Terminal("the terminal is gone".to_string()))?;
+    let terminal_error = |e: std::io::Error| NpingError::Terminal(e.to_string());
+    let mut last_tick = Instant::now();
+    while !shutdown.is_cancelled() {
+        if event::poll(Duration::from_millis(50)).map_err(terminal_error)? {
+            if let Event::Key(key) = event::read().map_err(terminal_error)? {
+                if key.kind == KeyEventKind::Press && !player.handle_key(key.code, key.modifiers) {
+                    break;
+                }
+            }
+        }
+        player.tick(last_tick.elapsed());
+        last_tick = Instant::now();
+
+        let ip_data = player.processor.snapshot();
+        terminal.draw(|f| {
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Length(1), Constraint::Min(0)])
+                .split(f.area());
+            f.render_widget(Paragraph::new(player.status_line()), chunks[0]);
+            draw::draw_view(f, &args.view_type, &ip_data, &[], chunks[1]);
+        }).map_err(terminal_error)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use crate::ping_event::PingEvent;
+
+    fn at(secs: i64) -> DateTime<Local> {
+        Local.timestamp_opt(1_700_000_000 + secs, 0).unwrap()
+    }
+
+    // one reply per second from second 1 to 10
+    fn recording() -> Recording {
+        let events = (1..=10)
+            .map(|secs| {
+                let event = PingEvent::Success {
+                    addr: "example.com".to_string(),
+                    ip: "192.0.2.1".to_string(),
+                    rtt: 10.0,
+                    seq: secs as usize,
+                    timestamp: at(secs),
+                };
+                (at(secs), event)
+            })
+            .collect();
+        Recording {
+            targets: vec![("example.com".to_string(), "192.0.2.1".to_string())],
+            events,
+            started_at: at(0),
+            metadata: Vec::new(),
+        }
+    }
+
+    fn config() -> ProcessorConfig {
+        ProcessorConfig {
+            outage_threshold: 3,
+            outage_history: MAX_OUTAGE_HISTORY,
+            health_rules: HealthRules::new(&[], 10.0, Duration::ZERO),
+            anomaly_sensitivity: 3.0,
+            baseline_warmup: 20,
+        }
+    }
+
+    fn received(player: &Player) -> usize {
+        player.processor.snapshot()[0].received
+    }
+
+    #[test]
+    fn seeking_back_replays_from_the_start() {
+        let (recording, config) = (recording(), config());
+        let mut player = Player::new(&recording, &config, "graph", Speed::Normal);
+        player.seek(at(4));
+        assert_eq!(received(&player), 4);
+        player.seek(at(2));
+        assert_eq!(received(&player), 2);
+        assert_eq!(player.position, at(2));
+
+        // clamped to the recording
+        player.seek(at(60));
+        assert_eq!(received(&player), 10);
+        assert_eq!(player.position, at(10));
+        player.seek(at(-60));
+        assert_eq!((received(&player), player.position), (0, at(0)));
+    }
+
+    #[test]
+    fn ticks_advance_by_the_speed_and_stop_at_the_end() {
+        let (recording, config) = (recording(), config());
+        let mut player = Player::new(&recording, &config, "graph", Speed::Normal);
+        player.tick(Duration::from_secs(3));
+        assert_eq!((received(&player), player.position), (3, at(3)));
+
+        player.speed = Speed::Fast;
+        player.tick(Duration::from_millis(500));
+        assert_eq!((received(&player), player.position), (8, at(8)));
+
+        player.playing = false;
+        player.tick(Duration::from_secs(1));
+        assert_eq!(received(&player), 8);
+
+        player.playing = true;
+        player.speed = Speed::Max;
+        player.tick(Duration::ZERO);
+        assert_eq!((received(&player), player.position), (10, at(10)));
+        assert!(!player.playing);
+    }
+}
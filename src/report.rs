@@ -0,0 +1,494 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::time::Duration;
+use chrono::{DateTime, Local};
+use clap::Args;
+use crate::data_processor::{DataProcessor, ProcessorConfig};
+use crate::error::NpingError;
+use crate::health::HealthRules;
+use crate::ip_data::IpData;
+use crate::outage::Outage;
+use crate::ping_event::PingEvent;
+use crate::recording::{load_recording, Recording};
+use crate::summary::TargetSummary;
+use crate::ui::utils::format_duration;
+
+// size of the plot area of the charts, in svg units
+const PLOT_WIDTH: f64 = 860.0;
+const PLOT_HEIGHT: f64 = 180.0;
+// the chart margin holding the axis labels
+const MARGIN_LEFT: f64 = 60.0;
+const MARGIN_BOTTOM: f64 = 24.0;
+const HISTOGRAM_BINS: usize = 40;
+
+const STYLE: &str = "
+body { font-family: -apple-system, 'Segoe UI', Helvetica, Arial, sans-serif; margin: 2em auto; max-width: 1000px; color: #222; }
+h1 { font-size: 1.6em; } h2 { margin-top: 2em; border-bottom: 1px solid #ddd; } h3 { margin-bottom: 0.3em; }
+table { border-collapse: collapse; font-size: 0.9em; margin: 0.5em 0; }
+th, td { padding: 3px 10px; border-bottom: 1px solid #eee; text-align: right; }
+th:first-child, td:first-child { text-align: left; }
+.meta td { text-align: left; } .bad { color: #c62828; font-weight: bold; }
+svg { display: block; margin: 0.5em 0; } svg text { font-size: 11px; fill: #555; }
+";
+
+#[derive(Args, Debug)]
+pub struct ReportArgs {
+    #[arg(help = "JSONL or CSV output file, or SQLite database written with --record")]
+    file: String,
+
+    #[arg(short, long, help = "HTML file to write, defaults to the recording with .html appended")]
+    output: Option<String>,
+
+    #[arg(long, help = "Session of a SQLite recording, defaults to the latest")]
+    session: Option<i64>,
+
+    #[arg(long, help = "Title of the report")]
+    title: Option<String>,
+
+    #[arg(long, default_value_t = 3, help = "Number of consecutive timeouts counted as an outage")]
+    outage_threshold: usize,
+}
+
+// the time and rtt of every result, None for a timeout
+pub(crate) type Results = Vec<(DateTime<Local>, Option<f64>)>;
+
+// what the report shows of one target
+pub(crate) struct TargetReport {
+    pub(crate) summary: TargetSummary,
+    pub(crate) results: Results,
+    pub(crate) outages: Vec<Outage>,
+}
+
+/// write the report of a recording, returns the path of the html file
+pub fn run_report(args: ReportArgs) -> Result<String, NpingError> {
+    let recording = load_recording(&args.file, args.session).map_err(NpingError::Output)?;
+    let reports = analyze(&recording, args.outage_threshold);
+    let title = args.title.clone().unwrap_or_else(|| format!("nping report {}", recording.started_at.format("%Y-%m-%d %H:%M")));
+    let html = render(&title, &recording, &reports);
+
+    let path = args.output.unwrap_or_else(|| format!("{}.html", args.file));
+    std::fs::write(&path, html).map_err(|e| NpingError::Output(format!("failed to write {}: {}", path, e)))?;
+    Ok(path)
+}
+
+// run the results through a data processor as nping did while recording
+pub(crate) fn analyze(recording: &Recording, outage_threshold: usize) -> Vec<TargetReport> {
+    let config = ProcessorConfig {
+        outage_threshold,
+        health_rules: HealthRules::new(&[], 0.0, Duration::ZERO),
+        anomaly_sensitivity: 0.0,
+        baseline_warmup: 0,
+        // the report lists every outage of the recording
+        outage_history: usize::MAX,
+    };
+    let mut processor = DataProcessor::new_at(&recording.targets, "table", &config, recording.started_at);
+    let mut results: HashMap<String, Results> = HashMap::new(); // key: addr_ip
+    for (at, event) in &recording.events {
+        match event {
+            PingEvent::Success { addr, ip, rtt, .. } => {
+                results.entry(format!("{}_{}", addr, ip)).or_default().push((*at, Some(*rtt)))
+            }
+            PingEvent::Timeout { addr, ip, .. } => results.entry(format!("{}_{}", addr, ip)).or_default().push((*at, None)),
+            PingEvent::Error { .. } => {}
+        }
+        processor.process_event(event.clone());
+        processor.take_transitions();
+    }
+
+    let data: Vec<IpData> = processor.snapshot();
+    processor
+        .summaries()
+        .into_iter()
+        .zip(data)
+        .map(|(summary, data)| {
+            let mut outages: Vec<Outage> = data.outage.history.iter().cloned().collect();
+            outages.extend(data.outage.current.clone());
+            TargetReport {
+                results: results.remove(&format!("{}_{}", summary.addr, summary.ip)).unwrap_or_default(),
+                summary,
+                outages,
+            }
+        })
+        .collect()
+}
+
+fn render(title: &str, recording: &Recording, reports: &[TargetReport]) -> String {
+    let start = recording.started_at;
+    let end = recording.finished_at();
+    let mut html = String::new();
+    writeln!(html, "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{}</title>", esc(title)).ok();
+    writeln!(html, "<style>{}</style></head><body>", STYLE).ok();
+    writeln!(html, "<h1>{}</h1>", esc(title)).ok();
+
+    // the session
+    writeln!(html, "<table class=\"meta\">").ok();
+    let period = format!(
+        "{} - {} ({})",
+        start.format("%Y-%m-%d %H:%M:%S"),
+        end.format("%Y-%m-%d %H:%M:%S"),
+        format_duration((end - start).to_std().unwrap_or_default())
+    );
+    writeln!(html, "<tr><th>period</th><td>{}</td></tr>", esc(&period)).ok();
+    for (key, value) in &recording.metadata {
+        writeln!(html, "<tr><th>{}</th><td>{}</td></tr>", esc(key), esc(value)).ok();
+    }
+    writeln!(html, "</table>").ok();
+
+    html.push_str(&summary_table(reports));
+
+    writeln!(html, "<h2>Outages</h2>").ok();
+    html.push_str(&outage_timeline(reports, start, end));
+    html.push_str(&outage_table(reports));
+
+    for report in reports {
+        let s = &report.summary;
+        writeln!(html, "<h2>{} ({})</h2>", esc(&s.addr), esc(&s.ip)).ok();
+        writeln!(html, "<h3>Latency</h3>").ok();
+        html.push_str(&latency_chart(&report.results, start, end));
+        writeln!(html, "<h3>Distribution</h3>").ok();
+        html.push_str(&histogram(&report.results));
+    }
+    writeln!(html, "<p><small>generated by nping {} at {}</small></p>", env!("CARGO_PKG_VERSION"), Local::now().to_rfc3339()).ok();
+    writeln!(html, "</body></html>").ok();
+    html
+}
+
+fn summary_table(reports: &[TargetReport]) -> String {
+    let mut html = String::from("<h2>Summary</h2>\n<table>\n<tr><th>target</th><th>ip</th><th>sent</th><th>received</th><th>loss</th>\
+        <th>min/avg/max/mdev (ms)</th><th>p50/p95/p99 (ms)</th><th>outages</th><th>downtime</th></tr>\n");
+    for report in reports {
+        let s = &report.summary;
+        writeln!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td{}>{:.2}%</td><td>{:.2}/{:.2}/{:.2}/{:.2}</td>\
+             <td>{:.2}/{:.2}/{:.2}</td><td>{}</td><td>{}</td></tr>",
+            esc(&s.addr),
+            esc(&s.ip),
+            s.sent,
+            s.received,
+            if s.loss > 0.0 { " class=\"bad\"" } else { "" },
+            s.loss,
+            s.min,
+            s.avg,
+            s.max,
+            s.mdev,
+            s.p50,
+            s.p95,
+            s.p99,
+            s.outages,
+            format_duration(s.downtime),
+        )
+        .ok();
+    }
+    html.push_str("</table>\n");
+    html
+}
+
+fn outage_timeline(reports: &[TargetReport], start: DateTime<Local>, end: DateTime<Local>) -> String {
+    const ROW: f64 = 22.0;
+    let label_width = 160.0;
+    let width = label_width + PLOT_WIDTH;
+    let height = ROW * reports.len() as f64 + MARGIN_BOTTOM;
+    let x = |at: DateTime<Local>| label_width + time_x(at, start, end);
+
+    let mut svg = svg_open(width, height);
+    for (i, report) in reports.iter().enumerate() {
+        let y = i as f64 * ROW;
+        writeln!(svg, "<text x=\"0\" y=\"{:.1}\">{}</text>", y + 15.0, esc(&report.summary.addr)).ok();
+        writeln!(svg, "<rect x=\"{}\" y=\"{:.1}\" width=\"{}\" height=\"14\" fill=\"#c8e6c9\"/>", label_width, y + 4.0, PLOT_WIDTH).ok();
+        for outage in &report.outages {
+            let x1 = x(outage.start);
+            let x2 = x(outage.end.unwrap_or(end)).max(x1 + 1.0);
+            writeln!(
+                svg,
+                "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"14\" fill=\"#e53935\"><title>{} - {}, {} lost</title></rect>",
+                x1,
+                y + 4.0,
+                x2 - x1,
+                outage.start.format("%H:%M:%S"),
+                outage.end.map(|e| e.format("%H:%M:%S").to_string()).unwrap_or_else(|| "ongoing".to_string()),
+                outage.lost
+            )
+            .ok();
+        }
+    }
+    time_labels(&mut svg, label_width, height - 6.0, start, end);
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn outage_table(reports: &[TargetReport]) -> String {
+    let mut outages: Vec<(&str, &Outage)> = reports
+        .iter()
+        .flat_map(|r| r.outages.iter().map(move |o| (r.summary.addr.as_str(), o)))
+        .collect();
+    if outages.is_empty() {
+        return "<p>No outages.</p>\n".to_string();
+    }
+    outages.sort_by_key(|(_, o)| o.start);
+
+    let mut html = String::from("<table>\n<tr><th>target</th><th>start</th><th>end</th><th>duration</th><th>lost</th></tr>\n");
+    for (addr, outage) in outages {
+        let end = outage.end.map(|e| e.format("%Y-%m-%d %H:%M:%S").to_string()).unwrap_or_else(|| "ongoing".to_string());
+        let duration = outage.end.map(|e| format_duration(outage.duration(e))).unwrap_or_else(|| "-".to_string());
+        writeln!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            esc(addr),
+            outage.start.format("%Y-%m-%d %H:%M:%S"),
+            end,
+            duration,
+            outage.lost
+        )
+        .ok();
+    }
+    html.push_str("</table>\n");
+    html
+}
+
+// rtt over time, averaged per pixel column when there are more results than columns,
+// timeouts are marked red along the top
+fn latency_chart(results: &Results, start: DateTime<Local>, end: DateTime<Local>) -> String {
+    let columns = PLOT_WIDTH as usize;
+    let mut sums = vec![(0.0, 0usize, false); columns + 1];
+    for (at, rtt) in results {
+        let column = (time_x(*at, start, end) as usize).min(columns);
+        match rtt {
+            Some(rtt) => {
+                sums[column].0 += rtt;
+                sums[column].1 += 1;
+            }
+            None => sums[column].2 = true,
+        }
+    }
+    let max = sums.iter().filter(|s| s.1 > 0).map(|s| s.0 / s.1 as f64).fold(0.0, f64::max);
+    let y_max = nice_ceil(max);
+    let y = |rtt: f64| PLOT_HEIGHT - rtt / y_max * PLOT_HEIGHT;
+
+    let mut svg = svg_open(MARGIN_LEFT + PLOT_WIDTH, PLOT_HEIGHT + MARGIN_BOTTOM);
+    value_axis(&mut svg, y_max, "ms");
+
+    // a gap in the line for every column without a reply
+    let mut path = String::new();
+    let mut pen_down = false;
+    for (column, (sum, count, lost)) in sums.iter().enumerate() {
+        let x = MARGIN_LEFT + column as f64;
+        if *lost {
+            writeln!(svg, "<line x1=\"{:.1}\" y1=\"0\" x2=\"{:.1}\" y2=\"6\" stroke=\"#e53935\"/>", x, x).ok();
+        }
+        if *count == 0 {
+            if *lost {
+                pen_down = false;
+            }
+            continue;
+        }
+        write!(path, "{}{:.1},{:.1} ", if pen_down { "L" } else { "M" }, x, y(sum / *count as f64)).ok();
+        pen_down = true;
+    }
+    writeln!(svg, "<path d=\"{}\" fill=\"none\" stroke=\"#1e88e5\" stroke-width=\"1.2\"/>", path.trim_end()).ok();
+    time_labels(&mut svg, MARGIN_LEFT, PLOT_HEIGHT + 16.0, start, end);
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn histogram(results: &Results) -> String {
+    let rtts: Vec<f64> = results.iter().filter_map(|(_, rtt)| *rtt).collect();
+    if rtts.is_empty() {
+        return "<p>No replies.</p>\n".to_string();
+    }
+    let mut sorted = rtts.clone();
+    sorted.sort_by(f64::total_cmp);
+    // the slowest percent would squeeze everything else into a few bins, they go into the last one
+    let x_max = nice_ceil(sorted[(sorted.len() - 1) * 99 / 100]);
+    let mut bins = [0usize; HISTOGRAM_BINS];
+    for rtt in &rtts {
+        let bin = ((rtt / x_max * HISTOGRAM_BINS as f64) as usize).min(HISTOGRAM_BINS - 1);
+        bins[bin] += 1;
+    }
+    let most = *bins.iter().max().unwrap_or(&1) as f64;
+    let bin_width = PLOT_WIDTH / HISTOGRAM_BINS as f64;
+
+    let mut svg = svg_open(MARGIN_LEFT + PLOT_WIDTH, PLOT_HEIGHT + MARGIN_BOTTOM);
+    value_axis(&mut svg, most, "");
+    for (i, count) in bins.iter().enumerate() {
+        let height = *count as f64 / most * PLOT_HEIGHT;
+        writeln!(
+            svg,
+            "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"#1e88e5\"><title>{:.1}-{:.1} ms: {}</title></rect>",
+            MARGIN_LEFT + i as f64 * bin_width + 1.0,
+            PLOT_HEIGHT - height,
+            bin_width - 2.0,
+            height,
+            i as f64 * x_max / HISTOGRAM_BINS as f64,
+            (i + 1) as f64 * x_max / HISTOGRAM_BINS as f64,
+            count
+        )
+        .ok();
+    }
+    for i in 0..=4 {
+        let x = MARGIN_LEFT + PLOT_WIDTH * i as f64 / 4.0;
+        let anchor = if i == 0 { "start" } else if i == 4 { "end" } else { "middle" };
+        writeln!(
+            svg,
+            "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"{}\">{} ms</text>",
+            x,
+            PLOT_HEIGHT + 16.0,
+            anchor,
+            format_value(x_max * i as f64 / 4.0)
+        )
+        .ok();
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn svg_open(width: f64, height: f64) -> String {
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n",
+        w = width,
+        h = height
+    )
+}
+
+// horizontal grid lines with their values on the left
+fn value_axis(svg: &mut String, max: f64, unit: &str) {
+    for i in 0..=4 {
+        let value = max * i as f64 / 4.0;
+        let y = PLOT_HEIGHT - PLOT_HEIGHT * i as f64 / 4.0;
+        writeln!(
+            svg,
+            "<line x1=\"{}\" y1=\"{:.1}\" x2=\"{}\" y2=\"{:.1}\" stroke=\"#e0e0e0\"/><text x=\"{}\" y=\"{:.1}\" text-anchor=\"end\">{} {}</text>",
+            MARGIN_LEFT,
+            y,
+            MARGIN_LEFT + PLOT_WIDTH,
+            y,
+            MARGIN_LEFT - 6.0,
+            y + 4.0,
+            format_value(value),
+            unit
+        )
+        .ok();
+    }
+}
+
+// start, middle and end time below a plot starting at `left`
+fn time_labels(svg: &mut String, left: f64, y: f64, start: DateTime<Local>, end: DateTime<Local>) {
+    let middle = start + (end - start) / 2;
+    for (at, x, anchor) in [(start, 0.0, "start"), (middle, PLOT_WIDTH / 2.0, "middle"), (end, PLOT_WIDTH, "end")] {
+        writeln!(svg, "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"{}\">{}</text>", left + x, y, anchor, at.format("%H:%M:%S")).ok();
+    }
+}
+
+fn time_x(at: DateTime<Local>, start: DateTime<Local>, end: DateTime<Local>) -> f64 {
+    let total = (end - start).num_milliseconds().max(1) as f64;
+    ((at - start).num_milliseconds() as f64 / total * PLOT_WIDTH).clamp(0.0, PLOT_WIDTH)
+}
+
+// the next 1, 2 or 5 times a power of ten, so the axis labels are round
+fn nice_ceil(value: f64) -> f64 {
+    if value <= 0.0 {
+        return 1.0;
+    }
+    let magnitude = 10f64.powf(value.log10().floor());
+    [1.0, 2.0, 5.0, 10.0]
+        .iter()
+        .map(|step| step * magnitude)
+        .find(|candidate| *candidate >= value)
+        .unwrap_or(10.0 * magnitude)
+}
+
+fn format_value(value: f64) -> String {
+    if value.fract() == 0.0 {
+        format!("{}", value)
+    } else {
+        format!("{:.1}", value)
+    }
+}
+
+fn esc(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(secs: i64) -> DateTime<Local> {
+        Local.timestamp_opt(1_700_000_000 + secs, 0).unwrap()
+    }
+
+    // example.com loses seconds 4 to 6, example.org answers every second with 1 ms more
+    fn recording() -> Recording {
+        let mut events = Vec::new();
+        for secs in 1..=10 {
+            let (addr, ip, seq) = ("example.com".to_string(), "192.0.2.1".to_string(), secs as usize);
+            let event = if (4..=6).contains(&secs) {
+                PingEvent::Timeout { addr, ip, seq, timestamp: at(secs) }
+            } else {
+                PingEvent::Success { addr, ip, rtt: 10.0, seq, timestamp: at(secs) }
+            };
+            events.push((at(secs), event));
+            let (addr, ip) = ("example.org".to_string(), "192.0.2.2".to_string());
+            events.push((at(secs), PingEvent::Success { addr, ip, rtt: secs as f64, seq, timestamp: at(secs) }));
+        }
+        Recording {
+            targets: vec![
+                ("example.com".to_string(), "192.0.2.1".to_string()),
+                ("example.org".to_string(), "192.0.2.2".to_string()),
+            ],
+            events,
+            started_at: at(0),
+            metadata: vec![("hostname".to_string(), "probe <1>".to_string())],
+        }
+    }
+
+    #[test]
+    fn outages_and_summaries_per_target() {
+        let reports = analyze(&recording(), 3);
+        assert_eq!(reports.len(), 2);
+
+        let com = &reports[0];
+        assert_eq!((com.summary.addr.as_str(), com.summary.sent, com.summary.received), ("example.com", 10, 7));
+        assert_eq!(com.results.len(), 10);
+        assert_eq!(com.results[3], (at(4), None));
+        assert_eq!(com.outages.len(), 1);
+        assert_eq!((com.outages[0].start, com.outages[0].end, com.outages[0].lost), (at(4), Some(at(7)), 3));
+
+        let org = &reports[1];
+        assert_eq!((org.summary.received, org.summary.max), (10, 10.0));
+        assert!(org.outages.is_empty());
+
+        // below the threshold it is just loss
+        assert!(analyze(&recording(), 4)[0].outages.is_empty());
+    }
+
+    #[test]
+    fn axis_maximum_is_round() {
+        assert_eq!(nice_ceil(0.0), 1.0);
+        assert_eq!(nice_ceil(0.7), 1.0);
+        assert_eq!(nice_ceil(1.0), 1.0);
+        assert_eq!(nice_ceil(13.0), 20.0);
+        assert_eq!(nice_ceil(42.0), 50.0);
+        assert_eq!(nice_ceil(51.0), 100.0);
+        assert_eq!(nice_ceil(0.03), 0.05);
+    }
+
+    #[test]
+    fn html_is_escaped() {
+        assert_eq!(esc(r#"<a href="x">&</a>"#), "&lt;a href=&quot;x&quot;&gt;&amp;&lt;/a&gt;");
+    }
+
+    #[test]
+    fn one_latency_chart_and_histogram_per_target() {
+        let recording = recording();
+        let html = render("<title>", &recording, &analyze(&recording, 3));
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<h1>&lt;title&gt;</h1>"));
+        assert!(html.contains("<td>probe &lt;1&gt;</td>"));
+        assert_eq!(html.matches("<h3>Latency</h3>\n<svg").count(), 2);
+        assert_eq!(html.matches("<h3>Distribution</h3>\n<svg").count(), 2);
+        assert!(html.contains("<h2>example.org (192.0.2.2)</h2>"));
+        assert!(html.trim_end().ends_with("</body></html>"));
+    }
+}
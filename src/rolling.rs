@@ -0,0 +1,126 @@
+use std::collections::VecDeque;
+use chrono::{DateTime, Duration, Local};
+use crate::stats::calculate_percentile;
+
+// lengths of the sliding windows in minutes, like load averages
+pub const WINDOW_MINUTES: [i64; 3] = [1, 5, 15];
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WindowStats {
+    pub(crate) loss: f64,
+    pub(crate) avg: f64,
+    pub(crate) p95: f64,
+}
+
+/// statistics of the last 1, 5 and 15 minutes
+#[derive(Debug, Clone, Default)]
+pub struct RollingStats {
+    pub(crate) windows: [WindowStats; 3],
+}
+
+impl RollingStats {
+    pub fn loss(&self) -> [f64; 3] {
+        self.windows.map(|w| w.loss)
+    }
+
+    pub fn avg(&self) -> [f64; 3] {
+        self.windows.map(|w| w.avg)
+    }
+
+    pub fn p95(&self) -> [f64; 3] {
+        self.windows.map(|w| w.p95)
+    }
+}
+
+/// Timestamped results of one target over the longest window
+#[derive(Debug, Default)]
+pub struct RollingWindow {
+    // timestamp and rtt of each result, None for a timeout
+    samples: VecDeque<(DateTime<Local>, Option<f64>)>,
+}
+
+impl RollingWindow {
+    pub fn push(&mut self, at: DateTime<Local>, rtt: Option<f64>) {
+        self.samples.push_back((at, rtt));
+
+        let longest = Duration::minutes(WINDOW_MINUTES[WINDOW_MINUTES.len() - 1]);
+        while let Some(&(oldest, _)) = self.samples.front() {
+            if at - oldest <= longest {
+                break;
+            }
+            self.samples.pop_front();
+        }
+    }
+
+    pub fn stats(&self, now: DateTime<Local>) -> RollingStats {
+        let mut stats = RollingStats::default();
+        for (window, minutes) in stats.windows.iter_mut().zip(WINDOW_MINUTES) {
+            let since = now - Duration::minutes(minutes);
+            let rtts: VecDeque<f64> = self.samples
+                .iter()
+                .filter(|(at, _)| *at >= since)
+                .map(|(_, rtt)| rtt.unwrap_or(-1.0))
+                .collect();
+            *window = window_stats(&rtts);
+        }
+        stats
+    }
+}
+
+// timeouts are recorded as -1.0 like in IpData::rtts
+fn window_stats(rtts: &VecDeque<f64>) -> WindowStats {
+    let received: Vec<f64> = rtts.iter().cloned().filter(|&rtt| rtt >= 0.0).collect();
+    if rtts.is_empty() {
+        return WindowStats::default();
+    }
+    WindowStats {
+        loss: (rtts.len() - received.len()) as f64 / rtts.len() as f64 * 100.0,
+        avg: if received.is_empty() { 0.0 } else { received.iter().sum::<f64>() / received.len() as f64 },
+        p95: calculate_percentile(rtts, 95.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(secs: i64) -> DateTime<Local> {
+        Local.timestamp_opt(1_700_000_000 + secs, 0).unwrap()
+    }
+
+    #[test]
+    fn windows_only_count_their_own_minutes() {
+        let mut window = RollingWindow::default();
+        // ten minutes ago the target was losing every packet, the last minute was clean
+        for i in 0..60 {
+            window.push(at(i), None);
+        }
+        for i in 540..600 {
+            window.push(at(i), Some(10.0));
+        }
+
+        let stats = window.stats(at(600));
+        assert_eq!(stats.loss(), [0.0, 0.0, 50.0]);
+        assert_eq!(stats.avg(), [10.0, 10.0, 10.0]);
+        assert_eq!(stats.p95()[0], 10.0);
+    }
+
+    #[test]
+    fn samples_older_than_the_longest_window_are_dropped() {
+        let mut window = RollingWindow::default();
+        window.push(at(0), None);
+        window.push(at(16 * 60), Some(5.0));
+        assert_eq!(window.samples.len(), 1);
+        assert_eq!(window.stats(at(16 * 60)).loss(), [0.0; 3]);
+    }
+
+    #[test]
+    fn empty_window_is_all_zero() {
+        let mut window = RollingWindow::default();
+        window.push(at(0), None);
+        let stats = window.stats(at(120));
+        assert_eq!(stats.loss(), [0.0, 100.0, 100.0]);
+        assert_eq!(stats.avg(), [0.0; 3]);
+    }
+}
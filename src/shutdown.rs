@@ -0,0 +1,129 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
+use tokio::task;
+
+// exit code of a run whose terminal was closed, like a shell killed by SIGHUP
+pub const HANGUP_EXIT_CODE: i32 = 129;
+
+/// Stops a run, shared by the ping tasks, the data processor and the ui. Cancelled by a signal,
+/// a key press or once every ping task is done; threads check it between two results, the
+/// ping tasks wait on it next to the ping process.
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    cancelled: Arc<AtomicBool>,
+    notify: Arc<Notify>,
+    // the terminal of the ui was closed
+    hung_up: Arc<AtomicBool>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// cancel because the terminal of the ui is gone, the run ends as on SIGTERM
+    pub fn hang_up(&self) {
+        self.hung_up.store(true, Ordering::SeqCst);
+        self.cancel();
+    }
+
+    pub fn is_hung_up(&self) -> bool {
+        self.hung_up.load(Ordering::SeqCst)
+    }
+
+    /// resolves once cancelled, right away if it already is
+    pub async fn cancelled(&self) {
+        let notified = self.notify.notified();
+        tokio::pin!(notified);
+        // registered before the check, a cancel in between still wakes it
+        notified.as_mut().enable();
+        if !self.is_cancelled() {
+            notified.await;
+        }
+    }
+}
+
+/// The first Ctrl+C, SIGTERM or SIGQUIT cancels `shutdown` and the results so far are still
+/// written, a second one exits right away. Must be called inside a tokio runtime.
+pub fn handle_signals(shutdown: Shutdown) {
+    let (signal_tx, mut signal_rx) = tokio::sync::mpsc::unbounded_channel::<()>();
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        for kind in [SignalKind::interrupt(), SignalKind::terminate(), SignalKind::quit()] {
+            if let Ok(mut stream) = signal(kind) {
+                let signal_tx = signal_tx.clone();
+                task::spawn(async move {
+                    while stream.recv().await.is_some() && signal_tx.send(()).is_ok() {}
+                });
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    task::spawn(async move {
+        while tokio::signal::ctrl_c().await.is_ok() && signal_tx.send(()).is_ok() {}
+    });
+
+    task::spawn(async move {
+        if signal_rx.recv().await.is_none() {
+            return;
+        }
+        shutdown.cancel();
+        if signal_rx.recv().await.is_some() {
+            crate::draw::reset_terminal();
+            eprintln!("interrupted again, exiting without writing the summary");
+            std::process::exit(130);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn cancel_wakes_waiters() {
+        let shutdown = Shutdown::new();
+        let waiter = {
+            let shutdown = shutdown.clone();
+            task::spawn(async move { shutdown.cancelled().await })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiter.is_finished());
+        shutdown.cancel();
+        tokio::time::timeout(Duration::from_secs(1), waiter).await.unwrap().unwrap();
+        // later waiters return right away
+        tokio::time::timeout(Duration::from_millis(10), shutdown.cancelled()).await.unwrap();
+    }
+
+    #[test]
+    fn every_clone_sees_the_cancel() {
+        let shutdown = Shutdown::new();
+        let task = shutdown.clone();
+        assert!(!task.is_cancelled());
+        shutdown.cancel();
+        assert!(task.is_cancelled());
+        assert!(shutdown.clone().is_cancelled());
+        assert!(!shutdown.is_hung_up());
+    }
+
+    #[tokio::test]
+    async fn hang_up_cancels_as_well() {
+        let shutdown = Shutdown::new();
+        let task = shutdown.clone();
+        task.hang_up();
+        tokio::time::timeout(Duration::from_millis(10), shutdown.cancelled()).await.unwrap();
+        assert!(shutdown.is_hung_up());
+    }
+}
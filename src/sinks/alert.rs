@@ -0,0 +1,238 @@
+use std::collections::{HashMap, HashSet};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use serde_json::json;
+use crate::health::{HealthState, HealthTransition};
+use crate::ip_data::IpData;
+use crate::sinks::{Sink, SinkEvent};
+use crate::stats::{calculate_avg_rtt, calculate_jitter, calculate_loss_pkg};
+use crate::error::lock;
+
+// a hook taking longer than this is killed
+const HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Run a command and/or post to a webhook when the health state of a target changes
+pub struct AlertSink {
+    command: Option<String>,
+    webhook: Option<String>,
+    // minimum time between two alerts of the same target and state
+    rate_limit: Duration,
+    last_sent: HashMap<String, (HealthState, Instant)>, // key: addr_ip
+    // targets with a delivered, not yet recovered alert
+    alerted: HashSet<String>, // key: addr_ip
+    errs: Arc<Mutex<Vec<String>>>,
+}
+
+impl AlertSink {
+    pub fn new(
+        command: Option<String>,
+        webhook: Option<String>,
+        rate_limit: Duration,
+        errs: Arc<Mutex<Vec<String>>>,
+    ) -> Self {
+        Self {
+            command,
+            webhook,
+            rate_limit,
+            last_sent: HashMap::new(),
+            alerted: HashSet::new(),
+            errs,
+        }
+    }
+
+    fn notify(&self, transition: &HealthTransition, data: &IpData) {
+        let recovery = transition.to == HealthState::Ok;
+        let vars = [
+            ("NPING_TARGET", transition.addr.clone()),
+            ("NPING_IP", transition.ip.clone()),
+            ("NPING_STATE", transition.to.to_string()),
+            ("NPING_PREVIOUS_STATE", transition.from.to_string()),
+            ("NPING_REASON", transition.reason.clone()),
+            ("NPING_TIME", transition.at.to_rfc3339()),
+            ("NPING_RECOVERY", if recovery { "1" } else { "0" }.to_string()),
+            ("NPING_LOSS", format!("{:.2}", calculate_loss_pkg(data.timeout, data.received))),
+            ("NPING_AVG_RTT", format!("{:.2}", calculate_avg_rtt(&data.rtts))),
+            ("NPING_JITTER", format!("{:.2}", calculate_jitter(&data.rtts))),
+            ("NPING_LAST_RTT", format!("{:.2}", data.last_attr)),
+            ("NPING_MIN_RTT", format!("{:.2}", data.min_rtt)),
+            ("NPING_MAX_RTT", format!("{:.2}", data.max_rtt)),
+            ("NPING_RECEIVED", data.received.to_string()),
+            ("NPING_TIMEOUTS", data.timeout.to_string()),
+            ("NPING_OUTAGES", data.outage.count.to_string()),
+        ];
+
+        if let Some(ref command) = self.command {
+            if let Err(err) = run_hook(command, &vars) {
+                lock(&self.errs).push(format!("alert command failed for {}: {}", transition.addr, err));
+            }
+        }
+
+        if let Some(ref url) = self.webhook {
+            let payload = json!({
+                "target": transition.addr,
+                "ip": transition.ip,
+                "state": transition.to.as_str(),
+                "previous_state": transition.from.as_str(),
+                "reason": transition.reason,
+                "timestamp": transition.at.to_rfc3339(),
+                "recovery": recovery,
+                "metrics": {
+                    "loss": calculate_loss_pkg(data.timeout, data.received),
+                    "avg_rtt": calculate_avg_rtt(&data.rtts),
+                    "jitter": calculate_jitter(&data.rtts),
+                    "last_rtt": data.last_attr,
+                    "min_rtt": data.min_rtt,
+                    "max_rtt": data.max_rtt,
+                    "received": data.received,
+                    "timeouts": data.timeout,
+                    "outages": data.outage.count,
+                },
+            });
+            let res = ureq::post(url)
+                .timeout(HOOK_TIMEOUT)
+                .set("Content-Type", "application/json")
+                .send_string(&payload.to_string());
+            if let Err(err) = res {
+                lock(&self.errs).push(format!("alert webhook failed for {}: {}", transition.addr, err));
+            }
+        }
+    }
+}
+
+impl Sink for AlertSink {
+    fn wants_updates(&self) -> bool {
+        false
+    }
+
+    fn handle(&mut self, event: &SinkEvent) {
+        let SinkEvent::Transition { transition, data } = event else {
+            return;
+        };
+        let key = format!("{}_{}", transition.addr, transition.ip);
+
+        if transition.to == HealthState::Ok {
+            // only announce a recovery if the alert went out
+            if self.alerted.remove(&key) {
+                self.notify(transition, data);
+            }
+            return;
+        }
+
+        // a flapping target repeats its alert, a change to another state always goes out
+        let now = Instant::now();
+        if let Some((state, last)) = self.last_sent.get(&key) {
+            if *state == transition.to && now.duration_since(*last) < self.rate_limit {
+                return;
+            }
+        }
+        self.last_sent.insert(key.clone(), (transition.to, now));
+        self.alerted.insert(key);
+        self.notify(transition, data);
+    }
+}
+
+fn run_hook(command: &str, vars: &[(&str, String)]) -> Result<(), String> {
+    let mut cmd = if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.args(["/C", command]);
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", command]);
+        cmd
+    };
+    for (key, value) in vars {
+        cmd.env(key, value);
+    }
+    // keep the hook from drawing over the ui
+    cmd.stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null());
+
+    let child = cmd.spawn().map_err(|e| e.to_string())?;
+    wait_with_timeout(child, HOOK_TIMEOUT)
+}
+
+fn wait_with_timeout(mut child: Child, timeout: Duration) -> Result<(), String> {
+    let started = Instant::now();
+    loop {
+        match child.try_wait().map_err(|e| e.to_string())? {
+            Some(status) if status.success() => return Ok(()),
+            Some(status) => return Err(format!("exited with {}", status)),
+            None if started.elapsed() > timeout => {
+                child.kill().ok();
+                child.wait().ok();
+                return Err(format!("timed out after {}s", timeout.as_secs()));
+            }
+            None => std::thread::sleep(Duration::from_millis(50)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Local;
+    use crate::sinks::testing::http_listener;
+
+    fn transition(from: HealthState, to: HealthState) -> SinkEvent {
+        let data = IpData::new("example.com".to_string(), "192.0.2.1".to_string());
+        SinkEvent::Transition {
+            transition: HealthTransition {
+                addr: data.addr.clone(),
+                ip: data.ip.clone(),
+                from,
+                to,
+                at: Local::now(),
+                reason: "loss 60.00% > 50".to_string(),
+            },
+            data,
+        }
+    }
+
+    #[test]
+    fn webhook_gets_escalations_and_recoveries_but_not_repeats() {
+        let (url, requests) = http_listener();
+        let errs = Arc::new(Mutex::new(Vec::new()));
+        let mut sink = AlertSink::new(None, Some(url), Duration::from_secs(60), errs.clone());
+
+        sink.handle(&transition(HealthState::Ok, HealthState::Warn));
+        sink.handle(&transition(HealthState::Warn, HealthState::Crit));
+        sink.handle(&transition(HealthState::Crit, HealthState::Ok));
+        // the same state again within the rate limit
+        sink.handle(&transition(HealthState::Ok, HealthState::Crit));
+
+        let states: Vec<(String, bool)> = requests
+            .try_iter()
+            .map(|(request_line, body)| {
+                assert!(request_line.starts_with("POST / "));
+                let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+                assert_eq!(body["target"], "example.com");
+                (body["state"].as_str().unwrap().to_string(), body["recovery"].as_bool().unwrap())
+            })
+            .collect();
+        assert_eq!(
+            states,
+            [("WARN".to_string(), false), ("CRIT".to_string(), false), ("OK".to_string(), true)]
+        );
+        assert!(errs.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn recovery_is_only_sent_after_an_alert() {
+        let (url, requests) = http_listener();
+        let mut sink = AlertSink::new(None, Some(url), Duration::from_secs(60), Arc::new(Mutex::new(Vec::new())));
+        sink.handle(&transition(HealthState::Warn, HealthState::Ok));
+        assert!(requests.try_iter().next().is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn command_gets_the_transition_in_its_environment() {
+        let out = std::env::temp_dir().join(format!("nping-alert-{}.txt", std::process::id()));
+        let command = format!("echo \"$NPING_TARGET $NPING_STATE $NPING_PREVIOUS_STATE\" > {}", out.display());
+        let mut sink = AlertSink::new(Some(command), None, Duration::from_secs(60), Arc::new(Mutex::new(Vec::new())));
+        sink.handle(&transition(HealthState::Ok, HealthState::Crit));
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "example.com CRIT OK\n");
+        std::fs::remove_file(out).ok();
+    }
+}
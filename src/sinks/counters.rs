@@ -0,0 +1,45 @@
+use crate::health::HealthState;
+use crate::ip_data::IpData;
+
+// upper bounds of the rtt histogram buckets in seconds
+pub(crate) const RTT_BUCKETS: [f64; 12] = [0.001, 0.002, 0.005, 0.01, 0.02, 0.05, 0.1, 0.2, 0.5, 1.0, 2.0, 5.0];
+
+/// Cumulative counters and rtt histogram of one target, for the exporters
+#[derive(Debug, Default)]
+pub(crate) struct TargetMetrics {
+    pub(crate) addr: String,
+    pub(crate) ip: String,
+    pub(crate) sent: usize,
+    pub(crate) received: usize,
+    pub(crate) timeouts: usize,
+    pub(crate) errors: usize,
+    // None until the first reply, or after a timeout
+    pub(crate) last_rtt: Option<f64>,
+    pub(crate) health: HealthState,
+    // count of rtts per bucket, not cumulative
+    pub(crate) buckets: [u64; RTT_BUCKETS.len()],
+    pub(crate) rtt_count: u64,
+    pub(crate) rtt_sum: f64,
+}
+
+impl TargetMetrics {
+    pub fn observe(&mut self, data: &IpData) {
+        self.sent = data.received + data.timeout;
+        self.received = data.received;
+        self.timeouts = data.timeout;
+        self.errors = data.errors;
+        self.health = data.health;
+
+        if data.last_attr < 0.0 {
+            self.last_rtt = None;
+            return;
+        }
+        let rtt = data.last_attr / 1000.0;
+        self.last_rtt = Some(rtt);
+        self.rtt_count += 1;
+        self.rtt_sum += rtt;
+        if let Some(pos) = RTT_BUCKETS.iter().position(|le| rtt <= *le) {
+            self.buckets[pos] += 1;
+        }
+    }
+}
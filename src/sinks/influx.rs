@@ -0,0 +1,180 @@
+use std::collections::BTreeMap;
+use std::net::UdpSocket;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::Local;
+use crate::ip_data::IpData;
+use crate::sinks::{send_udp, LineBuffer, Sink, SinkEvent, TargetGroups};
+use crate::stats::IntervalStats;
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_UDP_PACKET: usize = 1400;
+
+enum Endpoint {
+    // the full write url, e.g. http://localhost:8086/api/v2/write?org=o&bucket=b
+    Http { url: String, token: Option<String> },
+    Udp { socket: UdpSocket, addr: String },
+}
+
+/// Push results as InfluxDB line protocol over HTTP or UDP
+pub struct InfluxSink {
+    endpoint: Endpoint,
+    groups: TargetGroups,
+    // rendered tag set shared by all points, starting with a comma
+    tags: String,
+    aggregate: bool,
+    intervals: BTreeMap<String, (String, String, IntervalStats)>, // key: addr_ip
+    buffer: LineBuffer,
+}
+
+impl InfluxSink {
+    /// `url` is an http(s) write endpoint or udp://host:port, with `aggregate`
+    /// one point per target and flush is written instead of one per result
+    pub fn new(
+        url: &str,
+        token: Option<String>,
+        groups: TargetGroups,
+        tags: &[(String, String)],
+        aggregate: bool,
+        errs: Arc<Mutex<Vec<String>>>,
+    ) -> std::io::Result<Self> {
+        let endpoint = match url.strip_prefix("udp://") {
+            Some(addr) => Endpoint::Udp { socket: UdpSocket::bind("0.0.0.0:0")?, addr: addr.to_string() },
+            None => Endpoint::Http { url: url.to_string(), token },
+        };
+        let tags = tags.iter().map(|(k, v)| format!(",{}={}", escape(k), escape(v))).collect();
+        Ok(Self {
+            endpoint,
+            groups,
+            tags,
+            aggregate,
+            intervals: BTreeMap::new(),
+            buffer: LineBuffer::new("influxdb", errs),
+        })
+    }
+
+    fn series(&self, addr: &str, ip: &str) -> String {
+        format!("target={},ip={},probe=icmp,group={}{}", escape(addr), escape(ip), escape(&self.groups.of(addr)), self.tags)
+    }
+
+    fn point(&self, data: &IpData) -> String {
+        let fields = if data.last_attr < 0.0 {
+            format!("timeout=true,seq={}i", data.last_seq)
+        } else {
+            format!("rtt_ms={},timeout=false,seq={}i", data.last_attr, data.last_seq)
+        };
+        let at = data.last_timestamp.unwrap_or_else(Local::now);
+        format!("nping,{} {} {}", self.series(&data.addr, &data.ip), fields, at.timestamp_nanos_opt().unwrap_or_default())
+    }
+}
+
+impl Sink for InfluxSink {
+    fn handle(&mut self, event: &SinkEvent) {
+        let SinkEvent::Update { data } = event else {
+            return;
+        };
+        if self.aggregate {
+            self.intervals
+                .entry(format!("{}_{}", data.addr, data.ip))
+                .or_insert_with(|| (data.addr.clone(), data.ip.clone(), IntervalStats::default()))
+                .2
+                .push(data);
+        } else {
+            let line = self.point(data);
+            self.buffer.push(line);
+        }
+    }
+
+    fn flush(&mut self) {
+        let now = Local::now().timestamp_nanos_opt().unwrap_or_default();
+        for (addr, ip, interval) in std::mem::take(&mut self.intervals).values() {
+            let mut fields = format!("sent={}i,received={}i,loss={}", interval.sent, interval.received(), interval.loss());
+            // an interval without replies has no rtt, leave the fields out rather than write zeros
+            if interval.received() > 0 {
+                fields.push_str(&format!(
+                    ",min_ms={},avg_ms={},max_ms={},jitter_ms={}",
+                    interval.min(),
+                    interval.avg(),
+                    interval.max(),
+                    interval.jitter()
+                ));
+            }
+            let line = format!("nping_interval,{} {} {}", self.series(addr, ip), fields, now);
+            self.buffer.push(line);
+        }
+
+        let endpoint = &self.endpoint;
+        self.buffer.flush_with(|lines| match endpoint {
+            Endpoint::Http { url, token } => {
+                let mut req = ureq::post(url).timeout(HTTP_TIMEOUT).set("Content-Type", "text/plain; charset=utf-8");
+                if let Some(token) = token {
+                    req = req.set("Authorization", &format!("Token {}", token));
+                }
+                req.send_string(&lines.join("\n")).map(|_| ()).map_err(|e| (0, e.to_string()))
+            }
+            Endpoint::Udp { socket, addr } => send_udp(socket, addr, lines, MAX_UDP_PACKET),
+        });
+    }
+}
+
+// tag keys and values escape commas, equal signs and spaces
+fn escape(value: &str) -> String {
+    value.replace(',', "\\,").replace('=', "\\=").replace(' ', "\\ ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sinks::testing::{http_listener, recv_lines, result, udp_listener};
+
+    fn tags() -> Vec<(String, String)> {
+        vec![("env".to_string(), "prod eu".to_string())]
+    }
+
+    #[test]
+    fn writes_points_over_http() {
+        let (url, requests) = http_listener();
+        let errs = Arc::new(Mutex::new(Vec::new()));
+        let mut sink = InfluxSink::new(&format!("{}/api/v2/write?bucket=b", url), Some("secret".into()), TargetGroups::new("default"), &tags(), false, errs.clone()).unwrap();
+        sink.handle(&SinkEvent::Update { data: result(1, 12.5) });
+        sink.handle(&SinkEvent::Update { data: result(2, -1.0) });
+        sink.flush();
+
+        let (request, body) = requests.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(request, "POST /api/v2/write?bucket=b HTTP/1.1");
+        assert_eq!(body, "nping,target=example.com,ip=192.0.2.1,probe=icmp,group=default,env=prod\\ eu rtt_ms=12.5,timeout=false,seq=1i 1700000000000000000\n\
+                          nping,target=example.com,ip=192.0.2.1,probe=icmp,group=default,env=prod\\ eu timeout=true,seq=2i 1700000000000000000");
+        assert!(errs.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn writes_interval_points_over_udp() {
+        let (socket, addr) = udp_listener();
+        let errs = Arc::new(Mutex::new(Vec::new()));
+        let groups = TargetGroups::new("default");
+        groups.set("example.com", Some("web"));
+        let mut sink = InfluxSink::new(&format!("udp://{}", addr), None, groups, &[], true, errs).unwrap();
+        sink.handle(&SinkEvent::Update { data: result(1, 10.0) });
+        sink.handle(&SinkEvent::Update { data: result(2, 20.0) });
+        sink.handle(&SinkEvent::Update { data: result(3, -1.0) });
+        sink.flush();
+
+        let lines = recv_lines(&socket);
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("nping_interval,target=example.com,ip=192.0.2.1,probe=icmp,group=web sent=3i,received=2i,loss=33.33"));
+        assert!(lines[0].contains(",min_ms=10,avg_ms=15,max_ms=20,jitter_ms=10 "));
+    }
+
+    #[test]
+    fn intervals_without_replies_have_no_rtt_fields() {
+        let (socket, addr) = udp_listener();
+        let errs = Arc::new(Mutex::new(Vec::new()));
+        let mut sink = InfluxSink::new(&format!("udp://{}", addr), None, TargetGroups::new("default"), &[], true, errs).unwrap();
+        sink.handle(&SinkEvent::Update { data: result(1, -1.0) });
+        sink.flush();
+
+        let lines = recv_lines(&socket);
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("nping_interval,target=example.com,ip=192.0.2.1,probe=icmp,group=default sent=1i,received=0i,loss=100 "), "{}", lines[0]);
+    }
+}
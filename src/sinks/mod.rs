@@ -0,0 +1,547 @@
+mod alert;
+mod counters;
+mod prometheus;
+mod influx;
+mod statsd;
+mod otlp;
+mod mqtt;
+#[cfg(unix)]
+mod syslog;
+
+pub use alert::AlertSink;
+pub use prometheus::PrometheusSink;
+pub use influx::InfluxSink;
+pub use statsd::StatsdSink;
+pub use otlp::OtlpSink;
+pub use mqtt::{parse_mqtt_url, MqttBroker, MqttSink};
+#[cfg(unix)]
+pub use syslog::{LogTarget, SyslogSink, JOURNALD_SOCKET};
+
+use std::collections::{HashMap, VecDeque};
+use std::net::UdpSocket;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender, TrySendError};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use crate::health::HealthTransition;
+use crate::ip_data::IpData;
+use crate::error::lock;
+
+// events buffered per sink before new ones are dropped
+const SINK_QUEUE_SIZE: usize = 1024;
+// lines kept while a metrics endpoint is unreachable, the oldest are dropped first
+const MAX_BUFFERED_LINES: usize = 10_000;
+
+/// Events the data processor publishes to sinks
+#[derive(Debug, Clone)]
+pub enum SinkEvent {
+    // a new reply or timeout, data is the target right after it
+    Update {
+        data: IpData,
+    },
+    // the health state of a target changed, data is the target right after the change
+    Transition {
+        transition: HealthTransition,
+        data: IpData,
+    },
+    // a hostname resolves to other addresses, pinging moved from old_ips to new_ips
+    DnsChange {
+        addr: String,
+        old_ips: Vec<String>,
+        new_ips: Vec<String>,
+    },
+    // ping itself failed for a target
+    ProbeError {
+        addr: String,
+        ip: String,
+        message: String,
+    },
+}
+
+/// A consumer of data processor events, each sink runs on its own thread
+pub trait Sink: Send {
+    fn handle(&mut self, event: &SinkEvent);
+
+    // sinks that only act on transitions and errors are not sent every result
+    fn wants_updates(&self) -> bool {
+        true
+    }
+
+    // sinks that must not miss a result hold up the data processor instead of dropping one
+    fn lossless(&self) -> bool {
+        false
+    }
+
+    // called every flush interval and once before the sink stops
+    fn flush(&mut self) {}
+}
+
+struct SinkWorker {
+    name: String,
+    tx: SyncSender<SinkEvent>,
+    wants_updates: bool,
+    lossless: bool,
+    handle: JoinHandle<()>,
+    dropped: usize,
+}
+
+/// Fan out events to all sinks, a slow sink only holds up the data processor for events it must not miss
+#[derive(Default)]
+pub struct SinkHub {
+    workers: Vec<SinkWorker>,
+}
+
+impl SinkHub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, name: &str, mut sink: Box<dyn Sink>, flush_interval: Duration) {
+        let (tx, rx) = mpsc::sync_channel::<SinkEvent>(SINK_QUEUE_SIZE);
+        let (wants_updates, lossless) = (sink.wants_updates(), sink.lossless());
+        let handle = std::thread::spawn(move || {
+            let mut last_flush = Instant::now();
+            loop {
+                // a steady stream of events must not hold back the flush
+                match rx.recv_timeout(flush_interval.saturating_sub(last_flush.elapsed())) {
+                    Ok(event) => sink.handle(&event),
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => {
+                        sink.flush();
+                        break;
+                    }
+                }
+                if last_flush.elapsed() >= flush_interval {
+                    sink.flush();
+                    last_flush = Instant::now();
+                }
+            }
+        });
+        self.workers.push(SinkWorker {
+            name: name.to_string(),
+            tx,
+            wants_updates,
+            lossless,
+            handle,
+            dropped: 0,
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.workers.is_empty()
+    }
+
+    /// hand an event to every sink, results for a sink whose queue is full are dropped while
+    /// transitions and errors wait for room, an alert must not get lost behind a slow sink,
+    /// lossless sinks wait for room for every event
+    pub fn publish(&mut self, event: SinkEvent) {
+        let update = matches!(event, SinkEvent::Update { .. });
+        for worker in &mut self.workers {
+            if !update || worker.lossless {
+                worker.tx.send(event.clone()).ok();
+                continue;
+            }
+            if !worker.wants_updates {
+                continue;
+            }
+            match worker.tx.try_send(event.clone()) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => worker.dropped += 1,
+                Err(TrySendError::Disconnected(_)) => {}
+            }
+        }
+    }
+
+    /// stop all sinks and wait until they flushed what they buffered
+    pub fn shutdown(self) {
+        for worker in self.workers {
+            drop(worker.tx);
+            if worker.handle.join().is_err() {
+                eprintln!("sink {} stopped unexpectedly", worker.name);
+            }
+            if worker.dropped > 0 {
+                eprintln!("sink {} was too slow, {} events were dropped", worker.name, worker.dropped);
+            }
+        }
+    }
+}
+
+/// parse a `KEY=VALUE` tag attached to pushed metrics
+pub fn parse_tag(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() && !value.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("invalid tag: {}, expected KEY=VALUE", s)),
+    }
+}
+
+/// Lines waiting to be pushed to a metrics endpoint, kept across failed flushes
+pub(crate) struct LineBuffer {
+    name: &'static str,
+    lines: VecDeque<String>,
+    dropped: usize,
+    // the last flush failed, errors are only reported when this changes
+    failing: bool,
+    errs: Arc<Mutex<Vec<String>>>,
+}
+
+impl LineBuffer {
+    pub fn new(name: &'static str, errs: Arc<Mutex<Vec<String>>>) -> Self {
+        Self { name, lines: VecDeque::new(), dropped: 0, failing: false, errs }
+    }
+
+    pub fn push(&mut self, line: String) {
+        if self.lines.len() >= MAX_BUFFERED_LINES {
+            self.lines.pop_front();
+            self.dropped += 1;
+        }
+        self.lines.push_back(line);
+    }
+
+    /// hand all buffered lines to `send`, when it fails it returns how many lines went out
+    /// before the error, only the rest are kept for the next flush so nothing is sent twice
+    pub fn flush_with(&mut self, send: impl FnOnce(&[String]) -> Result<(), (usize, String)>) {
+        if self.lines.is_empty() {
+            return;
+        }
+        let res = send(self.lines.make_contiguous());
+        let mut errs = lock(&self.errs);
+        match res {
+            Ok(()) => {
+                self.lines.clear();
+                if self.failing {
+                    errs.push(format!("{}: delivering again, {} lines were dropped meanwhile", self.name, self.dropped));
+                }
+                self.failing = false;
+                self.dropped = 0;
+            }
+            Err((sent, err)) => {
+                self.lines.drain(..sent.min(self.lines.len()));
+                if !self.failing {
+                    errs.push(format!("{}: {}, buffering up to {} lines", self.name, err, MAX_BUFFERED_LINES));
+                }
+                self.failing = true;
+            }
+        }
+    }
+}
+
+// split lines into datagrams that stay below a typical MTU, each with the number of lines in it
+fn udp_packets(lines: &[String], max_size: usize) -> Vec<(String, usize)> {
+    let mut packets = Vec::new();
+    let mut packet = String::new();
+    let mut count = 0;
+    for line in lines {
+        if !packet.is_empty() && packet.len() + line.len() + 1 > max_size {
+            packets.push((std::mem::take(&mut packet), count));
+            count = 0;
+        }
+        if !packet.is_empty() {
+            packet.push('\n');
+        }
+        packet.push_str(line);
+        count += 1;
+    }
+    if !packet.is_empty() {
+        packets.push((packet, count));
+    }
+    packets
+}
+
+/// send lines as datagrams for LineBuffer::flush_with, an error carries the lines already sent
+pub(crate) fn send_udp(socket: &UdpSocket, addr: &str, lines: &[String], max_size: usize) -> Result<(), (usize, String)> {
+    let mut sent = 0;
+    for (packet, count) in udp_packets(lines, max_size) {
+        socket.send_to(packet.as_bytes(), addr).map_err(|e| (sent, e.to_string()))?;
+        sent += count;
+    }
+    Ok(())
+}
+
+/// The group label of each target, the group it has in the config file or --group,
+/// shared with the ping tasks so targets added by a reload get theirs too
+#[derive(Clone, Default)]
+pub struct TargetGroups {
+    default: String,
+    groups: Arc<Mutex<HashMap<String, String>>>, // key: addr
+}
+
+impl TargetGroups {
+    pub fn new(default: &str) -> Self {
+        Self { default: default.to_string(), groups: Arc::default() }
+    }
+
+    /// set the config file group of a target, None when it has none
+    pub fn set(&self, addr: &str, group: Option<&str>) {
+        let mut groups = lock(&self.groups);
+        match group {
+            Some(group) => groups.insert(addr.to_string(), group.to_string()),
+            None => groups.remove(addr),
+        };
+    }
+
+    /// the group of a target in the config file
+    pub fn configured(&self, addr: &str) -> Option<String> {
+        lock(&self.groups).get(addr).cloned()
+    }
+
+    /// the group label of a target
+    pub fn of(&self, addr: &str) -> String {
+        self.configured(addr).unwrap_or_else(|| self.default.clone())
+    }
+}
+
+// local listeners the sink tests deliver to
+#[cfg(test)]
+pub(crate) mod testing {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc::{self, Receiver};
+
+    /// a UDP socket to send datagrams to, with its address
+    pub(crate) fn udp_listener() -> (std::net::UdpSocket, String) {
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(std::time::Duration::from_secs(5))).unwrap();
+        let addr = socket.local_addr().unwrap().to_string();
+        (socket, addr)
+    }
+
+    /// the next datagram, one string per line
+    pub(crate) fn recv_lines(socket: &std::net::UdpSocket) -> Vec<String> {
+        let mut buf = [0; 2048];
+        let len = socket.recv(&mut buf).unwrap();
+        String::from_utf8_lossy(&buf[..len]).lines().map(str::to_string).collect()
+    }
+
+    /// a target after a reply, or a timeout for a negative rtt
+    pub(crate) fn result(seq: usize, rtt: f64) -> crate::ip_data::IpData {
+        let mut data = crate::ip_data::IpData::new("example.com".to_string(), "192.0.2.1".to_string());
+        data.last_seq = seq;
+        data.last_attr = rtt;
+        data.last_timestamp = chrono::DateTime::from_timestamp(1_700_000_000, 0).map(|t| t.into());
+        data
+    }
+
+    /// an HTTP server answering 200 to everything, returns its url and the requests as (request line, body)
+    pub(crate) fn http_listener() -> (String, Receiver<(String, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { break };
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                // recorded before the answer, the client returns once it has it
+                if tx.send((request_line.trim().to_string(), String::from_utf8_lossy(&body).into_owned())).is_err() {
+                    break;
+                }
+                stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").ok();
+            }
+        });
+        (url, rx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Local;
+    use crate::health::HealthState;
+
+    // records what it gets, the first event blocks until `release` is dropped
+    struct SlowSink {
+        events: Arc<Mutex<Vec<&'static str>>>,
+        release: Option<mpsc::Receiver<()>>,
+        wants_updates: bool,
+    }
+
+    impl Sink for SlowSink {
+        fn wants_updates(&self) -> bool {
+            self.wants_updates
+        }
+
+        fn handle(&mut self, event: &SinkEvent) {
+            if let Some(release) = self.release.take() {
+                release.recv().ok();
+            }
+            let kind = match event {
+                SinkEvent::Update { .. } => "update",
+                SinkEvent::Transition { .. } => "transition",
+                SinkEvent::DnsChange { .. } => "dns",
+                SinkEvent::ProbeError { .. } => "error",
+            };
+            self.events.lock().unwrap().push(kind);
+        }
+    }
+
+    fn slow_sink(hub: &mut SinkHub, wants_updates: bool) -> (Arc<Mutex<Vec<&'static str>>>, mpsc::Sender<()>) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let (release_tx, release) = mpsc::channel();
+        let sink = SlowSink { events: events.clone(), release: Some(release), wants_updates };
+        hub.add("slow", Box::new(sink), Duration::from_secs(60));
+        (events, release_tx)
+    }
+
+    fn transition() -> SinkEvent {
+        let data = IpData::new("example.com".to_string(), "192.0.2.1".to_string());
+        let transition = HealthTransition {
+            addr: data.addr.clone(),
+            ip: data.ip.clone(),
+            from: HealthState::Ok,
+            to: HealthState::Crit,
+            at: Local::now(),
+            reason: "loss 100.00% > 50".to_string(),
+        };
+        SinkEvent::Transition { transition, data }
+    }
+
+    #[test]
+    fn transitions_are_kept_when_results_are_dropped() {
+        let mut hub = SinkHub::new();
+        let (events, release) = slow_sink(&mut hub, true);
+        let data = IpData::new("example.com".to_string(), "192.0.2.1".to_string());
+        for _ in 0..SINK_QUEUE_SIZE * 2 {
+            hub.publish(SinkEvent::Update { data: data.clone() });
+        }
+        assert!(hub.workers[0].dropped > 0);
+
+        // waits for room in the queue instead of being dropped
+        let publisher = std::thread::spawn(move || {
+            hub.publish(transition());
+            hub
+        });
+        std::thread::sleep(Duration::from_millis(50));
+        drop(release);
+        publisher.join().unwrap().shutdown();
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.last(), Some(&"transition"));
+        assert!(events.iter().filter(|e| **e == "update").count() <= SINK_QUEUE_SIZE + 1);
+    }
+
+    #[test]
+    fn lines_sent_before_a_failure_are_not_sent_again() {
+        let errs = Arc::new(Mutex::new(Vec::new()));
+        let mut buffer = LineBuffer::new("test", errs.clone());
+        (0..5).for_each(|i| buffer.push(format!("line{}", i)));
+
+        buffer.flush_with(|lines| Err((2, format!("failed after 2 of {}", lines.len()))));
+        assert_eq!(*errs.lock().unwrap(), ["test: failed after 2 of 5, buffering up to 10000 lines"]);
+
+        let mut sent = Vec::new();
+        buffer.flush_with(|lines| {
+            sent = lines.to_vec();
+            Ok(())
+        });
+        assert_eq!(sent, ["line2", "line3", "line4"]);
+    }
+
+    #[test]
+    fn datagrams_stay_below_the_size_limit() {
+        let lines: Vec<String> = (0..10).map(|i| format!("metric.{}:1|c", i)).collect();
+        let packets = udp_packets(&lines, 40);
+        assert!(packets.iter().all(|(packet, _)| packet.len() <= 40));
+        assert_eq!(packets.iter().map(|(_, count)| count).sum::<usize>(), 10);
+        assert_eq!(packets[0], ("metric.0:1|c\nmetric.1:1|c\nmetric.2:1|c".to_string(), 3));
+    }
+
+    #[test]
+    fn parse_tags() {
+        assert_eq!(parse_tag("env=prod"), Ok(("env".to_string(), "prod".to_string())));
+        assert_eq!(parse_tag("url=a=b"), Ok(("url".to_string(), "a=b".to_string())));
+        assert!(parse_tag("env").is_err());
+        assert!(parse_tag("=prod").is_err());
+        assert!(parse_tag("env=").is_err());
+    }
+
+    #[test]
+    fn lossless_sinks_get_every_result() {
+        struct Recorder(Arc<Mutex<usize>>);
+        impl Sink for Recorder {
+            fn lossless(&self) -> bool {
+                true
+            }
+            fn handle(&mut self, _: &SinkEvent) {
+                std::thread::sleep(Duration::from_micros(10));
+                *self.0.lock().unwrap() += 1;
+            }
+        }
+
+        let mut hub = SinkHub::new();
+        let count = Arc::new(Mutex::new(0));
+        hub.add("record", Box::new(Recorder(count.clone())), Duration::from_secs(60));
+        let data = IpData::new("example.com".to_string(), "192.0.2.1".to_string());
+        for _ in 0..SINK_QUEUE_SIZE * 3 {
+            hub.publish(SinkEvent::Update { data: data.clone() });
+        }
+        assert_eq!(hub.workers[0].dropped, 0);
+        hub.shutdown();
+        assert_eq!(*count.lock().unwrap(), SINK_QUEUE_SIZE * 3);
+    }
+
+    #[test]
+    fn updates_are_not_sent_to_sinks_without_interest() {
+        let mut hub = SinkHub::new();
+        let (events, release) = slow_sink(&mut hub, false);
+        drop(release);
+        hub.publish(SinkEvent::Update { data: IpData::new("example.com".to_string(), "192.0.2.1".to_string()) });
+        hub.publish(transition());
+        hub.shutdown();
+        assert_eq!(*events.lock().unwrap(), ["transition"]);
+    }
+
+    // counts the events it handled and how often it was flushed
+    struct CountingSink(Arc<Mutex<(usize, usize)>>);
+
+    impl Sink for CountingSink {
+        fn handle(&mut self, _event: &SinkEvent) {
+            self.0.lock().unwrap().0 += 1;
+        }
+
+        fn flush(&mut self) {
+            self.0.lock().unwrap().1 += 1;
+        }
+    }
+
+    #[test]
+    fn events_reach_every_sink_and_are_flushed_at_shutdown() {
+        let mut hub = SinkHub::new();
+        let counts: Vec<_> = (0..2).map(|_| Arc::new(Mutex::new((0, 0)))).collect();
+        for count in &counts {
+            hub.add("counting", Box::new(CountingSink(count.clone())), Duration::from_secs(60));
+        }
+        let data = IpData::new("example.com".to_string(), "192.0.2.1".to_string());
+        for _ in 0..3 {
+            let transition = HealthTransition {
+                addr: data.addr.clone(),
+                ip: data.ip.clone(),
+                from: HealthState::Ok,
+                to: HealthState::Crit,
+                at: Local::now(),
+                reason: "loss 100.00% > 50".to_string(),
+            };
+            hub.publish(SinkEvent::Transition { transition, data: data.clone() });
+        }
+        hub.shutdown();
+        for count in counts {
+            assert_eq!(*count.lock().unwrap(), (3, 1));
+        }
+    }
+
+}
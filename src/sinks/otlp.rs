@@ -0,0 +1,223 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::Local;
+use serde_json::{json, Value};
+use crate::sinks::counters::{TargetMetrics, RTT_BUCKETS};
+use crate::sinks::{Sink, SinkEvent, TargetGroups};
+use crate::error::lock;
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+// cumulative temporality, every export carries the totals since start
+const CUMULATIVE: u8 = 2;
+
+/// Export per target metrics to an OpenTelemetry collector over OTLP/HTTP with JSON encoding
+pub struct OtlpSink {
+    url: String,
+    resource: Vec<Value>,
+    groups: TargetGroups,
+    start_nanos: i64,
+    metrics: BTreeMap<String, TargetMetrics>, // key: addr_ip
+    // the last export failed, errors are only reported when this changes
+    failing: bool,
+    errs: Arc<Mutex<Vec<String>>>,
+}
+
+impl OtlpSink {
+    /// `endpoint` is the collector base url like http://localhost:4318, /v1/metrics is appended
+    pub fn new(endpoint: &str, groups: TargetGroups, hostname: String, errs: Arc<Mutex<Vec<String>>>) -> Self {
+        let url = if endpoint.ends_with("/v1/metrics") {
+            endpoint.to_string()
+        } else {
+            format!("{}/v1/metrics", endpoint.trim_end_matches('/'))
+        };
+        let start_nanos = Local::now().timestamp_nanos_opt().unwrap_or_default();
+        let resource = vec![
+            attribute("service.name", "nping"),
+            attribute("service.version", env!("CARGO_PKG_VERSION")),
+            attribute("host.name", &hostname),
+            // tells the series of two runs on the same host apart
+            attribute("nping.session.id", &format!("{:x}-{:x}", start_nanos, std::process::id())),
+        ];
+        Self {
+            url,
+            resource,
+            groups,
+            start_nanos,
+            metrics: BTreeMap::new(),
+            failing: false,
+            errs,
+        }
+    }
+
+    fn request(&self) -> Value {
+        let now = Local::now().timestamp_nanos_opt().unwrap_or_default().to_string();
+        let start = self.start_nanos.to_string();
+        let point = |m: &TargetMetrics, value: Value| {
+            let mut point = json!({
+                "attributes": [
+                    attribute("target", &m.addr),
+                    attribute("ip", &m.ip),
+                    attribute("probe", "icmp"),
+                    attribute("group", &self.groups.of(&m.addr)),
+                ],
+                "startTimeUnixNano": start,
+                "timeUnixNano": now,
+            });
+            if let (Some(point), Value::Object(value)) = (point.as_object_mut(), value) {
+                point.extend(value);
+            }
+            point
+        };
+        let counter = |name: &str, description: &str, value: fn(&TargetMetrics) -> usize| {
+            json!({
+                "name": name,
+                "description": description,
+                "unit": "{packet}",
+                "sum": {
+                    "aggregationTemporality": CUMULATIVE,
+                    "isMonotonic": true,
+                    "dataPoints": self.metrics.values()
+                        .map(|m| point(m, json!({ "asInt": value(m).to_string() })))
+                        .collect::<Vec<_>>(),
+                },
+            })
+        };
+
+        let metrics = vec![
+            json!({
+                "name": "nping.rtt",
+                "description": "Round trip time of the replies",
+                "unit": "s",
+                "histogram": {
+                    "aggregationTemporality": CUMULATIVE,
+                    "dataPoints": self.metrics.values().map(|m| {
+                        // the last bucket counts everything above the highest bound
+                        let mut counts: Vec<String> = m.buckets.iter().map(|c| c.to_string()).collect();
+                        counts.push((m.rtt_count - m.buckets.iter().sum::<u64>()).to_string());
+                        point(m, json!({
+                            "count": m.rtt_count.to_string(),
+                            "sum": m.rtt_sum,
+                            "bucketCounts": counts,
+                            "explicitBounds": RTT_BUCKETS,
+                        }))
+                    }).collect::<Vec<_>>(),
+                },
+            }),
+            counter("nping.packets.sent", "Probes sent", |m| m.sent),
+            counter("nping.packets.received", "Replies received", |m| m.received),
+            counter("nping.packets.lost", "Probes without a reply", |m| m.timeouts),
+            counter("nping.errors", "Failures of the ping command", |m| m.errors),
+            json!({
+                "name": "nping.health.state",
+                "description": "Health state, 0 OK, 1 WARN, 2 CRIT",
+                "unit": "1",
+                "gauge": {
+                    "dataPoints": self.metrics.values()
+                        .map(|m| point(m, json!({ "asInt": (m.health as u8).to_string() })))
+                        .collect::<Vec<_>>(),
+                },
+            }),
+        ];
+
+        json!({
+            "resourceMetrics": [{
+                "resource": { "attributes": self.resource },
+                "scopeMetrics": [{
+                    "scope": { "name": "nping", "version": env!("CARGO_PKG_VERSION") },
+                    "metrics": metrics,
+                }],
+            }],
+        })
+    }
+}
+
+impl Sink for OtlpSink {
+    fn handle(&mut self, event: &SinkEvent) {
+        let SinkEvent::Update { data } = event else {
+            return;
+        };
+        self.metrics
+            .entry(format!("{}_{}", data.addr, data.ip))
+            .or_insert_with(|| TargetMetrics {
+                addr: data.addr.clone(),
+                ip: data.ip.clone(),
+                ..Default::default()
+            })
+            .observe(data);
+    }
+
+    fn flush(&mut self) {
+        if self.metrics.is_empty() {
+            return;
+        }
+        let res = ureq::post(&self.url)
+            .timeout(HTTP_TIMEOUT)
+            .set("Content-Type", "application/json")
+            .send_string(&self.request().to_string());
+
+        // the metrics are cumulative, a failed export is made up by the next one
+        match res {
+            Ok(_) => self.failing = false,
+            Err(err) => {
+                if !self.failing {
+                    lock(&self.errs).push(format!("otlp export to {} failed: {}", self.url, err));
+                }
+                self.failing = true;
+            }
+        }
+    }
+}
+
+fn attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sinks::testing::{http_listener, result};
+
+    #[test]
+    fn exports_cumulative_metrics_as_json() {
+        let (url, requests) = http_listener();
+        let errs = Arc::new(Mutex::new(Vec::new()));
+        let groups = TargetGroups::new("core");
+        groups.set("example.com", Some("web"));
+        let mut sink = OtlpSink::new(&format!("{}/", url), groups, "probe-1".into(), errs.clone());
+        sink.flush();
+
+        let mut data = result(1, 12.5);
+        data.received = 1;
+        sink.handle(&SinkEvent::Update { data: data.clone() });
+        data.timeout = 1;
+        data.last_attr = -1.0;
+        sink.handle(&SinkEvent::Update { data });
+        sink.flush();
+
+        // nothing is exported before the first result
+        let (request, body) = requests.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(request, "POST /v1/metrics HTTP/1.1");
+        assert!(errs.lock().unwrap().is_empty());
+
+        let body: Value = serde_json::from_str(&body).unwrap();
+        let resource = &body["resourceMetrics"][0];
+        assert_eq!(resource["resource"]["attributes"][2], attribute("host.name", "probe-1"));
+        let metrics = resource["scopeMetrics"][0]["metrics"].as_array().unwrap();
+
+        let rtt = &metrics[0]["histogram"]["dataPoints"][0];
+        assert_eq!(rtt["count"], "1");
+        assert_eq!(rtt["bucketCounts"][4], "1");
+        assert_eq!(rtt["bucketCounts"].as_array().unwrap().len(), RTT_BUCKETS.len() + 1);
+        // the group of the target in the config file wins over --group
+        assert!(rtt["attributes"].as_array().unwrap().contains(&attribute("group", "web")));
+
+        let counter = |name: &str| {
+            let metric = metrics.iter().find(|m| m["name"] == name).unwrap();
+            metric["sum"]["dataPoints"][0]["asInt"].clone()
+        };
+        assert_eq!(counter("nping.packets.sent"), "2");
+        assert_eq!(counter("nping.packets.received"), "1");
+        assert_eq!(counter("nping.packets.lost"), "1");
+    }
+}
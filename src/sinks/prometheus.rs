@@ -0,0 +1,208 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use crate::sinks::counters::{TargetMetrics, RTT_BUCKETS};
+use crate::sinks::{Sink, SinkEvent, TargetGroups};
+use crate::error::lock;
+
+// reads one counter of a target
+type Counter = fn(&TargetMetrics) -> usize;
+
+type Metrics = Arc<Mutex<BTreeMap<String, TargetMetrics>>>; // key: addr_ip
+
+/// Serve per target metrics in the Prometheus text format on /metrics
+pub struct PrometheusSink {
+    metrics: Metrics,
+}
+
+impl PrometheusSink {
+    /// bind the listener, requests are answered on a background thread
+    pub fn new(listen: &str, groups: TargetGroups, errs: Arc<Mutex<Vec<String>>>) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(listen)?;
+        let metrics: Metrics = Arc::default();
+
+        let served = metrics.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let res = stream.and_then(|stream| serve(stream, &served, &groups));
+                if let Err(err) = res {
+                    lock(&errs).push(format!("prometheus exporter: {}", err));
+                }
+            }
+        });
+        Ok(Self { metrics })
+    }
+}
+
+impl Sink for PrometheusSink {
+    fn handle(&mut self, event: &SinkEvent) {
+        let SinkEvent::Update { data } = event else {
+            return;
+        };
+        let mut metrics = lock(&self.metrics);
+        let target = metrics.entry(format!("{}_{}", data.addr, data.ip)).or_insert_with(|| TargetMetrics {
+            addr: data.addr.clone(),
+            ip: data.ip.clone(),
+            ..Default::default()
+        });
+        target.observe(data);
+    }
+}
+
+fn serve(stream: TcpStream, metrics: &Metrics, groups: &TargetGroups) -> std::io::Result<()> {
+    // a client that never sends its request must not block the exporter
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // skip the headers
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+
+    let path = request_line.split_whitespace().nth(1).unwrap_or("");
+    let (status, content_type, body) = if path == "/metrics" || path.starts_with("/metrics?") {
+        let body = render(&lock(metrics), groups);
+        ("200 OK", "text/plain; version=0.0.4; charset=utf-8", body)
+    } else {
+        ("404 Not Found", "text/plain; charset=utf-8", "try /metrics\n".to_string())
+    };
+
+    let mut stream = &stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+fn render(metrics: &BTreeMap<String, TargetMetrics>, groups: &TargetGroups) -> String {
+    let mut out = String::new();
+    let labels = |m: &TargetMetrics| {
+        format!(
+            "target=\"{}\",ip=\"{}\",probe=\"icmp\",group=\"{}\"",
+            escape(&m.addr),
+            escape(&m.ip),
+            escape(&groups.of(&m.addr))
+        )
+    };
+
+    let counters: [(&str, &str, Counter); 4] = [
+        ("nping_sent_total", "Probes sent", |m| m.sent),
+        ("nping_received_total", "Replies received", |m| m.received),
+        ("nping_timeouts_total", "Probes without a reply", |m| m.timeouts),
+        ("nping_errors_total", "Failures of the ping command", |m| m.errors),
+    ];
+    for (name, help, value) in counters {
+        writeln!(out, "# HELP {} {}\n# TYPE {} counter", name, help, name).ok();
+        for m in metrics.values() {
+            writeln!(out, "{}{{{}}} {}", name, labels(m), value(m)).ok();
+        }
+    }
+
+    writeln!(out, "# HELP nping_last_rtt_seconds Round trip time of the latest reply, NaN after a timeout").ok();
+    writeln!(out, "# TYPE nping_last_rtt_seconds gauge").ok();
+    for m in metrics.values() {
+        let value = m.last_rtt.map(|v| v.to_string()).unwrap_or_else(|| "NaN".to_string());
+        writeln!(out, "nping_last_rtt_seconds{{{}}} {}", labels(m), value).ok();
+    }
+
+    writeln!(out, "# HELP nping_health_state Health state, 0 OK, 1 WARN, 2 CRIT").ok();
+    writeln!(out, "# TYPE nping_health_state gauge").ok();
+    for m in metrics.values() {
+        writeln!(out, "nping_health_state{{{}}} {}", labels(m), m.health as u8).ok();
+    }
+
+    writeln!(out, "# HELP nping_rtt_seconds Round trip time of the replies").ok();
+    writeln!(out, "# TYPE nping_rtt_seconds histogram").ok();
+    for m in metrics.values() {
+        let labels = labels(m);
+        let mut cumulative = 0;
+        for (le, count) in RTT_BUCKETS.iter().zip(m.buckets.iter()) {
+            cumulative += count;
+            writeln!(out, "nping_rtt_seconds_bucket{{{},le=\"{}\"}} {}", labels, le, cumulative).ok();
+        }
+        writeln!(out, "nping_rtt_seconds_bucket{{{},le=\"+Inf\"}} {}", labels, m.rtt_count).ok();
+        writeln!(out, "nping_rtt_seconds_sum{{{}}} {}", labels, m.rtt_sum).ok();
+        writeln!(out, "nping_rtt_seconds_count{{{}}} {}", labels, m.rtt_count).ok();
+    }
+    out
+}
+
+// label values escape backslash, double quote and line feed
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use crate::ip_data::IpData;
+
+    fn get(addr: &str, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", path, addr).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    fn value<'a>(body: &'a str, series: &str) -> &'a str {
+        body.lines()
+            .find_map(|line| line.strip_prefix(series).and_then(|rest| rest.strip_prefix(' ')))
+            .unwrap_or_else(|| panic!("no {} in\n{}", series, body))
+    }
+
+    #[test]
+    fn metrics_are_served_with_a_cumulative_histogram() {
+        // the exporter binds by itself, borrow a free port for it
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        let groups = TargetGroups::new("core\"dc");
+        let mut sink = PrometheusSink::new(&addr, groups.clone(), Arc::default()).unwrap();
+
+        let mut data = IpData::new("a\\b".to_string(), "192.0.2.1".to_string());
+        for (rtt, received, timeout) in [(1.5, 1, 0), (15.0, 2, 0), (-1.0, 2, 1), (700.0, 3, 1)] {
+            data.last_attr = rtt;
+            data.received = received;
+            data.timeout = timeout;
+            sink.handle(&SinkEvent::Update { data: data.clone() });
+        }
+
+        let response = get(&addr, "/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        let body = response.split_once("\r\n\r\n").unwrap().1;
+        let labels = r#"target="a\\b",ip="192.0.2.1",probe="icmp",group="core\"dc""#;
+        assert_eq!(value(body, &format!("nping_sent_total{{{}}}", labels)), "4");
+        assert_eq!(value(body, &format!("nping_timeouts_total{{{}}}", labels)), "1");
+        assert_eq!(value(body, &format!("nping_last_rtt_seconds{{{}}}", labels)), "0.7");
+
+        let bucket = |le: &str| value(body, &format!("nping_rtt_seconds_bucket{{{},le=\"{}\"}}", labels, le));
+        assert_eq!(bucket("0.001"), "0");
+        assert_eq!(bucket("0.002"), "1");
+        assert_eq!(bucket("0.02"), "2");
+        assert_eq!(bucket("0.5"), "2");
+        assert_eq!(bucket("1"), "3");
+        assert_eq!(bucket("+Inf"), value(body, &format!("nping_rtt_seconds_count{{{}}}", labels)));
+        assert_eq!(bucket("+Inf"), "3");
+
+        assert!(get(&addr, "/").starts_with("HTTP/1.1 404 Not Found"));
+
+        // a target in a group of the config file is labelled with it
+        groups.set("dns", Some("resolvers"));
+        let mut data = IpData::new("dns".to_string(), "192.0.2.53".to_string());
+        data.received = 1;
+        sink.handle(&SinkEvent::Update { data });
+        let response = get(&addr, "/metrics");
+        let labels = r#"target="dns",ip="192.0.2.53",probe="icmp",group="resolvers""#;
+        assert_eq!(value(&response, &format!("nping_sent_total{{{}}}", labels)), "1");
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::BTreeMap;
+use std::net::UdpSocket;
+use std::sync::{Arc, Mutex};
+use crate::ip_data::IpData;
+use crate::sinks::{send_udp, LineBuffer, Sink, SinkEvent, TargetGroups};
+use crate::stats::IntervalStats;
+
+const MAX_UDP_PACKET: usize = 1400;
+
+/// Push results as StatsD timers, counters and gauges over UDP
+pub struct StatsdSink {
+    socket: UdpSocket,
+    addr: String,
+    prefix: String,
+    // DogStatsD style key:value tags of every metric
+    tags: Vec<String>,
+    // targets in a group of the config file are tagged with it
+    groups: TargetGroups,
+    aggregate: bool,
+    intervals: BTreeMap<String, (String, IntervalStats)>, // key: metric name of the target
+    buffer: LineBuffer,
+}
+
+impl StatsdSink {
+    /// with `aggregate` the counts and rtt statistics of each flush interval
+    /// are sent instead of one timer per reply
+    pub fn new(
+        addr: &str,
+        prefix: &str,
+        tags: &[(String, String)],
+        groups: TargetGroups,
+        aggregate: bool,
+        errs: Arc<Mutex<Vec<String>>>,
+    ) -> std::io::Result<Self> {
+        Ok(Self {
+            socket: UdpSocket::bind("0.0.0.0:0")?,
+            addr: addr.to_string(),
+            prefix: prefix.to_string(),
+            tags: tags.iter().map(|(k, v)| format!("{}:{}", k, v)).collect(),
+            groups,
+            aggregate,
+            intervals: BTreeMap::new(),
+            buffer: LineBuffer::new("statsd", errs),
+        })
+    }
+
+    // prefix.target_name, dots of hostnames and ips would add levels to the hierarchy
+    fn name(&self, data: &IpData) -> String {
+        let target: String = data.addr
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+            .collect();
+        format!("{}.{}", self.prefix, target)
+    }
+
+    // the tag suffix of the metrics of a target, empty without tags
+    fn tags(&self, addr: &str) -> String {
+        let group = self.groups.configured(addr).map(|group| format!("group:{}", group));
+        let tags: Vec<&str> = group.iter().chain(&self.tags).map(String::as_str).collect();
+        if tags.is_empty() {
+            String::new()
+        } else {
+            format!("|#{}", tags.join(","))
+        }
+    }
+
+    fn metric(&mut self, (name, tags): (&str, &str), metric: &str, value: String, kind: &str) {
+        let line = format!("{}.{}:{}|{}{}", name, metric, value, kind, tags);
+        self.buffer.push(line);
+    }
+}
+
+impl Sink for StatsdSink {
+    fn handle(&mut self, event: &SinkEvent) {
+        let SinkEvent::Update { data } = event else {
+            return;
+        };
+        let name = self.name(data);
+        if self.aggregate {
+            self.intervals.entry(name).or_insert_with(|| (data.addr.clone(), IntervalStats::default())).1.push(data);
+            return;
+        }
+        let tags = self.tags(&data.addr);
+        let name = (name.as_str(), tags.as_str());
+
+        self.metric(name, "sent", "1".to_string(), "c");
+        if data.last_attr < 0.0 {
+            self.metric(name, "timeout", "1".to_string(), "c");
+        } else {
+            self.metric(name, "received", "1".to_string(), "c");
+            self.metric(name, "rtt", format!("{:.3}", data.last_attr), "ms");
+        }
+        self.metric(name, "health", (data.health as u8).to_string(), "g");
+    }
+
+    fn flush(&mut self) {
+        for (name, (addr, interval)) in std::mem::take(&mut self.intervals) {
+            let tags = self.tags(&addr);
+            let name = (name.as_str(), tags.as_str());
+            self.metric(name, "sent", interval.sent.to_string(), "c");
+            self.metric(name, "received", interval.received().to_string(), "c");
+            self.metric(name, "timeout", (interval.sent - interval.received()).to_string(), "c");
+            self.metric(name, "loss", format!("{:.2}", interval.loss()), "g");
+            if interval.received() > 0 {
+                self.metric(name, "rtt_min", format!("{:.3}", interval.min()), "g");
+                self.metric(name, "rtt_avg", format!("{:.3}", interval.avg()), "g");
+                self.metric(name, "rtt_max", format!("{:.3}", interval.max()), "g");
+                self.metric(name, "jitter", format!("{:.3}", interval.jitter()), "g");
+            }
+        }
+
+        let (socket, addr) = (&self.socket, &self.addr);
+        self.buffer.flush_with(|lines| send_udp(socket, addr, lines, MAX_UDP_PACKET));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sinks::testing::{recv_lines, result, udp_listener};
+
+    #[test]
+    fn sends_one_timer_per_reply() {
+        let (socket, addr) = udp_listener();
+        let errs = Arc::new(Mutex::new(Vec::new()));
+        let tags = [("env".to_string(), "prod".to_string())];
+        let mut sink = StatsdSink::new(&addr, "nping", &tags, TargetGroups::default(), false, errs).unwrap();
+        sink.handle(&SinkEvent::Update { data: result(1, 12.5) });
+        sink.handle(&SinkEvent::Update { data: result(2, -1.0) });
+        sink.flush();
+
+        assert_eq!(recv_lines(&socket), [
+            "nping.example_com.sent:1|c|#env:prod",
+            "nping.example_com.received:1|c|#env:prod",
+            "nping.example_com.rtt:12.500|ms|#env:prod",
+            "nping.example_com.health:0|g|#env:prod",
+            "nping.example_com.sent:1|c|#env:prod",
+            "nping.example_com.timeout:1|c|#env:prod",
+            "nping.example_com.health:0|g|#env:prod",
+        ]);
+    }
+
+    #[test]
+    fn sends_interval_aggregates() {
+        let (socket, addr) = udp_listener();
+        let errs = Arc::new(Mutex::new(Vec::new()));
+        let groups = TargetGroups::new("default");
+        groups.set("example.com", Some("web"));
+        let mut sink = StatsdSink::new(&addr, "nping", &[], groups, true, errs).unwrap();
+        sink.handle(&SinkEvent::Update { data: result(1, 10.0) });
+        sink.handle(&SinkEvent::Update { data: result(2, -1.0) });
+        sink.flush();
+
+        let lines = recv_lines(&socket);
+        assert_eq!(lines[..4], [
+            "nping.example_com.sent:2|c|#group:web",
+            "nping.example_com.received:1|c|#group:web",
+            "nping.example_com.timeout:1|c|#group:web",
+            "nping.example_com.loss:50.00|g|#group:web",
+        ]);
+        assert!(lines.contains(&"nping.example_com.rtt_avg:10.000|g|#group:web".to_string()));
+    }
+}
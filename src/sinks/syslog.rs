@@ -0,0 +1,334 @@
+use std::collections::HashMap;
+use std::os::unix::net::UnixDatagram;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Local, SecondsFormat};
+use crate::health::{HealthState, HealthTransition};
+use crate::ip_data::IpData;
+use crate::sinks::{Sink, SinkEvent};
+use crate::ui::utils::format_duration;
+use crate::error::lock;
+
+pub const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+// syslog facility daemon
+const FACILITY: u8 = 3;
+// private enterprise number of the structured data id, the one reserved for documentation
+const SD_ID: &str = "nping@32473";
+
+// syslog severities, journald uses the same values as PRIORITY
+const CRIT: u8 = 2;
+const ERR: u8 = 3;
+const WARNING: u8 = 4;
+const NOTICE: u8 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogTarget {
+    // RFC 5424 messages to a local syslog socket
+    Syslog,
+    // native protocol of the systemd journal
+    Journald,
+}
+
+// one log entry, the fields become structured data or journal fields
+struct LogEntry {
+    kind: &'static str,
+    severity: u8,
+    at: DateTime<Local>,
+    message: String,
+    fields: Vec<(&'static str, String)>,
+}
+
+/// Log health state changes, outages and ping errors, not every result
+pub struct SyslogSink {
+    target: LogTarget,
+    socket: UnixDatagram,
+    path: String,
+    hostname: String,
+    // targets with an outage in progress at their last update
+    in_outage: HashMap<String, bool>, // key: addr_ip
+    failing: bool,
+    errs: Arc<Mutex<Vec<String>>>,
+}
+
+impl SyslogSink {
+    pub fn new(target: LogTarget, path: &str, hostname: String, errs: Arc<Mutex<Vec<String>>>) -> std::io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        Ok(Self {
+            target,
+            socket,
+            path: path.to_string(),
+            hostname,
+            in_outage: HashMap::new(),
+            failing: false,
+            errs,
+        })
+    }
+
+    fn log(&mut self, entry: LogEntry) {
+        let datagram = match self.target {
+            LogTarget::Syslog => syslog_message(&entry, &self.hostname),
+            LogTarget::Journald => journal_message(&entry),
+        };
+        match self.socket.send(&datagram) {
+            Ok(_) => self.failing = false,
+            Err(err) => {
+                // report once per failure streak, the log daemon may just be restarting
+                if !self.failing {
+                    lock(&self.errs).push(format!("logging to {} failed: {}", self.path, err));
+                }
+                self.failing = true;
+            }
+        }
+    }
+
+    fn transition_entry(transition: &HealthTransition) -> LogEntry {
+        let severity = match transition.to {
+            HealthState::Ok => NOTICE,
+            HealthState::Warn => WARNING,
+            HealthState::Crit => CRIT,
+        };
+        LogEntry {
+            kind: "health",
+            severity,
+            at: transition.at,
+            message: format!(
+                "{} ({}) changed from {} to {}: {}",
+                transition.addr, transition.ip, transition.from, transition.to, transition.reason
+            ),
+            fields: vec![
+                ("target", transition.addr.clone()),
+                ("ip", transition.ip.clone()),
+                ("state", transition.to.as_str().to_string()),
+                ("previous_state", transition.from.as_str().to_string()),
+                ("reason", transition.reason.clone()),
+            ],
+        }
+    }
+
+    // an outage starting or ending with this update
+    fn outage_entry(&mut self, data: &IpData) -> Option<LogEntry> {
+        let key = format!("{}_{}", data.addr, data.ip);
+        let ongoing = data.outage.current.is_some();
+        let was_ongoing = self.in_outage.insert(key, ongoing).unwrap_or(false);
+        let fields = vec![("target", data.addr.clone()), ("ip", data.ip.clone())];
+
+        if ongoing && !was_ongoing {
+            let outage = data.outage.current.as_ref()?;
+            let mut entry = LogEntry {
+                kind: "outage",
+                severity: ERR,
+                at: outage.start,
+                message: format!("{} ({}) outage started, {} packets lost", data.addr, data.ip, outage.lost),
+                fields,
+            };
+            entry.fields.push(("lost", outage.lost.to_string()));
+            Some(entry)
+        } else if !ongoing && was_ongoing {
+            let outage = data.outage.history.back()?;
+            let end = outage.end.unwrap_or_else(Local::now);
+            let duration = outage.duration(end);
+            let mut entry = LogEntry {
+                kind: "outage",
+                severity: NOTICE,
+                at: end,
+                message: format!(
+                    "{} ({}) outage ended after {}, {} packets lost",
+                    data.addr, data.ip, format_duration(duration), outage.lost
+                ),
+                fields,
+            };
+            entry.fields.push(("lost", outage.lost.to_string()));
+            entry.fields.push(("duration_s", format!("{:.3}", duration.as_secs_f64())));
+            Some(entry)
+        } else {
+            None
+        }
+    }
+}
+
+impl Sink for SyslogSink {
+    fn handle(&mut self, event: &SinkEvent) {
+        let entry = match event {
+            SinkEvent::Update { data } => self.outage_entry(data),
+            SinkEvent::Transition { transition, .. } => Some(Self::transition_entry(transition)),
+            SinkEvent::DnsChange { addr, old_ips, new_ips } => Some(LogEntry {
+                kind: "dns",
+                severity: NOTICE,
+                at: Local::now(),
+                message: format!("{} now resolves to {} instead of {}", addr, new_ips.join(", "), old_ips.join(", ")),
+                fields: vec![
+                    ("target", addr.clone()),
+                    ("ip", new_ips.join(",")),
+                    ("previous_ip", old_ips.join(",")),
+                ],
+            }),
+            SinkEvent::ProbeError { addr, ip, message } => Some(LogEntry {
+                kind: "error",
+                severity: ERR,
+                at: Local::now(),
+                message: message.clone(),
+                fields: vec![("target", addr.clone()), ("ip", ip.clone())],
+            }),
+        };
+        if let Some(entry) = entry {
+            self.log(entry);
+        }
+    }
+}
+
+// <PRI>1 TIMESTAMP HOSTNAME APP-NAME PROCID MSGID [SD-ID PARAM="VALUE" ...] MSG
+fn syslog_message(entry: &LogEntry, hostname: &str) -> Vec<u8> {
+    let params: Vec<String> = entry
+        .fields
+        .iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, sd_escape(value)))
+        .collect();
+    format!(
+        "<{}>1 {} {} nping {} {} [{} {}] {}",
+        FACILITY * 8 + entry.severity,
+        entry.at.to_rfc3339_opts(SecondsFormat::Millis, false),
+        if hostname.is_empty() { "-" } else { hostname },
+        std::process::id(),
+        entry.kind,
+        SD_ID,
+        params.join(" "),
+        entry.message,
+    )
+    .into_bytes()
+}
+
+// '"', '\' and ']' must be escaped in structured data values
+fn sd_escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | ']') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+// KEY=value lines, values with a newline use the length prefixed binary form
+fn journal_message(entry: &LogEntry) -> Vec<u8> {
+    let mut fields = vec![
+        ("MESSAGE".to_string(), entry.message.clone()),
+        ("PRIORITY".to_string(), entry.severity.to_string()),
+        ("SYSLOG_IDENTIFIER".to_string(), "nping".to_string()),
+        ("NPING_EVENT".to_string(), entry.kind.to_string()),
+        ("NPING_TIMESTAMP".to_string(), entry.at.to_rfc3339()),
+    ];
+    fields.extend(entry.fields.iter().map(|(name, value)| (format!("NPING_{}", name.to_uppercase()), value.clone())));
+
+    let mut out = Vec::new();
+    for (name, value) in fields {
+        out.extend_from_slice(name.as_bytes());
+        if value.contains('\n') {
+            out.push(b'\n');
+            out.extend_from_slice(&(value.len() as u64).to_le_bytes());
+            out.extend_from_slice(value.as_bytes());
+        } else {
+            out.push(b'=');
+            out.extend_from_slice(value.as_bytes());
+        }
+        out.push(b'\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sink(target: LogTarget) -> (SyslogSink, UnixDatagram) {
+        let path = std::env::temp_dir().join(format!("nping-syslog-{}-{:?}.sock", std::process::id(), target));
+        std::fs::remove_file(&path).ok();
+        let daemon = UnixDatagram::bind(&path).unwrap();
+        daemon.set_read_timeout(Some(std::time::Duration::from_secs(5))).unwrap();
+        let sink = SyslogSink::new(target, path.to_str().unwrap(), "probe-1".into(), Arc::new(Mutex::new(Vec::new()))).unwrap();
+        std::fs::remove_file(&path).ok();
+        (sink, daemon)
+    }
+
+    fn recv(daemon: &UnixDatagram) -> String {
+        let mut buf = [0; 4096];
+        let len = daemon.recv(&mut buf).unwrap();
+        String::from_utf8_lossy(&buf[..len]).into_owned()
+    }
+
+    fn transition() -> SinkEvent {
+        let data = IpData::new("example.com".into(), "192.0.2.1".into());
+        let transition = HealthTransition {
+            addr: data.addr.clone(),
+            ip: data.ip.clone(),
+            from: HealthState::Ok,
+            to: HealthState::Crit,
+            at: Local::now(),
+            reason: "loss 100.00% > 50".into(),
+        };
+        SinkEvent::Transition { transition, data }
+    }
+
+    #[test]
+    fn health_changes_carry_structured_data() {
+        let (mut sink, daemon) = sink(LogTarget::Syslog);
+        sink.handle(&transition());
+        let message = recv(&daemon);
+        assert!(message.starts_with("<26>1 "));
+        assert!(message.contains(" probe-1 nping "));
+        assert!(message.ends_with(
+            " health [nping@32473 target=\"example.com\" ip=\"192.0.2.1\" state=\"CRIT\" previous_state=\"OK\" reason=\"loss 100.00% > 50\"] example.com (192.0.2.1) changed from OK to CRIT: loss 100.00% > 50"
+        ));
+    }
+
+    fn dns_change() -> SinkEvent {
+        SinkEvent::DnsChange {
+            addr: "example.com".into(),
+            old_ips: vec!["192.0.2.1".into()],
+            new_ips: vec!["192.0.2.2".into()],
+        }
+    }
+
+    #[test]
+    fn dns_changes_are_notices() {
+        let (mut sink, daemon) = sink(LogTarget::Syslog);
+        sink.handle(&dns_change());
+        let message = recv(&daemon);
+        assert!(message.starts_with("<29>1 "));
+        assert!(message.contains(" probe-1 nping "));
+        assert!(message.ends_with(
+            " dns [nping@32473 target=\"example.com\" ip=\"192.0.2.2\" previous_ip=\"192.0.2.1\"] example.com now resolves to 192.0.2.2 instead of 192.0.2.1"
+        ));
+    }
+
+    #[test]
+    fn journal_fields() {
+        let (mut sink, daemon) = sink(LogTarget::Journald);
+        sink.handle(&SinkEvent::ProbeError {
+            addr: "example.com".into(),
+            ip: "192.0.2.1".into(),
+            message: "ping: sendmsg: Network is unreachable".into(),
+        });
+        let message = recv(&daemon);
+        assert!(message.contains("MESSAGE=ping: sendmsg: Network is unreachable\n"));
+        assert!(message.contains("PRIORITY=3\n"));
+        assert!(message.contains("NPING_EVENT=error\n"));
+        assert!(message.contains("NPING_TARGET=example.com\n"));
+    }
+
+    #[test]
+    fn journal_dns_fields() {
+        let (mut sink, daemon) = sink(LogTarget::Journald);
+        sink.handle(&dns_change());
+        let message = recv(&daemon);
+        assert!(message.contains("PRIORITY=5\n"));
+        assert!(message.contains("NPING_EVENT=dns\n"));
+        assert!(message.contains("NPING_PREVIOUS_IP=192.0.2.1\n"));
+    }
+
+    #[test]
+    fn structured_data_is_escaped() {
+        assert_eq!(sd_escape(r#"a "b" [c] \d"#), r#"a \"b\" [c\] \\d"#);
+    }
+}
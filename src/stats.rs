@@ -0,0 +1,439 @@
+use std::collections::{BTreeMap, VecDeque};
+use crate::ip_data::IpData;
+
+pub fn calculate_avg_rtt(rtt: &VecDeque<f64>) -> f64 {
+    if !rtt.is_empty() {
+        let valid_rtt: Vec<f64> = rtt.iter().cloned().filter(|&rtt| rtt >= 0.0).collect();
+        if !valid_rtt.is_empty() {
+            let sum: f64 = valid_rtt.iter().sum();
+            sum / valid_rtt.len() as f64
+        } else {
+            0.0
+        }
+    } else {
+        0.0
+    }
+}
+
+// mean difference between consecutive replies, timeouts (-1.0) are skipped so loss does not count as jitter
+pub fn calculate_jitter(rtt: &VecDeque<f64>) -> f64 {
+    let rtt: Vec<f64> = rtt.iter().cloned().filter(|&rtt| rtt >= 0.0).collect();
+    if rtt.len() > 1 {
+        let diffs: Vec<f64> = rtt.iter().zip(rtt.iter().skip(1)).map(|(y1, y2)| (y2 - y1).abs()).collect();
+        let sum: f64 = diffs.iter().sum();
+        sum / diffs.len() as f64
+    } else {
+        0.0
+    }
+}
+
+pub fn calculate_loss_pkg(timeout: usize, received: usize) -> f64 {
+    if timeout > 0 {
+        (timeout as f64 / (received as f64 + timeout as f64)) * 100.0
+    } else {
+        0.0
+    }
+}
+
+// nearest-rank percentile of the valid rtts, timeouts are ignored
+pub fn calculate_percentile(rtt: &VecDeque<f64>, percentile: f64) -> f64 {
+    let mut valid_rtt: Vec<f64> = rtt.iter().cloned().filter(|&rtt| rtt >= 0.0).collect();
+    if valid_rtt.is_empty() {
+        return 0.0;
+    }
+    valid_rtt.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let rank = ((percentile / 100.0) * valid_rtt.len() as f64).ceil() as usize;
+    valid_rtt[rank.clamp(1, valid_rtt.len()) - 1]
+}
+
+// relative width of a histogram bucket, percentiles are within 1% of the exact value
+const BUCKET_GROWTH: f64 = 1.01;
+// lower edge of the first bucket in ms, anything faster ends up there
+const MIN_BUCKET_RTT: f64 = 0.01;
+
+/// Every rtt of a run in constant memory: running sums for the mean and
+/// deviation, and a log-scale histogram for the percentiles
+#[derive(Debug, Default, Clone)]
+pub struct RttHistogram {
+    count: u64,
+    sum: f64,
+    sum_sq: f64,
+    min: f64,
+    max: f64,
+    // bucket index to number of rtts, 0.01ms to a minute takes at most about 1600 buckets
+    buckets: BTreeMap<u32, u64>,
+}
+
+impl RttHistogram {
+    /// add a reply, timeouts (-1.0) are ignored
+    pub fn push(&mut self, rtt: f64) {
+        if rtt < 0.0 {
+            return;
+        }
+        if self.count == 0 || rtt < self.min {
+            self.min = rtt;
+        }
+        self.max = self.max.max(rtt);
+        self.count += 1;
+        self.sum += rtt;
+        self.sum_sq += rtt * rtt;
+        *self.buckets.entry(bucket_index(rtt)).or_default() += 1;
+    }
+
+    /// standard deviation of the rtts, the mdev printed by ping
+    pub fn mdev(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        let mean = self.sum / self.count as f64;
+        (self.sum_sq / self.count as f64 - mean * mean).max(0.0).sqrt()
+    }
+
+    /// nearest-rank percentile like calculate_percentile, up to the bucket width
+    pub fn percentile(&self, percentile: f64) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        let rank = ((percentile / 100.0) * self.count as f64).ceil().clamp(1.0, self.count as f64) as u64;
+        let mut seen = 0;
+        for (&index, &count) in &self.buckets {
+            seen += count;
+            if seen >= rank {
+                return bucket_value(index).clamp(self.min, self.max);
+            }
+        }
+        self.max
+    }
+}
+
+fn bucket_index(rtt: f64) -> u32 {
+    ((rtt.max(MIN_BUCKET_RTT) / MIN_BUCKET_RTT).ln() / BUCKET_GROWTH.ln()) as u32
+}
+
+// geometric middle of a bucket
+fn bucket_value(index: u32) -> f64 {
+    MIN_BUCKET_RTT * BUCKET_GROWTH.powf(index as f64 + 0.5)
+}
+
+/// Results of one target since the last report, for per interval aggregates
+#[derive(Debug, Default, Clone)]
+pub struct IntervalStats {
+    pub(crate) sent: usize,
+    // rtts of the replies, timeouts are only counted in sent
+    pub(crate) rtts: VecDeque<f64>,
+}
+
+impl IntervalStats {
+    /// add the latest result of a target
+    pub fn push(&mut self, data: &IpData) {
+        self.sent += 1;
+        if data.last_attr >= 0.0 {
+            self.rtts.push_back(data.last_attr);
+        }
+    }
+
+    pub fn received(&self) -> usize {
+        self.rtts.len()
+    }
+
+    pub fn loss(&self) -> f64 {
+        calculate_loss_pkg(self.sent - self.received(), self.received())
+    }
+
+    pub fn min(&self) -> f64 {
+        self.rtts.iter().cloned().reduce(f64::min).unwrap_or(0.0)
+    }
+
+    pub fn max(&self) -> f64 {
+        self.rtts.iter().cloned().reduce(f64::max).unwrap_or(0.0)
+    }
+
+    pub fn avg(&self) -> f64 {
+        calculate_avg_rtt(&self.rtts)
+    }
+
+    pub fn jitter(&self) -> f64 {
+        calculate_jitter(&self.rtts)
+    }
+}
+
+// mean and sample variance
+fn mean_var(values: &[f64]) -> (f64, f64) {
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let var = values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / (n - 1.0);
+    (mean, var)
+}
+
+/// Two sided p-value of Welch's t-test that `a` and `b` have the same mean,
+/// None if there are too few values to tell
+pub fn welch_t_test(a: &[f64], b: &[f64]) -> Option<f64> {
+    if a.len() < 2 || b.len() < 2 {
+        return None;
+    }
+    let (mean_a, var_a) = mean_var(a);
+    let (mean_b, var_b) = mean_var(b);
+    let (se_a, se_b) = (var_a / a.len() as f64, var_b / b.len() as f64);
+    let se = se_a + se_b;
+    if se <= 0.0 {
+        return Some(if mean_a == mean_b { 1.0 } else { 0.0 });
+    }
+    let t = (mean_a - mean_b) / se.sqrt();
+    // Welch–Satterthwaite degrees of freedom
+    let df = se * se / (se_a * se_a / (a.len() - 1) as f64 + se_b * se_b / (b.len() - 1) as f64);
+    Some(incomplete_beta(df / 2.0, 0.5, df / (df + t * t)))
+}
+
+/// Two sided p-value of the z-test that two loss rates are the same,
+/// None if nothing was sent or the pooled rate leaves nothing to compare
+pub fn two_proportion_z_test(lost_a: usize, sent_a: usize, lost_b: usize, sent_b: usize) -> Option<f64> {
+    if sent_a == 0 || sent_b == 0 {
+        return None;
+    }
+    let (n_a, n_b) = (sent_a as f64, sent_b as f64);
+    let pooled = (lost_a + lost_b) as f64 / (n_a + n_b);
+    let se = (pooled * (1.0 - pooled) * (1.0 / n_a + 1.0 / n_b)).sqrt();
+    if se <= 0.0 {
+        return None;
+    }
+    let z = (lost_a as f64 / n_a - lost_b as f64 / n_b) / se;
+    Some(erfc(z.abs() / std::f64::consts::SQRT_2).min(1.0))
+}
+
+/// Two sided p-value of the Mann-Whitney U test that `a` and `b` come from the same
+/// distribution, sensitive to a shift of the whole distribution including its tail,
+/// normal approximation with tie correction, None if there are too few values to tell
+pub fn mann_whitney_u_test(a: &[f64], b: &[f64]) -> Option<f64> {
+    if a.len() < 2 || b.len() < 2 {
+        return None;
+    }
+    let mut values: Vec<(f64, bool)> = a.iter().map(|&v| (v, true)).chain(b.iter().map(|&v| (v, false))).collect();
+    values.sort_by(|x, y| x.0.partial_cmp(&y.0).unwrap_or(std::cmp::Ordering::Equal));
+
+    // tied values share the mean of their ranks
+    let n = values.len() as f64;
+    let (mut rank_sum_a, mut ties) = (0.0, 0.0);
+    let mut start = 0;
+    while start < values.len() {
+        let end = (start..values.len()).find(|&i| values[i].0 != values[start].0).unwrap_or(values.len());
+        let rank = (start + end + 1) as f64 / 2.0;
+        rank_sum_a += rank * values[start..end].iter().filter(|v| v.1).count() as f64;
+        let t = (end - start) as f64;
+        ties += t * t * t - t;
+        start = end;
+    }
+
+    let (n_a, n_b) = (a.len() as f64, b.len() as f64);
+    let u = rank_sum_a - n_a * (n_a + 1.0) / 2.0;
+    let mean = n_a * n_b / 2.0;
+    let sd = (n_a * n_b / 12.0 * ((n + 1.0) - ties / (n * (n - 1.0)))).sqrt();
+    if sd <= 0.0 {
+        return Some(1.0);
+    }
+    // continuity correction
+    let z = ((u - mean).abs() - 0.5).max(0.0) / sd;
+    Some(erfc(z / std::f64::consts::SQRT_2).min(1.0))
+}
+
+/// Two sided p-value of the exact test that two event counts have the same rate per unit
+/// of exposure, e.g. outages per probe sent, None if there is no exposure or no event
+pub fn poisson_rate_test(count_a: usize, exposure_a: f64, count_b: usize, exposure_b: f64) -> Option<f64> {
+    let total = count_a + count_b;
+    if exposure_a <= 0.0 || exposure_b <= 0.0 || total == 0 {
+        return None;
+    }
+    // given the total, count_a is binomial with the share of a in the exposure
+    let share = exposure_a / (exposure_a + exposure_b);
+    let ln_probability = |k: usize| {
+        ln_gamma(total as f64 + 1.0) - ln_gamma(k as f64 + 1.0) - ln_gamma((total - k) as f64 + 1.0)
+            + k as f64 * share.ln()
+            + (total - k) as f64 * (1.0 - share).ln()
+    };
+    let observed = ln_probability(count_a);
+    // every outcome at most as likely as the observed one, with a little slack for rounding
+    let p: f64 = (0..=total)
+        .map(ln_probability)
+        .filter(|&ln_p| ln_p <= observed + 1e-7)
+        .map(f64::exp)
+        .sum();
+    Some(p.min(1.0))
+}
+
+// complementary error function, Chebyshev fit from Numerical Recipes, relative error below 1.2e-7
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let r = t * (-z * z - 1.26551223
+        + t * (1.00002368
+            + t * (0.37409196
+                + t * (0.09678418
+                    + t * (-0.18628806
+                        + t * (0.27886807 + t * (-1.13520398 + t * (1.48851587 + t * (-0.82215223 + t * 0.17087277)))))))))
+        .exp();
+    if x >= 0.0 { r } else { 2.0 - r }
+}
+
+// ln of the gamma function, Lanczos approximation
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.18009172947146,
+        -86.50532032941677,
+        24.01409824083091,
+        -1.231739572450155,
+        0.1208650973866179e-2,
+        -0.5395239384953e-5,
+    ];
+    let tmp = x + 5.5;
+    let tmp = tmp - (x + 0.5) * tmp.ln();
+    let mut ser = 1.000000000190015;
+    for (i, c) in COEFFICIENTS.iter().enumerate() {
+        ser += c / (x + 1.0 + i as f64);
+    }
+    -tmp + (2.5066282746310005 * ser / x).ln()
+}
+
+// regularized incomplete beta function I_x(a, b)
+fn incomplete_beta(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+    let front = (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp();
+    // the continued fraction converges quickly on this side, use the symmetry on the other
+    if x < (a + 1.0) / (a + b + 2.0) {
+        front * beta_fraction(a, b, x) / a
+    } else {
+        1.0 - front * beta_fraction(b, a, 1.0 - x) / b
+    }
+}
+
+// continued fraction of the incomplete beta function, modified Lentz's method
+fn beta_fraction(a: f64, b: f64, x: f64) -> f64 {
+    const TINY: f64 = 1e-300;
+    let mut c = 1.0;
+    let mut d = 1.0 - (a + b) * x / (a + 1.0);
+    if d.abs() < TINY {
+        d = TINY;
+    }
+    d = 1.0 / d;
+    let mut h = d;
+    for m in 1..300 {
+        let m = m as f64;
+        for aa in [
+            m * (b - m) * x / ((a + 2.0 * m - 1.0) * (a + 2.0 * m)),
+            -(a + m) * (a + b + m) * x / ((a + 2.0 * m) * (a + 2.0 * m + 1.0)),
+        ] {
+            d = 1.0 + aa * d;
+            if d.abs() < TINY {
+                d = TINY;
+            }
+            c = 1.0 + aa / c;
+            if c.abs() < TINY {
+                c = TINY;
+            }
+            d = 1.0 / d;
+            h *= d * c;
+        }
+        if (d * c - 1.0).abs() < 1e-12 {
+            break;
+        }
+    }
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn welch_t_test_p_values() {
+        // same mean, any spread
+        assert!(welch_t_test(&[9.0, 10.0, 11.0], &[5.0, 10.0, 15.0]).unwrap() > 0.99);
+        // t = -4.670 with 7.71 degrees of freedom, checked by integrating the t density
+        let p = welch_t_test(&[19.0, 20.0, 21.0, 20.5, 19.5], &[22.0, 23.0, 21.5, 22.5, 24.0]).unwrap();
+        assert!((p - 0.001769).abs() < 1e-5, "{}", p);
+        assert_eq!(welch_t_test(&[1.0], &[1.0, 2.0]), None);
+        assert_eq!(welch_t_test(&[1.0, 1.0], &[2.0, 2.0]), Some(0.0));
+    }
+
+    #[test]
+    fn two_proportion_z_test_p_values() {
+        // 1% vs 5% of 1000, z = 5.26
+        let p = two_proportion_z_test(10, 1000, 50, 1000).unwrap();
+        assert!(p < 1e-6, "{}", p);
+        assert!((two_proportion_z_test(10, 1000, 12, 1000).unwrap() - 0.668).abs() < 1e-3);
+        assert_eq!(two_proportion_z_test(0, 100, 0, 100), None);
+        assert_eq!(two_proportion_z_test(1, 0, 1, 100), None);
+    }
+
+    #[test]
+    fn histogram_percentiles_match_the_exact_ones() {
+        let rtts: VecDeque<f64> = (1..=1000).map(|i| i as f64 * 0.37).collect();
+        let mut histogram = RttHistogram::default();
+        rtts.iter().for_each(|&rtt| histogram.push(rtt));
+
+        for percentile in [1.0, 50.0, 95.0, 99.0, 100.0] {
+            let exact = calculate_percentile(&rtts, percentile);
+            let approx = histogram.percentile(percentile);
+            assert!((approx - exact).abs() <= exact * 0.01, "p{}: {} vs {}", percentile, approx, exact);
+        }
+        assert_eq!(histogram.percentile(100.0), 370.0);
+    }
+
+    #[test]
+    fn histogram_ignores_timeouts_and_stays_small() {
+        let mut histogram = RttHistogram::default();
+        histogram.push(-1.0);
+        assert_eq!(histogram.percentile(50.0), 0.0);
+        assert_eq!(histogram.mdev(), 0.0);
+
+        for i in 0..200_000 {
+            histogram.push(10.0 + (i % 1000) as f64 * 0.05);
+        }
+        // 10ms to 60ms in 1% steps
+        assert!(histogram.buckets.len() <= 181);
+    }
+
+    #[test]
+    fn histogram_mdev_is_the_standard_deviation() {
+        let mut histogram = RttHistogram::default();
+        [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0].iter().for_each(|&rtt| histogram.push(rtt));
+        assert!((histogram.mdev() - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn mann_whitney_u_test_p_values() {
+        let before: Vec<f64> = (0..200).map(|i| 20.0 + (i % 10) as f64).collect();
+        // the same values in another order
+        let shuffled: Vec<f64> = before.iter().rev().copied().collect();
+        assert!(mann_whitney_u_test(&before, &shuffled).unwrap() > 0.99);
+        // a slow tail in a fifth of the replies
+        let after: Vec<f64> = before.iter().enumerate().map(|(i, v)| if i % 5 == 0 { v + 100.0 } else { *v }).collect();
+        assert!(mann_whitney_u_test(&before, &after).unwrap() < 0.05);
+        // U = 3 with two ties, z = 1.892 after the tie and continuity corrections
+        let p = mann_whitney_u_test(&[1.0, 2.0, 3.0, 4.0, 5.0], &[3.0, 5.0, 6.0, 7.0, 8.0]).unwrap();
+        assert!((p - 0.05855).abs() < 1e-4, "{}", p);
+        assert_eq!(mann_whitney_u_test(&[5.0; 4], &[5.0; 4]), Some(1.0));
+    }
+
+    #[test]
+    fn poisson_rate_test_p_values() {
+        // no outages before, 50 after the same number of probes
+        assert!(poisson_rate_test(0, 1000.0, 50, 1000.0).unwrap() < 1e-10);
+        assert_eq!(poisson_rate_test(5, 1000.0, 5, 1000.0), Some(1.0));
+        // twice the outages in twice the time is the same rate
+        assert!(poisson_rate_test(5, 1000.0, 10, 2000.0).unwrap() > 0.99);
+        assert_eq!(poisson_rate_test(0, 1000.0, 0, 1000.0), None);
+    }
+
+    #[test]
+    fn jitter_and_loss() {
+        let rtts: VecDeque<f64> = [10.0, -1.0, 14.0, 12.0].into_iter().collect();
+        assert_eq!(calculate_jitter(&rtts), 3.0);
+        assert_eq!(calculate_avg_rtt(&rtts), 12.0);
+        assert_eq!(calculate_loss_pkg(1, 3), 25.0);
+        assert_eq!(calculate_loss_pkg(0, 0), 0.0);
+    }
+}
@@ -0,0 +1,261 @@
+use std::io::Write;
+use std::time::Duration;
+use chrono::{DateTime, Local};
+use serde_json::{json, Value};
+use crate::ip_data::IpData;
+use crate::output::OutputFormat;
+use crate::rolling::{RollingStats, WINDOW_MINUTES};
+use crate::stats::{calculate_loss_pkg, RttHistogram};
+use crate::ui::utils::{format_duration, latest_time};
+
+/// Statistics of one target over the whole run
+#[derive(Debug, Clone, Default)]
+pub struct TargetSummary {
+    pub(crate) addr: String,
+    pub(crate) ip: String,
+    pub(crate) sent: usize,
+    pub(crate) received: usize,
+    pub(crate) loss: f64,
+    pub(crate) min: f64,
+    pub(crate) avg: f64,
+    pub(crate) max: f64,
+    // mean deviation as printed by ping, the standard deviation of the rtts
+    pub(crate) mdev: f64,
+    pub(crate) p50: f64,
+    pub(crate) p95: f64,
+    pub(crate) p99: f64,
+    pub(crate) outages: usize,
+    pub(crate) longest_burst: usize,
+    pub(crate) downtime: Duration,
+    pub(crate) rolling: RollingStats,
+}
+
+impl TargetSummary {
+    /// `rtts` covers the whole run, the data only keeps the recent ones
+    pub fn new(data: &IpData, rtts: &RttHistogram) -> Self {
+        let avg = if data.received > 0 { data.rtt_sum / data.received as f64 } else { 0.0 };
+
+        Self {
+            addr: data.addr.clone(),
+            ip: data.ip.clone(),
+            sent: data.received + data.timeout,
+            received: data.received,
+            loss: calculate_loss_pkg(data.timeout, data.received),
+            min: data.min_rtt,
+            avg,
+            max: data.max_rtt,
+            mdev: rtts.mdev(),
+            p50: rtts.percentile(50.0),
+            p95: rtts.percentile(95.0),
+            p99: rtts.percentile(99.0),
+            outages: data.outage.count,
+            longest_burst: data.outage.longest_burst,
+            downtime: data.outage.total_downtime + data.outage.current_duration(latest_time(data)).unwrap_or_default(),
+            rolling: data.rolling.clone(),
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let rolling: serde_json::Map<String, Value> = WINDOW_MINUTES
+            .iter()
+            .zip(self.rolling.windows.iter())
+            .map(|(minutes, w)| (format!("{}m", minutes), json!({ "loss": w.loss, "avg_ms": w.avg, "p95_ms": w.p95 })))
+            .collect();
+
+        json!({
+            "target": self.addr,
+            "ip": self.ip,
+            "sent": self.sent,
+            "received": self.received,
+            "loss": self.loss,
+            "min_ms": self.min,
+            "avg_ms": self.avg,
+            "max_ms": self.max,
+            "mdev_ms": self.mdev,
+            "p50_ms": self.p50,
+            "p95_ms": self.p95,
+            "p99_ms": self.p99,
+            "outages": self.outages,
+            "longest_burst": self.longest_burst,
+            "downtime_s": self.downtime.as_secs_f64(),
+            "rolling": rolling,
+        })
+    }
+}
+
+/// statistics of every target, printed when nping exits
+pub fn print_summary(
+    out: &mut impl Write,
+    summaries: &[TargetSummary],
+    elapsed: Duration,
+    format: OutputFormat,
+) -> std::io::Result<()> {
+    match format {
+        // a trailing summary would break csv parsing, the rows already carry everything
+        OutputFormat::Csv => Ok(()),
+        OutputFormat::Jsonl => {
+            for summary in summaries {
+                let mut record = json!({ "type": "summary", "duration_s": elapsed.as_secs_f64() });
+                if let (Some(record), Value::Object(fields)) = (record.as_object_mut(), summary.to_json()) {
+                    record.extend(fields);
+                }
+                writeln!(out, "{}", record)?;
+            }
+            Ok(())
+        }
+        OutputFormat::Text => print_table(out, summaries, elapsed),
+    }
+}
+
+fn print_table(out: &mut impl Write, summaries: &[TargetSummary], elapsed: Duration) -> std::io::Result<()> {
+    let width = summaries.iter().map(|s| s.addr.len()).max().unwrap_or(0).max(6);
+    let ip_width = summaries.iter().map(|s| s.ip.len()).max().unwrap_or(0).max(2);
+
+    writeln!(out)?;
+    writeln!(out, "--- nping statistics, run time {} ---", format_duration(elapsed))?;
+    writeln!(
+        out,
+        "{:<width$}  {:<ip_width$}  {:>6} {:>6} {:>7}  {:<27}  {:<20}  {:<17}  {:<20}  {:>7} {:>8}",
+        "TARGET", "IP", "SENT", "RECV", "LOSS", "MIN/AVG/MAX/MDEV (ms)", "P50/P95/P99 (ms)",
+        "LOSS 1/5/15m (%)", "AVG 1/5/15m (ms)", "OUTAGES", "DOWNTIME",
+    )?;
+    for s in summaries {
+        let [loss_1, loss_5, loss_15] = s.rolling.loss();
+        let [avg_1, avg_5, avg_15] = s.rolling.avg();
+        writeln!(
+            out,
+            "{:<width$}  {:<ip_width$}  {:>6} {:>6} {:>6.2}%  {:<27}  {:<20}  {:<17}  {:<20}  {:>7} {:>8}",
+            s.addr,
+            s.ip,
+            s.sent,
+            s.received,
+            s.loss,
+            format!("{:.2}/{:.2}/{:.2}/{:.2}", s.min, s.avg, s.max, s.mdev),
+            format!("{:.2}/{:.2}/{:.2}", s.p50, s.p95, s.p99),
+            format!("{:.1}/{:.1}/{:.1}", loss_1, loss_5, loss_15),
+            format!("{:.2}/{:.2}/{:.2}", avg_1, avg_5, avg_15),
+            s.outages,
+            format_duration(s.downtime),
+        )?;
+    }
+    Ok(())
+}
+
+/// write the summary as a JSON document for tickets and scripts
+pub fn write_summary_file(
+    path: &str,
+    summaries: &[TargetSummary],
+    started: DateTime<Local>,
+    elapsed: Duration,
+) -> std::io::Result<()> {
+    let doc = json!({
+        "started": started.to_rfc3339(),
+        "finished": Local::now().to_rfc3339(),
+        "duration_s": elapsed.as_secs_f64(),
+        "targets": summaries.iter().map(TargetSummary::to_json).collect::<Vec<_>>(),
+    });
+    let mut file = std::fs::File::create(path)?;
+    writeln!(file, "{}", serde_json::to_string_pretty(&doc)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rolling::WindowStats;
+
+    fn summary() -> TargetSummary {
+        let mut data = IpData::new("example.com".to_string(), "192.0.2.1".to_string());
+        data.received = 3;
+        data.timeout = 1;
+        data.rtt_sum = 30.0;
+        data.min_rtt = 5.0;
+        data.max_rtt = 15.0;
+        let mut rtts = RttHistogram::default();
+        [5.0, 10.0, 15.0].iter().for_each(|&rtt| rtts.push(rtt));
+        TargetSummary::new(&data, &rtts)
+    }
+
+    #[test]
+    fn statistics_of_the_whole_run() {
+        let s = summary();
+        assert_eq!((s.sent, s.received), (4, 3));
+        assert_eq!(s.loss, 25.0);
+        assert_eq!(s.avg, 10.0);
+        assert!((s.mdev - (50.0f64 / 3.0).sqrt()).abs() < 1e-9);
+        assert!((s.p50 - 10.0).abs() <= 0.1, "{}", s.p50);
+    }
+
+    #[test]
+    fn table_has_a_row_per_target() {
+        let idle = TargetSummary::new(&IpData::new("example.org".to_string(), "192.0.2.2".to_string()), &RttHistogram::default());
+        let mut out = Vec::new();
+        print_summary(&mut out, &[summary(), idle], Duration::from_secs(4), OutputFormat::Text).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[2].starts_with("TARGET"));
+        assert!(lines[3].starts_with("example.com  192.0.2.1"));
+        assert!(lines[3].contains("25.00%  5.00/10.00/15.00/4.08"));
+        assert!(lines[4].starts_with("example.org  192.0.2.2"));
+    }
+
+    #[test]
+    fn jsonl_summary_is_one_record_per_target_and_csv_gets_none() {
+        let mut out = Vec::new();
+        print_summary(&mut out, &[summary()], Duration::from_secs(4), OutputFormat::Jsonl).unwrap();
+        let record: Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(record["type"], "summary");
+        assert_eq!(record["target"], "example.com");
+        assert_eq!(record["duration_s"], 4.0);
+        assert_eq!(record["loss"], 25.0);
+
+        let mut out = Vec::new();
+        print_summary(&mut out, &[summary()], Duration::from_secs(4), OutputFormat::Csv).unwrap();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn summary_file_is_a_json_document() {
+        let path = std::env::temp_dir().join(format!("nping-summary-{}.json", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        write_summary_file(&path, &[summary()], Local::now(), Duration::from_secs(4)).unwrap();
+        let doc: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(doc["duration_s"], 4.0);
+        assert_eq!(doc["targets"][0]["target"], "example.com");
+        assert_eq!(doc["targets"][0]["rolling"]["1m"]["loss"], 0.0);
+    }
+
+    #[test]
+    fn table_shows_rolling_figures() {
+        let summary = TargetSummary {
+            addr: "example.com".into(),
+            ip: "192.0.2.1".into(),
+            sent: 10,
+            received: 9,
+            loss: 10.0,
+            rolling: RollingStats {
+                windows: [
+                    WindowStats { loss: 0.0, avg: 12.5, p95: 14.0 },
+                    WindowStats { loss: 2.5, avg: 13.0, p95: 15.0 },
+                    WindowStats { loss: 10.0, avg: 20.25, p95: 30.0 },
+                ],
+            },
+            ..Default::default()
+        };
+
+        let mut out = Vec::new();
+        print_summary(&mut out, &[summary], Duration::from_secs(60), OutputFormat::Text).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("LOSS 1/5/15m (%)"));
+        assert!(out.contains("0.0/2.5/10.0"));
+        assert!(out.contains("12.50/13.00/20.25"));
+    }
+
+    #[test]
+    fn csv_has_no_trailing_summary() {
+        let mut out = Vec::new();
+        print_summary(&mut out, &[TargetSummary::default()], Duration::from_secs(1), OutputFormat::Csv).unwrap();
+        assert!(out.is_empty());
+    }
+}
@@ -1,16 +1,16 @@
-use ratatui::backend::Backend;
 use ratatui::{symbols, Frame};
 use ratatui::layout::{Constraint, Direction, Layout, Rect};
-use ratatui::prelude::{Color, Line, Span, Style};
+use ratatui::prelude::{Line, Span, Style};
 use ratatui::widgets::{Axis, Block, Chart, Dataset, Paragraph, Wrap};
 use crate::ip_data::IpData;
-use crate::ui::utils::{calculate_avg_rtt, calculate_jitter, draw_errors_section};
+use crate::stats::{calculate_avg_rtt, calculate_jitter};
+use crate::ui::utils::{draw_errors_section, health_color, outage_status, theme};
 
-pub fn draw_graph_view<B: Backend>(
+pub fn draw_graph_view(
     f: &mut Frame,
     ip_data: &[IpData],
-    errs: &[String]) {
-    let size = f.area();
+    errs: &[String],
+    size: Rect) {
     let rows = (ip_data.len() as f64 / 5.0).ceil() as usize;
     let mut chunks = Vec::new();
 
@@ -58,13 +58,7 @@ pub fn draw_graph_view<B: Backend>(
                 0.0
             };
 
-            let loss_pkg_color = if loss_pkg > 50.0 {
-                Color::Red
-            } else if loss_pkg > 0.0 {
-                Color::Yellow
-            } else {
-                Color::Green
-            };
+            let health_color = health_color(data.health);
 
             // render the content of each target
             let render_content = |f: &mut Frame, area: Rect| {
@@ -90,11 +84,15 @@ pub fn draw_graph_view<B: Backend>(
                 // calculate the jitter
                 let jitter = calculate_jitter(&data.rtts);
 
+                let (outage_text, outage_color) = outage_status(data);
+
 
                 // render the target text
                 let target_text = Line::from(vec![
                     Span::styled("Target: ", Style::default()),
-                    Span::styled(&data.addr, Style::default().fg(Color::Green)),
+                    Span::styled(&data.addr, Style::default().fg(theme().ok)),
+                    Span::raw(" "),
+                    Span::styled(format!("[{}]", data.health), Style::default().fg(health_color)),
                 ]);
 
                 let base_metric_text = Line::from(vec![
@@ -107,23 +105,32 @@ pub fn draw_graph_view<B: Backend>(
                         } else {
                             format!("{:?}ms", data.last_attr)
                         },
-                        Style::default().fg(Color::Green)
+                        Style::default().fg(theme().ok)
                     ),
                     Span::raw("  "),
                     Span::styled("Avg Rtt : ", Style::default()),
-                    Span::styled(format!("{:.2} ms", avg_rtt), Style::default().fg(Color::Green)),
+                    Span::styled(format!("{:.2} ms", avg_rtt), Style::default().fg(theme().ok)),
                     Span::raw("  "),
                     Span::styled("Jitter: ", Style::default()),
-                    Span::styled(format!("{:.2} ms", jitter), Style::default().fg(Color::Green)),
+                    Span::styled(format!("{:.2} ms", jitter), Style::default().fg(theme().ok)),
                     Span::raw("  "),
                     Span::styled("Max: ", Style::default()),
-                    Span::styled(format!("{:.2} ms", data.max_rtt), Style::default().fg(Color::Green)),
+                    Span::styled(format!("{:.2} ms", data.max_rtt), Style::default().fg(theme().ok)),
                     Span::raw("  "),
                     Span::styled("Min: ", Style::default()),
-                    Span::styled(format!("{:.2} ms", data.min_rtt), Style::default().fg(Color::Green)),
+                    Span::styled(format!("{:.2} ms", data.min_rtt), Style::default().fg(theme().ok)),
                     Span::raw("  "),
                     Span::styled("Loss: ", Style::default()),
-                    Span::styled(format!("{:.2}%", loss_pkg), Style::default().fg(loss_pkg_color)),
+                    Span::styled(format!("{:.2}%", loss_pkg), Style::default().fg(health_color)),
+                    Span::raw("  "),
+                    Span::styled("Outage: ", Style::default()),
+                    Span::styled(outage_text, Style::default().fg(outage_color)),
+                    Span::raw("  "),
+                    Span::styled("Anomalies: ", Style::default()),
+                    Span::styled(
+                        data.baseline.anomalies.to_string(),
+                        Style::default().fg(if data.baseline.in_period { theme().anomaly } else { theme().ok })
+                    ),
                 ]);
 
 
@@ -141,11 +148,26 @@ pub fn draw_graph_view<B: Backend>(
                     .map(|(i, &y)| (data.pop_count as f64 + i as f64 + 1.0, y))
                     .collect::<Vec<(f64, f64)>>();
 
-                let datasets = vec![Dataset::default()
-                    .marker(symbols::Marker::HalfBlock)
-                    .style(Style::default().fg(Color::Red))
-                    .graph_type(ratatui::widgets::GraphType::Line)
-                    .data(&data_points)];
+                // replies far above the learned baseline
+                let anomaly_points = data_points
+                    .iter()
+                    .zip(data.anomalies.iter())
+                    .filter(|(_, &anomalous)| anomalous)
+                    .map(|(&point, _)| point)
+                    .collect::<Vec<(f64, f64)>>();
+
+                let datasets = vec![
+                    Dataset::default()
+                        .marker(symbols::Marker::HalfBlock)
+                        .style(Style::default().fg(theme().crit))
+                        .graph_type(ratatui::widgets::GraphType::Line)
+                        .data(&data_points),
+                    Dataset::default()
+                        .marker(symbols::Marker::Dot)
+                        .style(Style::default().fg(theme().anomaly))
+                        .graph_type(ratatui::widgets::GraphType::Scatter)
+                        .data(&anomaly_points),
+                ];
 
                 let y_bounds = [0.0, data.max_rtt * 1.2];
 
@@ -171,7 +193,7 @@ pub fn draw_graph_view<B: Backend>(
                             .bounds(y_bounds)
                             .labels(
                                 (0..=5)
-                                    .map(|i| Span::raw(format!("{:.2}ms", i as f64 * (y_bounds[1] / 5.0) as f64)))
+                                    .map(|i| Span::raw(format!("{:.2}ms", i as f64 * (y_bounds[1] / 5.0))))
                                     .collect::<Vec<Span>>(),
                             ),
                     )
@@ -191,9 +213,9 @@ pub fn draw_graph_view<B: Backend>(
                             format!("{}ms", rtt)
                         };
                         let display_color = if rtt == -1.0 {
-                            Color::Red
+                            theme().crit
                         } else {
-                            Color::Green
+                            theme().ok
                         };
                         Line::from(vec![
                             Span::styled(&data.ip, Style::default()),
@@ -216,5 +238,5 @@ pub fn draw_graph_view<B: Backend>(
     }
 
     let errors_chunk = vertical_chunks.last().unwrap();
-    draw_errors_section::<B>(f, errs, *errors_chunk);
+    draw_errors_section(f, errs, *errors_chunk);
 }
\ No newline at end of file
@@ -1,10 +1,12 @@
 mod graph;
-mod utils;
+pub(crate) mod utils;
 mod table;
 mod point;
 mod sparkline;
+mod outage;
 
 pub use graph::draw_graph_view;
 pub use table::draw_table_view;
 pub use point::draw_point_view;
 pub use sparkline::draw_sparkline_view;
+pub use outage::draw_outage_view;
@@ -0,0 +1,127 @@
+use ratatui::Frame;
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::prelude::{Modifier, Style};
+use ratatui::widgets::{Block, Borders, Cell, Row, Table};
+use crate::ip_data::IpData;
+use crate::outage::Outage;
+use crate::ui::utils::{draw_errors_section, format_duration, latest_time, outage_status};
+
+pub fn draw_outage_view(
+    f: &mut Frame,
+    ip_data: &[IpData],
+    errs: &[String],
+    area: Rect,
+) {
+    let header_style = Style::default()
+        .add_modifier(Modifier::BOLD);
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(1),
+            Constraint::Length(ip_data.len() as u16 + 3),
+            Constraint::Min(5),
+            Constraint::Length(6),
+        ].as_ref())
+        .split(area);
+
+    // per target outage statistics
+    let header = Row::new(vec![
+        "Target",
+        "Ip",
+        "Status",
+        "Outages",
+        "Longest Burst",
+        "Downtime",
+        "MTBF",
+    ])
+        .style(header_style)
+        .height(1);
+
+    let rows = ip_data.iter().map(|data| {
+        let (status, status_color) = outage_status(data);
+        let downtime = data.outage.total_downtime + data.outage.current_duration(latest_time(data)).unwrap_or_default();
+
+        Row::new(vec![
+            Cell::from(data.addr.clone()),
+            Cell::from(data.ip.clone()),
+            Cell::from(status).style(Style::default().fg(status_color)),
+            Cell::from(data.outage.count.to_string()),
+            Cell::from(format!("{} pkts", data.outage.longest_burst)),
+            Cell::from(format_duration(downtime)),
+            Cell::from(data.outage.mtbf.map(format_duration).unwrap_or_else(|| "-".to_string())),
+        ]).height(1)
+    });
+
+    let summary = Table::new(
+        rows,
+        [
+            Constraint::Percentage(20),
+            Constraint::Percentage(20),
+            Constraint::Percentage(15),
+            Constraint::Percentage(10),
+            Constraint::Percentage(15),
+            Constraint::Percentage(10),
+            Constraint::Percentage(10),
+        ],
+    )
+        .header(header)
+        .block(Block::default().borders(Borders::ALL).title("🏎  Nping Outages"));
+
+    // past outages of all targets, most recent first
+    let mut history: Vec<(&IpData, &Outage)> = ip_data
+        .iter()
+        .flat_map(|data| data.outage.history.iter().map(move |o| (data, o)))
+        .collect();
+    history.sort_by_key(|(_, o)| std::cmp::Reverse(o.start));
+
+    // only the most recent outages of each target are kept, the counts above include all of them
+    let dropped: usize = ip_data.iter().map(|data| data.outage.dropped).sum();
+    let history_title = if dropped > 0 {
+        format!("Past Outages ({} older not shown)", dropped)
+    } else {
+        "Past Outages".to_string()
+    };
+
+    let history_header = Row::new(vec![
+        "Target",
+        "Ip",
+        "Start",
+        "End",
+        "Duration",
+        "Lost",
+    ])
+        .style(header_style)
+        .height(1);
+
+    let history_rows = history.iter().map(|(data, outage)| {
+        Row::new(vec![
+            data.addr.clone(),
+            data.ip.clone(),
+            outage.start.format("%Y-%m-%d %H:%M:%S").to_string(),
+            outage.end.map(|e| e.format("%Y-%m-%d %H:%M:%S").to_string()).unwrap_or_default(),
+            format_duration(outage.duration(latest_time(data))),
+            format!("{} pkts", outage.lost),
+        ]).height(1)
+    });
+
+    let history_table = Table::new(
+        history_rows,
+        [
+            Constraint::Percentage(20),
+            Constraint::Percentage(20),
+            Constraint::Percentage(20),
+            Constraint::Percentage(20),
+            Constraint::Percentage(10),
+            Constraint::Percentage(10),
+        ],
+    )
+        .header(history_header)
+        .block(Block::default().borders(Borders::ALL).title(history_title));
+
+    f.render_widget(summary, chunks[1]);
+    f.render_widget(history_table, chunks[2]);
+
+    let errors_chunk = chunks.last().unwrap();
+    draw_errors_section(f, errs, *errors_chunk);
+}
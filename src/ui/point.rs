@@ -1,22 +1,12 @@
-use ratatui::backend::Backend;
 use ratatui::Frame;
 use ratatui::layout::{Constraint, Direction, Layout, Rect};
-use ratatui::prelude::{Color, Line, Span, Style};
+use ratatui::prelude::{Line, Span, Style};
 use ratatui::widgets::{Block, Paragraph, Wrap};
 use crate::ip_data::IpData;
-use crate::ui::utils::{calculate_avg_rtt, calculate_jitter, calculate_loss_pkg, draw_errors_section};
-
-pub fn get_loss_color_and_emoji(loss_rate: f64) -> Color {
-    if loss_rate > 50.0 {
-        Color::Red
-    } else if loss_rate > 0.0 {
-        Color::Yellow
-    } else {
-        Color::Green
-    }
-}
+use crate::stats::{calculate_avg_rtt, calculate_jitter, calculate_loss_pkg};
+use crate::ui::utils::{draw_errors_section, health_color, outage_status, theme};
 
-pub fn draw_point_view<B: Backend>(
+pub fn draw_point_view(
     f: &mut Frame,
     ip_data: &[IpData],
     errs: &[String],
@@ -39,13 +29,15 @@ pub fn draw_point_view<B: Backend>(
 
     // draw legend
     let legend = Line::from(vec![
-        Span::styled(" 🏎  Nping Point View ", Style::default().fg(Color::Cyan)),
+        Span::styled(" 🏎  Nping Point View ", Style::default().fg(theme().title)),
         Span::raw("("),
-        Span::styled("•", Style::default().fg(Color::Green)),
+        Span::styled("•", Style::default().fg(theme().ok)),
         Span::raw(" Healthy, "),
-        Span::styled("↑", Style::default().fg(Color::Yellow)),
+        Span::styled("↑", Style::default().fg(theme().warn)),
         Span::raw(" High Latency (over 80% of max), "),
-        Span::styled("✗", Style::default().fg(Color::Red)),
+        Span::styled("!", Style::default().fg(theme().anomaly)),
+        Span::raw(" Above Baseline, "),
+        Span::styled("✗", Style::default().fg(theme().crit)),
         Span::raw(" Timeout)"),
     ]);
 
@@ -65,15 +57,16 @@ pub fn draw_point_view<B: Backend>(
         let avg_rtt = calculate_avg_rtt(&ip.rtts);
         let jitter = calculate_jitter(&ip.rtts);
         let loss_pkg = calculate_loss_pkg(ip.timeout, ip.received);
-        let loss_pkg_color = get_loss_color_and_emoji(loss_pkg);
+        let loss_pkg_color = health_color(ip.health);
+        let (outage_text, outage_color) = outage_status(ip);
 
 
         // Create the info line (row 1) with all metrics from table view
         let info_line = Line::from(vec![
             Span::raw("Target: "),
-            Span::styled(format!("{} ", ip.addr), Style::default().fg(Color::Green)),
+            Span::styled(format!("{} ", ip.addr), Style::default().fg(theme().ok)),
             Span::raw("Ip: "),
-            Span::styled(format!("{} ", ip.ip), Style::default().fg(Color::Green)),
+            Span::styled(format!("{} ", ip.ip), Style::default().fg(theme().ok)),
             Span::raw("Last: "),
             Span::styled(
                 if ip.last_attr == 0.0 {
@@ -83,30 +76,40 @@ pub fn draw_point_view<B: Backend>(
                 } else {
                     format!("{:.2}ms", ip.last_attr)
                 },
-                Style::default().fg(Color::Green)
+                Style::default().fg(theme().ok)
             ),
             Span::raw(" Avg: "),
-            Span::styled(format!("{:.2}ms", avg_rtt), Style::default().fg(Color::Green)),
+            Span::styled(format!("{:.2}ms", avg_rtt), Style::default().fg(theme().ok)),
             Span::raw(" Max: "),
-            Span::styled(format!("{:.2}ms", ip.max_rtt), Style::default().fg(Color::Green)),
+            Span::styled(format!("{:.2}ms", ip.max_rtt), Style::default().fg(theme().ok)),
             Span::raw(" Min: "),
-            Span::styled(format!("{:.2}ms", ip.min_rtt), Style::default().fg(Color::Green)),
+            Span::styled(format!("{:.2}ms", ip.min_rtt), Style::default().fg(theme().ok)),
             Span::raw(" Jitter: "),
-            Span::styled(format!("{:.2}ms", jitter), Style::default().fg(Color::Green)),
+            Span::styled(format!("{:.2}ms", jitter), Style::default().fg(theme().ok)),
             Span::raw(" Loss: "),
-            Span::styled(format!("{:.2}%", loss_pkg), Style::default().fg(loss_pkg_color)), ]);
+            Span::styled(format!("{:.2}%", loss_pkg), Style::default().fg(loss_pkg_color)),
+            Span::raw(" Outage: "),
+            Span::styled(outage_text, Style::default().fg(outage_color)),
+            Span::raw(" Anomalies: "),
+            Span::styled(
+                ip.baseline.anomalies.to_string(),
+                Style::default().fg(if ip.baseline.in_period { theme().anomaly } else { theme().ok })
+            ), ]);
 
         let mut points_spans = Vec::new();
-        for &rtt in &ip.rtts {
+        for (&rtt, &anomalous) in ip.rtts.iter().zip(ip.anomalies.iter()) {
             if rtt < 0.0 {
                 // Timeout/packet loss - red X
-                points_spans.push(Span::styled("✗", Style::default().fg(Color::Red)));
+                points_spans.push(Span::styled("✗", Style::default().fg(theme().crit)));
+            } else if anomalous {
+                // far above the learned baseline - magenta exclamation
+                points_spans.push(Span::styled("!", Style::default().fg(theme().anomaly)));
             } else if rtt > ip.max_rtt * 0.8 {
                 // High latency () - yellow dot
-                points_spans.push(Span::styled("↑", Style::default().fg(Color::Yellow)));
+                points_spans.push(Span::styled("↑", Style::default().fg(theme().warn)));
             } else {
                 // Normal latency - green dot
-                points_spans.push(Span::styled("•", Style::default().fg(Color::Green)));
+                points_spans.push(Span::styled("•", Style::default().fg(theme().ok)));
             }
             points_spans.push(Span::raw(" ")); // Space between points
         }
@@ -135,5 +138,5 @@ pub fn draw_point_view<B: Backend>(
 
     // Draw errors section at the bottom
     let errors_chunk = chunks[1];
-    draw_errors_section::<B>(f, errs, errors_chunk);
+    draw_errors_section(f, errs, errors_chunk);
 }
\ No newline at end of file
@@ -1,13 +1,12 @@
-use ratatui::backend::Backend;
 use ratatui::Frame;
 use ratatui::layout::{Constraint, Direction, Layout, Rect};
 use ratatui::prelude::{Color, Style, Span, Line};
 use ratatui::widgets::{Block, Borders, Paragraph, Sparkline, Wrap};
 use crate::ip_data::IpData;
-use crate::ui::point::get_loss_color_and_emoji;
-use crate::ui::utils::{calculate_avg_rtt, calculate_jitter, calculate_loss_pkg, draw_errors_section};
+use crate::stats::{calculate_avg_rtt, calculate_jitter, calculate_loss_pkg};
+use crate::ui::utils::{draw_errors_section, health_color, outage_status, theme};
 
-pub fn draw_sparkline_view<B: Backend>(
+pub fn draw_sparkline_view(
     f: &mut Frame,
     ip_data: &[IpData],
     errs: &[String],
@@ -21,14 +20,14 @@ pub fn draw_sparkline_view<B: Backend>(
         .constraints(
             std::iter::once(Constraint::Length(0))
                 .chain(std::iter::once(Constraint::Length(2)))
-                .chain(std::iter::repeat(Constraint::Length(5)).take(n))
+                .chain(std::iter::repeat_n(Constraint::Length(5), n))
                 .chain([Constraint::Min(6)])
                 .collect::<Vec<_>>()
         )
         .split(area);
 
     let legend = Line::from(vec![
-        Span::styled(" 🏎  Nping SparkLine View ", Style::default().fg(Color::Cyan)),
+        Span::styled(" 🏎  Nping SparkLine View ", Style::default().fg(theme().title)),
         Span::raw("("),
         Span::raw(" Blank area means timeout or error"),
         Span::raw(")"),
@@ -41,13 +40,14 @@ pub fn draw_sparkline_view<B: Backend>(
         let avg_rtt = calculate_avg_rtt(&ip.rtts);
         let jitter = calculate_jitter(&ip.rtts);
         let loss_pkg = calculate_loss_pkg(ip.timeout, ip.received);
-        let loss_pkg_color = get_loss_color_and_emoji(loss_pkg);
+        let loss_pkg_color = health_color(ip.health);
+        let (outage_text, outage_color) = outage_status(ip);
 
         let info_line = Line::from(vec![
             Span::raw("Target: "),
-            Span::styled(format!("{} ", ip.addr), Style::default().fg(Color::Green)),
+            Span::styled(format!("{} ", ip.addr), Style::default().fg(theme().ok)),
             Span::raw("Ip: "),
-            Span::styled(format!("{} ", ip.ip), Style::default().fg(Color::Green)),
+            Span::styled(format!("{} ", ip.ip), Style::default().fg(theme().ok)),
             Span::raw("Last: "),
             Span::styled(
                 if ip.last_attr == 0.0 {
@@ -57,18 +57,20 @@ pub fn draw_sparkline_view<B: Backend>(
                 } else {
                     format!("{:.2}ms", ip.last_attr)
                 },
-                Style::default().fg(Color::Green)
+                Style::default().fg(theme().ok)
             ),
             Span::raw(" Avg: "),
-            Span::styled(format!("{:.2}ms", avg_rtt), Style::default().fg(Color::Green)),
+            Span::styled(format!("{:.2}ms", avg_rtt), Style::default().fg(theme().ok)),
             Span::raw(" Max: "),
-            Span::styled(format!("{:.2}ms", ip.max_rtt), Style::default().fg(Color::Green)),
+            Span::styled(format!("{:.2}ms", ip.max_rtt), Style::default().fg(theme().ok)),
             Span::raw(" Min: "),
-            Span::styled(format!("{:.2}ms", ip.min_rtt), Style::default().fg(Color::Green)),
+            Span::styled(format!("{:.2}ms", ip.min_rtt), Style::default().fg(theme().ok)),
             Span::raw(" Jitter: "),
-            Span::styled(format!("{:.2}ms", jitter), Style::default().fg(Color::Green)),
+            Span::styled(format!("{:.2}ms", jitter), Style::default().fg(theme().ok)),
             Span::raw(" Loss: "),
             Span::styled(format!("{:.2}%", loss_pkg), Style::default().fg(loss_pkg_color)),
+            Span::raw(" Outage: "),
+            Span::styled(outage_text, Style::default().fg(outage_color)),
         ]);
 
         let info_para = Paragraph::new(info_line).wrap(Wrap { trim: true });
@@ -97,5 +99,5 @@ pub fn draw_sparkline_view<B: Backend>(
     }
 
     let errors_chunk = chunks.last().unwrap();
-    draw_errors_section::<B>(f, errs, *errors_chunk);
+    draw_errors_section(f, errs, *errors_chunk);
 }
@@ -1,13 +1,14 @@
-use ratatui::backend::Backend;
 use ratatui::Frame;
 use ratatui::layout::{Constraint, Direction, Layout, Rect};
 use ratatui::prelude::{Color, Modifier, Style};
 use ratatui::widgets::{Block, Paragraph, Row, Table};
 use crate::ip_data::IpData;
-use crate::ui::utils::{calculate_avg_rtt, calculate_jitter, calculate_loss_pkg, draw_errors_section};
+use crate::health::HealthState;
+use crate::stats::{calculate_avg_rtt, calculate_jitter, calculate_loss_pkg};
+use crate::ui::utils::{draw_errors_section, format_triple, outage_status, theme};
 
 
-pub fn draw_table_view<B: Backend>(
+pub fn draw_table_view(
     f: &mut Frame,
     ip_data: &[IpData],
     errs: &[String],
@@ -49,6 +50,12 @@ pub fn draw_table_view<B: Backend>(
         "Min",
         "Jitter",
         "Loss",
+        "Outage",
+        "Anomalies",
+        "State",
+        "Loss 1/5/15m",
+        "Avg 1/5/15m",
+        "P95 1/5/15m",
     ])
         .style(header_style)
         .height(1);
@@ -59,6 +66,7 @@ pub fn draw_table_view<B: Backend>(
         let avg_rtt = calculate_avg_rtt(&data.rtts);
         let jitter = calculate_jitter(&data.rtts);
         let loss_pkg = calculate_loss_pkg(data.timeout, data.received);
+        let (outage_text, _) = outage_status(data);
 
         let rank = match index {
             0 => "🥇".to_string(),
@@ -84,15 +92,19 @@ pub fn draw_table_view<B: Backend>(
             format!("{:.2}ms", data.min_rtt),
             format!("{:.2}ms", jitter),
             format!("{:.2}%", loss_pkg),
+            outage_text,
+            format!("{} ({} periods)", data.baseline.anomalies, data.baseline.periods),
+            data.health.to_string(),
+            format!("{}%", format_triple(data.rolling.loss())),
+            format!("{}ms", format_triple(data.rolling.avg())),
+            format!("{}ms", format_triple(data.rolling.p95())),
         ]).height(1);
 
         // highlight the row with different colors
-        if loss_pkg > 50.0 {
-            row.style(Style::default().bg(Color::Red).fg(Color::White)) // 淡红色
-        } else if loss_pkg > 0.0 {
-            row.style(Style::default().bg(Color::Yellow).fg(Color::White)) // 淡黄色
-        } else {
-            row
+        match data.health {
+            HealthState::Crit => row.style(Style::default().bg(theme().crit).fg(Color::White)), // 淡红色
+            HealthState::Warn => row.style(Style::default().bg(theme().warn).fg(Color::White)), // 淡黄色
+            HealthState::Ok => row,
         }
     });
 
@@ -101,11 +113,17 @@ pub fn draw_table_view<B: Backend>(
         rows,
         [
             Constraint::Percentage(3),
-            Constraint::Percentage(15),
-            Constraint::Percentage(15),
-            Constraint::Percentage(10),
-            Constraint::Percentage(10),
-            Constraint::Percentage(10),
+            Constraint::Percentage(8),
+            Constraint::Percentage(8),
+            Constraint::Percentage(5),
+            Constraint::Percentage(5),
+            Constraint::Percentage(5),
+            Constraint::Percentage(5),
+            Constraint::Percentage(5),
+            Constraint::Percentage(5),
+            Constraint::Percentage(8),
+            Constraint::Percentage(8),
+            Constraint::Percentage(4),
             Constraint::Percentage(10),
             Constraint::Percentage(10),
             Constraint::Percentage(10),
@@ -132,5 +150,5 @@ pub fn draw_table_view<B: Backend>(
     f.render_widget(table, chunks[1]);
 
     let errors_chunk = chunks.last().unwrap();
-    draw_errors_section::<B>(f, errs, *errors_chunk);
+    draw_errors_section(f, errs, *errors_chunk);
 }
\ No newline at end of file
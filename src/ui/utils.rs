@@ -1,43 +1,110 @@
-use std::collections::VecDeque;
-use ratatui::backend::Backend;
+use std::sync::OnceLock;
+use std::time::Duration;
+use chrono::{DateTime, Local};
 use ratatui::Frame;
 use ratatui::layout::Rect;
 use ratatui::prelude::{Color, Line, Span, Style};
 use ratatui::widgets::{Block, Borders, Paragraph, Wrap};
+use crate::ip_data::IpData;
+use crate::health::HealthState;
 
-pub fn calculate_avg_rtt(rtt: &VecDeque<f64>) -> f64 {
-    if !rtt.is_empty() {
-        let valid_rtt: Vec<f64> = rtt.iter().cloned().filter(|&rtt| rtt >= 0.0).collect();
-        if !valid_rtt.is_empty() {
-            let sum: f64 = valid_rtt.iter().sum();
-            sum / valid_rtt.len() as f64
-        } else {
-            0.0
+/// Colours of the ui, changed with --color
+#[derive(Debug, Clone, Copy)]
+pub struct Theme {
+    // healthy values and replies
+    pub(crate) ok: Color,
+    pub(crate) warn: Color,
+    // timeouts, outages and critical health
+    pub(crate) crit: Color,
+    pub(crate) anomaly: Color,
+    pub(crate) title: Color,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self { ok: Color::Green, warn: Color::Yellow, crit: Color::Red, anomaly: Color::Magenta, title: Color::Cyan }
+    }
+}
+
+static THEME: OnceLock<Theme> = OnceLock::new();
+
+pub fn theme() -> &'static Theme {
+    THEME.get_or_init(Theme::default)
+}
+
+// set once at startup, before anything is drawn
+pub fn set_theme(colors: &[(String, Color)]) {
+    let mut theme = Theme::default();
+    for (name, color) in colors {
+        match name.as_str() {
+            "ok" => theme.ok = *color,
+            "warn" => theme.warn = *color,
+            "crit" => theme.crit = *color,
+            "anomaly" => theme.anomaly = *color,
+            "title" => theme.title = *color,
+            _ => {}
         }
-    } else {
-        0.0
+    }
+    THEME.set(theme).ok();
+}
+
+/// parse a --color NAME=COLOR value
+pub fn parse_color(s: &str) -> Result<(String, Color), String> {
+    let (name, color) = s.split_once('=').ok_or("expected NAME=COLOR, e.g. crit=light-red")?;
+    if !["ok", "warn", "crit", "anomaly", "title"].contains(&name) {
+        return Err(format!("unknown colour {}, expected ok/warn/crit/anomaly/title", name));
+    }
+    let color = color.parse::<Color>().map_err(|_| format!("invalid colour {}, use a name like light-red, an index or #rrggbb", color))?;
+    Ok((name.to_string(), color))
+}
+
+pub fn health_color(state: HealthState) -> Color {
+    match state {
+        HealthState::Ok => theme().ok,
+        HealthState::Warn => theme().warn,
+        HealthState::Crit => theme().crit,
     }
 }
 
-pub fn calculate_jitter(rtt: &VecDeque<f64>) -> f64 {
-    if rtt.len() > 1 {
-        let diffs: Vec<f64> = rtt.iter().zip(rtt.iter().skip(1)).map(|(y1, y2)| (y2 - y1).abs()).collect();
-        let sum: f64 = diffs.iter().sum();
-        sum / diffs.len() as f64
+// 1m/5m/15m values in a compact form like "0.5/1.2/0.8"
+pub fn format_triple(values: [f64; 3]) -> String {
+    values
+        .iter()
+        .map(|v| format!("{:.1}", v))
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}h{:02}m", secs / 3600, (secs % 3600) / 60)
+    } else if secs >= 60 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
     } else {
-        0.0
+        format!("{:.1}s", duration.as_secs_f64())
     }
 }
 
-pub fn calculate_loss_pkg(timeout: usize, received: usize) -> f64 {
-    if timeout > 0 {
-        (timeout as f64 / (received as f64 + timeout as f64)) * 100.0
+// live outage status: the duration of the current outage, or the number of past outages
+pub fn outage_status(data: &IpData) -> (String, Color) {
+    let outage = &data.outage;
+    let now = latest_time(data);
+    if let Some(duration) = outage.current_duration(now) {
+        (format!("DOWN {}", format_duration(duration)), theme().crit)
+    } else if let Some(last) = outage.history.back() {
+        (format!("{} (last {})", outage.count, format_duration(last.duration(now))), theme().warn)
     } else {
-        0.0
+        ("0".to_string(), theme().ok)
     }
 }
 
-pub fn draw_errors_section<B: Backend>(
+// ongoing durations are measured up to the latest result, which also holds when replaying a recording
+pub fn latest_time(data: &IpData) -> DateTime<Local> {
+    data.last_timestamp.unwrap_or_else(Local::now)
+}
+
+pub fn draw_errors_section(
     f: &mut Frame,
     errs: &[String],
     area: Rect,
@@ -48,8 +115,8 @@ pub fn draw_errors_section<B: Backend>(
         .take(5)
         .map(|err| {
             Line::from(vec![
-                Span::styled("⚠ ", Style::default().fg(Color::Yellow)),
-                Span::styled(err, Style::default().fg(Color::Red))
+                Span::styled("⚠ ", Style::default().fg(theme().warn)),
+                Span::styled(err, Style::default().fg(theme().crit))
             ])
         })
         .collect();